@@ -1,50 +1,230 @@
-use crate::twig_interp::{Env, Types, interp_program};
-use crate::twig_parser::parse_program;
+use crate::twig_descent::{parse_statement, parse_statements};
+use crate::twig_diagnostics::Diagnostic;
+use crate::twig_interp::{Env, InterpErrors, Types, interp_program};
+use crate::twig_parser::{Expression, ParseError, Span, SyntaxError, parse_file, parse_program};
+use crate::twig_resolver::resolve_program;
+use crate::twig_types::Checker;
+use crate::twig_vm::run_program;
 use pest::Parser;
+use pest::error::InputLocation;
 use pest_derive::Parser;
 use std::io::{self, BufRead, Write};
+use std::path::Path;
 
+pub mod twig_builtins;
+pub mod twig_bytecode;
+pub mod twig_descent;
+pub mod twig_diagnostics;
 pub mod twig_interp;
+pub mod twig_lexer;
+pub mod twig_modules;
 pub mod twig_parser;
+pub mod twig_resolver;
+pub mod twig_types;
+pub mod twig_vm;
 
 #[derive(Parser)]
 #[grammar = "grammars/grammar.pest"]
 pub struct GrammarParser;
 
-fn run_input(program_input: &str, env: &mut Env) {
+/// Parses a line of the REPL with the hand-written parser, or with the pest grammar when `pest`
+/// is set.
+fn parse_input(program_input: &str, pest: bool) -> Result<Expression, Box<Diagnostic>> {
+    if !pest {
+        return parse_statement(program_input).map_err(|e| Box::new(Diagnostic::from(&e)));
+    }
     match GrammarParser::parse(Rule::program, program_input) {
-        Ok(mut pairs) => {
-            let program = parse_program(pairs.next().unwrap().into_inner());
-            let result = interp_program(program, env);
+        Ok(mut pairs) => match pairs.next() {
+            Some(pair) => {
+                parse_program(pair.into_inner()).map_err(|e| Box::new(Diagnostic::from(&e)))
+            }
+            None => Err(Box::new(Diagnostic::from(&ParseError::ended_early(
+                Span::START,
+            )))),
+        },
+        Err(e) => Err(Box::new(Diagnostic::from(&SyntaxError::new(
+            program_input,
+            e,
+        )))),
+    }
+}
+
+fn run_input(program_input: &str, env: &mut Env, checker: &mut Checker, pest: bool, vm: bool) {
+    match parse_input(program_input, pest) {
+        Ok(mut program) => {
+            // Only keep what the checker learnt from the line if it goes on to run.
+            let mut checked = checker.clone();
+            if let Err(errors) = checked.check(&program) {
+                for e in errors {
+                    println!("{}", Diagnostic::from(&e).render(program_input));
+                }
+                return;
+            }
+            if let Err(errors) = resolve_program(std::slice::from_mut(&mut program), env) {
+                for e in errors {
+                    println!("{}", Diagnostic::from(&e).render(program_input));
+                }
+                return;
+            }
+            *checker = checked;
+            let result = match vm {
+                true => run_program(&program, env),
+                false => interp_program(&program, env),
+            };
+            let result = match result {
+                // A `return` or `?` outside of a function ends the line with its value.
+                Err(InterpErrors::Return(value)) => Ok(value),
+                result => result,
+            };
             match result {
                 Ok(Types::Integer(i)) => println!("Integer Result: {}", i),
                 Ok(Types::Boolean(b)) => println!("Boolean Result: {}", b),
                 Ok(Types::String(s)) => println!("String Result: {}", s),
+                Ok(Types::Nil) => println!("Nil Result"),
+                Ok(list @ Types::List(_)) => println!("List Result: {}", list),
+                Ok(record @ Types::Record { .. }) => println!("Record Result: {}", record),
+                Ok(structure @ Types::StructType { .. }) => {
+                    println!("Struct Result: {}", structure)
+                }
+                Ok(value @ Types::EnumValue { .. }) => println!("Enum Result: {}", value),
+                Ok(enumeration @ Types::EnumType { .. }) => {
+                    println!("Enum Type Result: {}", enumeration)
+                }
+                Ok(constructor @ Types::VariantConstructor { .. }) => {
+                    println!("Constructor Result: {}", constructor)
+                }
+                Ok(function @ Types::Function(_)) => println!("Function Result: {}", function),
+                Ok(builtin @ Types::Builtin(_)) => println!("Builtin Result: {}", builtin),
+                Ok(module @ Types::Module(_)) => println!("Module Result: {}", module),
                 Ok(Types::Unit) => println!("Unit Result"),
-                Err(e) => println!("{}", e),
+                Err(e) => {
+                    // Functions from earlier lines were parsed from a different input, so an
+                    // error inside one is shown where this line first called into it.
+                    let diagnostic = match e.trace() {
+                        Some(frames) => Diagnostic::from(&e).with_span(frames[0].span),
+                        None => Diagnostic::from(&e),
+                    };
+                    println!("{}", diagnostic.render(program_input));
+                }
             }
         }
-        Err(e) => {
-            println!("Program Parse Error: {}", e);
+        Err(diagnostic) => {
+            let diagnostic = match is_incomplete(program_input, pest) {
+                true => diagnostic.with_note("the input ended before the statement was complete"),
+                false => *diagnostic,
+            };
+            println!("{}", diagnostic.render(program_input));
         }
     }
 }
 
-fn write_indicator() {
-    io::stdout().write_all(b"> ").unwrap();
+// Input that only fails to parse at its very end is still being typed, like an open bracket or a
+// trailing operator, so the REPL keeps reading lines into it.
+fn is_incomplete(program_input: &str, pest: bool) -> bool {
+    if !pest {
+        return parse_statement(program_input).is_err_and(|e| {
+            // Strings and comments that are still open run over several lines too.
+            let rest = &program_input[e.span.start..];
+            rest.is_empty()
+                || rest.starts_with("/*")
+                || rest.starts_with('"') && rest.matches('"').count() == 1
+        });
+    }
+    match GrammarParser::parse(Rule::program, program_input) {
+        Err(e) => match e.location {
+            InputLocation::Pos(pos) => pos == program_input.len(),
+            InputLocation::Span((_, end)) => end == program_input.len(),
+        },
+        Ok(_) => false,
+    }
+}
+
+fn syntax_errors(source: &str, pest: bool) -> Vec<Diagnostic> {
+    match pest {
+        true => parse_file(source)
+            .err()
+            .unwrap_or_default()
+            .iter()
+            .map(Diagnostic::from)
+            .collect(),
+        false => parse_statements(source)
+            .err()
+            .unwrap_or_default()
+            .iter()
+            .map(Diagnostic::from)
+            .collect(),
+    }
+}
+
+fn write_indicator(continuing: bool) {
+    let indicator: &[u8] = if continuing { b". " } else { b"> " };
+    io::stdout().write_all(indicator).unwrap();
     io::stdout().flush().unwrap();
 }
 
 fn main() {
+    // `--strict` infers types for unannotated code and refuses to run anything that doesn't check.
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with("--"));
+    let strict = flags.iter().any(|flag| flag == "--strict");
+    // `--pest` parses with the original pest grammar rather than the hand-written parser.
+    let pest = flags.iter().any(|flag| flag == "--pest");
+    // `--vm` compiles each statement to bytecode and runs it on the VM instead of walking the tree.
+    let vm = flags.iter().any(|flag| flag == "--vm");
+
+    if let Some(path) = args.first() {
+        if let Err(e) = twig_modules::run_file(Path::new(path), strict, pest, vm) {
+            // Errors from imported modules have no span, so only the file's own source is needed.
+            let source = std::fs::read_to_string(path).unwrap_or_default();
+            // Syntax errors come back as text, so the file is parsed again to show each of them.
+            let diagnostics = match e {
+                InterpErrors::ModuleParseError(_) => syntax_errors(&source, pest),
+                _ => Vec::new(),
+            };
+            match diagnostics.is_empty() {
+                true => println!("{}", Diagnostic::from(&e).render(&source)),
+                false => diagnostics
+                    .iter()
+                    .for_each(|diagnostic| println!("{}", diagnostic.render(&source))),
+            }
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let stdin = io::stdin();
     let mut env = Env::new();
-    write_indicator();
+    env.loader().borrow_mut().set_strict(strict);
+    env.loader().borrow_mut().set_pest(pest);
+    env.loader().borrow_mut().set_vm(vm);
+    let mut checker = match strict {
+        true => Checker::strict(),
+        false => Checker::new(),
+    };
+    let mut input = String::new();
+    write_indicator(false);
     for line in stdin.lock().lines() {
         let l = line.unwrap();
-        if l == "exit" {
-            std::process::exit(1);
+        if input.is_empty() {
+            if l == "exit" {
+                std::process::exit(1);
+            }
+            if l.trim().is_empty() {
+                write_indicator(false);
+                continue;
+            }
+        } else {
+            input.push('\n');
+        }
+        input.push_str(&l);
+        // An empty line gives up on an unfinished input and reports why it doesn't parse.
+        if !l.trim().is_empty() && is_incomplete(&input, pest) {
+            write_indicator(true);
+            continue;
         }
-        run_input(l.as_str(), &mut env);
-        write_indicator();
+        run_input(input.as_str(), &mut env, &mut checker, pest, vm);
+        input.clear();
+        write_indicator(false);
     }
 }
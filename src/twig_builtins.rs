@@ -0,0 +1,226 @@
+use crate::twig_interp::{InterpErrors, Types};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    TypeOf,
+    Print,
+    Ok,
+    Err,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 4] = [Builtin::TypeOf, Builtin::Print, Builtin::Ok, Builtin::Err];
+
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "type_of" => Some(Builtin::TypeOf),
+            "print" => Some(Builtin::Print),
+            "Ok" => Some(Builtin::Ok),
+            "Err" => Some(Builtin::Err),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::TypeOf => "type_of",
+            Builtin::Print => "print",
+            Builtin::Ok => "Ok",
+            Builtin::Err => "Err",
+        }
+    }
+}
+
+pub fn call_builtin(builtin: Builtin, args: Vec<Types>) -> Result<Types, InterpErrors> {
+    match (builtin, args.as_slice()) {
+        (Builtin::TypeOf, [value]) => Ok(Types::String(value.type_name())),
+        (Builtin::Print, [value]) => {
+            match value {
+                Types::String(s) => println!("{}", s),
+                value => println!("{}", value),
+            }
+            Ok(Types::Unit)
+        }
+        (Builtin::Ok | Builtin::Err, [value]) => Ok(Types::EnumValue {
+            enum_name: String::from("Result"),
+            variant: String::from(builtin.name()),
+            payload: vec![value.clone()],
+        }),
+        _ => Err(InterpErrors::WrongArgumentCount {
+            expected: 1,
+            found: args.len(),
+        }),
+    }
+}
+
+/// Methods every string and list has without an `impl` block. Lists are values, so `push` returns
+/// a new list rather than changing the one it was called on.
+pub fn call_builtin_method(
+    receiver: Types,
+    method: &str,
+    args: Vec<Types>,
+) -> Result<Types, InterpErrors> {
+    match (receiver, method, args.as_slice()) {
+        (Types::String(s), "len", []) => Ok(Types::Integer(s.chars().count() as i32)),
+        (Types::String(s), "upper", []) => Ok(Types::String(s.to_uppercase())),
+        (Types::String(s), "lower", []) => Ok(Types::String(s.to_lowercase())),
+        (Types::String(s), "contains", [Types::String(part)]) => {
+            Ok(Types::Boolean(s.contains(part.as_str())))
+        }
+        (Types::List(items), "len", []) => Ok(Types::Integer(items.len() as i32)),
+        (Types::List(items), "contains", [value]) => Ok(Types::Boolean(items.contains(value))),
+        (Types::List(items), "get", [Types::Integer(i)]) => Ok(usize::try_from(*i)
+            .ok()
+            .and_then(|i| items.get(i).cloned())
+            .unwrap_or(Types::Nil)),
+        (Types::List(mut items), "push", [value]) => {
+            items.push(value.clone());
+            Ok(Types::List(items))
+        }
+        (Types::String(_), "contains", [part]) => Err(InterpErrors::InvalidTypeError(format!(
+            "`contains` on a string needs a string, found {}",
+            part.type_name()
+        ))),
+        (Types::List(_), "get", [index]) => Err(InterpErrors::InvalidTypeError(format!(
+            "list indexes must be integers, found {}",
+            index.type_name()
+        ))),
+        (Types::String(_), "len" | "upper" | "lower", _) | (Types::List(_), "len", _) => {
+            Err(InterpErrors::WrongArgumentCount {
+                expected: 0,
+                found: args.len(),
+            })
+        }
+        (Types::String(_), "contains", _) | (Types::List(_), "contains" | "get" | "push", _) => {
+            Err(InterpErrors::WrongArgumentCount {
+                expected: 1,
+                found: args.len(),
+            })
+        }
+        (receiver, method, _) => Err(InterpErrors::MethodDoesNotExist(
+            receiver.type_name(),
+            String::from(method),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod twig_builtins_tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Builtin::from_name("type_of"), Some(Builtin::TypeOf));
+        assert_eq!(Builtin::from_name("typeof"), None);
+    }
+
+    #[test]
+    fn test_type_of_record() {
+        let record = Types::Record {
+            name: String::from("Point"),
+            fields: vec![(String::from("x"), Types::Integer(1))],
+        };
+
+        let result = call_builtin(Builtin::TypeOf, vec![record]).unwrap();
+
+        assert_eq!(result, Types::String(String::from("Point")));
+    }
+
+    #[test]
+    fn test_type_of_int() {
+        let result = call_builtin(Builtin::TypeOf, vec![Types::Integer(1)]).unwrap();
+
+        assert_eq!(result, Types::String(String::from("int")));
+    }
+
+    #[test]
+    fn test_type_of_wrong_argument_count() {
+        let result = call_builtin(Builtin::TypeOf, vec![]).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::WrongArgumentCount {
+                expected: 1,
+                found: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_string_len() {
+        let receiver = Types::String(String::from("abc"));
+
+        let result = call_builtin_method(receiver, "len", vec![]).unwrap();
+
+        assert_eq!(result, Types::Integer(3));
+    }
+
+    #[test]
+    fn test_string_upper() {
+        let receiver = Types::String(String::from("abc"));
+
+        let result = call_builtin_method(receiver, "upper", vec![]).unwrap();
+
+        assert_eq!(result, Types::String(String::from("ABC")));
+    }
+
+    #[test]
+    fn test_list_push() {
+        let receiver = Types::List(vec![Types::Integer(1)]);
+
+        let result = call_builtin_method(receiver, "push", vec![Types::Integer(2)]).unwrap();
+
+        assert_eq!(
+            result,
+            Types::List(vec![Types::Integer(1), Types::Integer(2)])
+        );
+    }
+
+    #[test]
+    fn test_list_get_out_of_bounds() {
+        let receiver = Types::List(vec![]);
+
+        let result = call_builtin_method(receiver, "get", vec![Types::Integer(0)]).unwrap();
+
+        assert_eq!(result, Types::Nil);
+    }
+
+    #[test]
+    fn test_method_wrong_argument_count() {
+        let receiver = Types::List(vec![]);
+
+        let result = call_builtin_method(receiver, "push", vec![]).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::WrongArgumentCount {
+                expected: 1,
+                found: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_method_does_not_exist() {
+        let receiver = Types::Integer(1);
+
+        let result = call_builtin_method(receiver, "len", vec![]).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::MethodDoesNotExist(String::from("int"), String::from("len"))
+        );
+    }
+
+    #[test]
+    fn test_err_constructor() {
+        let result = call_builtin(Builtin::Err, vec![Types::Integer(1)]).unwrap();
+        let expected = Types::EnumValue {
+            enum_name: String::from("Result"),
+            variant: String::from("Err"),
+            payload: vec![Types::Integer(1)],
+        };
+
+        assert_eq!(result, expected);
+    }
+}
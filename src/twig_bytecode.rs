@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::twig_interp::{Function, InterpErrors, Types, exported_name, impl_function, operand};
+use crate::twig_parser::{
+    Accessor, BooleanOperator, ExprKind, Expression, MathOperator, Pattern, PatternKind, Span,
+};
+
+/// One instruction of the stack machine in `twig_vm`. Anything bigger than a number, like a name
+/// or a string, is an index into one of the tables of the chunk the instruction is in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Pushes a copy of a constant.
+    Constant(usize),
+    Integer(i32),
+    Boolean(bool),
+    Nil,
+    Unit,
+    /// Pops this many values into a list.
+    List(usize),
+    Pop,
+    /// Pushes a copy of the top of the stack.
+    Copy,
+    /// Pops the value under the top of the stack.
+    Nip,
+    /// Pushes the value of a variable or builtin.
+    Get(usize),
+    /// Pushes the value of a local variable by its depth and slot. The name is for errors.
+    Local(usize, usize, usize),
+    /// Pops a value into a new variable, which can't be declared again when the flag is set.
+    Declare(usize, bool),
+    PushScope,
+    PopScope,
+    Math(MathOperator),
+    Compare(BooleanOperator),
+    Jump(usize),
+    /// Jumps if the top of the stack is `nil`, leaving it there.
+    JumpIfNil(usize),
+    /// Jumps if the top of the stack isn't `nil`, leaving it there, and pops it otherwise.
+    JumpUnlessNil(usize),
+    /// Pops a value and pushes one of its fields. With the flag set a missing field is `nil`.
+    Field(usize, bool),
+    /// Fails unless the top of the stack is a list, before its index is evaluated.
+    Indexable,
+    /// Pops an index and a list and pushes the item. With the flag set a missing item is `nil`.
+    Index(bool),
+    /// Pops this many arguments and then the value to call with them.
+    Call(usize),
+    /// Pops this many arguments and then the receiver to call a method on.
+    Invoke(usize, usize),
+    /// Pushes the struct type a literal is built from.
+    StructType(usize),
+    /// Fails unless the struct type this far down the stack has the field.
+    CheckField(usize, usize),
+    /// Pops the fields of a literal and the struct type under them, and pushes the record.
+    Record(usize),
+    /// Pushes `Type::name` from the names of the type and the variant.
+    Variant(usize, usize),
+    /// Pushes a function from a declaration.
+    Function(usize),
+    /// Adds the methods of an `impl` block to its type.
+    Impl(usize),
+    /// Pushes the module imported from a path.
+    Import(usize),
+    Export(usize),
+    Throw,
+    Return,
+    Propagate,
+    /// Starts the body of a `try`, whose errors go to the handler until `PopHandler`.
+    PushHandler(usize),
+    PopHandler,
+    /// Raises the error a `finally` block ran for again once it's done.
+    Rethrow,
+    /// Checks the arms of a match against the value on top of the stack.
+    CheckExhaustive(usize),
+    /// Pops a value and pushes its payload, with the first field on top, if it's the variant a
+    /// pattern names. Otherwise the pattern fails.
+    MatchVariant(usize, usize),
+    /// Pops a literal and a value, and fails the pattern unless they're equal.
+    MatchLiteral(usize),
+    /// Pops a value into a variable a pattern binds.
+    Bind(usize),
+    /// Starts a scope holding the variables the last few patterns bound.
+    Bindings(usize),
+    /// Pops the value no arm of a match matched.
+    NoMatchingArm,
+    /// Ends the chunk with the value on top of the stack.
+    End,
+}
+
+/// The code of a program or a function body, along with the tables its instructions point into.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub(crate) code: Vec<Op>,
+    /// The region each instruction was compiled from.
+    pub(crate) regions: Vec<usize>,
+    pub(crate) tree: Vec<Region>,
+    pub(crate) constants: Vec<Types>,
+    pub(crate) names: Vec<String>,
+    pub(crate) functions: Vec<Rc<Function>>,
+    pub(crate) impls: Vec<(String, Vec<Rc<Function>>)>,
+    /// The name of each struct literal and its fields in the order they were written.
+    pub(crate) records: Vec<(String, Vec<String>)>,
+    pub(crate) handlers: Vec<Handler>,
+    /// The patterns of each match, for checking they cover every variant.
+    pub(crate) matches: Vec<Vec<Pattern>>,
+    /// The enum, variant and number of fields of each variant pattern.
+    pub(crate) variants: Vec<(String, String, usize)>,
+    pub(crate) fails: Vec<Fail>,
+}
+
+/// A stretch of the syntax tree that an error coming out of it is noted with, the same way the
+/// tree-walker notes it on the way out of each node. Regions nest like the nodes they came from.
+#[derive(Debug)]
+pub(crate) struct Region {
+    pub(crate) parent: Option<usize>,
+    pub(crate) wrap: Wrap,
+    /// The innermost span around the region, which is where calls made in it are made from.
+    pub(crate) site: Option<Span>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Wrap {
+    Span(Span),
+    Context(String),
+    Nothing,
+}
+
+/// Where the errors of a `try` body go: to a `catch` block, which receives the error's value, or
+/// to a `finally` block, which raises the error again once it's done.
+#[derive(Debug)]
+pub(crate) struct Handler {
+    pub(crate) target: usize,
+    pub(crate) catch: bool,
+}
+
+/// Where a pattern goes when it doesn't match: the next arm, once the payload items it hasn't
+/// looked at yet are popped and the variables it bound so far are dropped.
+#[derive(Debug)]
+pub(crate) struct Fail {
+    pub(crate) target: usize,
+    pub(crate) pending: usize,
+    pub(crate) bound: usize,
+}
+
+struct Compiler {
+    chunk: Chunk,
+    region: usize,
+    /// The file functions declared in the chunk belong to.
+    module: Option<PathBuf>,
+    names: HashMap<String, usize>,
+    /// The first internal error, which is returned instead of the chunk.
+    error: Option<InterpErrors>,
+}
+
+/// Compiles a statement, or the body of a function declared in `module`, into a chunk.
+pub fn compile(expr: &Expression, module: Option<PathBuf>) -> Result<Chunk, InterpErrors> {
+    let mut compiler = Compiler {
+        chunk: Chunk::default(),
+        region: 0,
+        module,
+        names: HashMap::new(),
+        error: None,
+    };
+    compiler.chunk.tree.push(Region {
+        parent: None,
+        wrap: Wrap::Nothing,
+        site: None,
+    });
+    compiler.expr(expr);
+    compiler.emit(Op::End);
+    match compiler.error {
+        Some(e) => Err(e),
+        None => Ok(compiler.chunk),
+    }
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.regions.push(self.region);
+        self.chunk.code.len() - 1
+    }
+
+    fn here(&self) -> usize {
+        self.chunk.code.len()
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        match &mut self.chunk.code[at] {
+            Op::Jump(target) | Op::JumpIfNil(target) | Op::JumpUnlessNil(target) => *target = here,
+            op => {
+                let e = InterpErrors::Internal(format!("{:?} isn't a jump", op));
+                self.internal(e);
+            }
+        }
+    }
+
+    /// Keeps the first internal error, which fails the whole chunk.
+    fn internal(&mut self, e: InterpErrors) {
+        self.error.get_or_insert(e);
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        if let Some(index) = self.names.get(name) {
+            return *index;
+        }
+        self.chunk.names.push(String::from(name));
+        self.names
+            .insert(String::from(name), self.chunk.names.len() - 1);
+        self.chunk.names.len() - 1
+    }
+
+    fn constant(&mut self, value: Types) {
+        self.chunk.constants.push(value);
+        self.emit(Op::Constant(self.chunk.constants.len() - 1));
+    }
+
+    /// Compiles whatever `f` emits in a region of its own.
+    fn within(&mut self, wrap: Wrap, f: impl FnOnce(&mut Compiler)) {
+        let site = match wrap {
+            Wrap::Span(span) => Some(span),
+            _ => self.chunk.tree[self.region].site,
+        };
+        self.chunk.tree.push(Region {
+            parent: Some(self.region),
+            wrap,
+            site,
+        });
+        let outer = std::mem::replace(&mut self.region, self.chunk.tree.len() - 1);
+        f(self);
+        self.region = outer;
+    }
+
+    fn declare(&mut self, name: &str, constant: bool) {
+        let name = self.name(name);
+        self.emit(Op::Declare(name, constant));
+        self.emit(Op::Unit);
+    }
+
+    /// Compiles the operands of a binary operator, noting which one an error came from.
+    fn operands(&mut self, lhs: &Expression, rhs: &Expression, op: &dyn std::fmt::Display) {
+        self.within(Wrap::Context(operand("left", op)), |c| c.expr(lhs));
+        self.within(Wrap::Context(operand("right", op)), |c| c.expr(rhs));
+    }
+
+    fn expr(&mut self, expr: &Expression) {
+        match expr.span {
+            Some(span) => self.within(Wrap::Span(span), |c| c.node(&expr.kind)),
+            None => self.node(&expr.kind),
+        }
+    }
+
+    /// Compiles one link of a chain of accesses and calls, like the `.c` of `a?.b.c`. A `?.` that
+    /// finds `nil` leaves it on the stack and jumps over the rest of the chain, so its jump is
+    /// added to `skips` to be pointed past the end of the whole chain.
+    fn link(&mut self, kind: &ExprKind, skips: &mut Vec<usize>) {
+        match kind {
+            ExprKind::Access {
+                target,
+                accessor,
+                optional,
+            } => {
+                self.target(target, skips);
+                if *optional {
+                    skips.push(self.emit(Op::JumpIfNil(0)));
+                }
+                match accessor {
+                    Accessor::Field(field) => {
+                        let field = self.name(field);
+                        self.emit(Op::Field(field, *optional));
+                    }
+                    Accessor::Index(index) => {
+                        self.emit(Op::Indexable);
+                        self.expr(index);
+                        self.emit(Op::Index(*optional));
+                    }
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                self.target(callee, skips);
+                args.iter().for_each(|arg| self.expr(arg));
+                self.emit(Op::Call(args.len()));
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+                optional,
+            } => {
+                self.target(receiver, skips);
+                if *optional {
+                    skips.push(self.emit(Op::JumpIfNil(0)));
+                }
+                args.iter().for_each(|arg| self.expr(arg));
+                let method = self.name(method);
+                self.emit(Op::Invoke(method, args.len()));
+            }
+            kind => self.node(kind),
+        }
+    }
+
+    /// Compiles what a link of a chain is applied to, carrying on the chain.
+    fn target(&mut self, expr: &Expression, skips: &mut Vec<usize>) {
+        match expr.span {
+            Some(span) => self.within(Wrap::Span(span), |c| c.link(&expr.kind, skips)),
+            None => self.link(&expr.kind, skips),
+        }
+    }
+
+    fn node(&mut self, kind: &ExprKind) {
+        match kind {
+            ExprKind::Integer(value) => {
+                self.emit(Op::Integer(*value));
+            }
+            ExprKind::Boolean(value) => {
+                self.emit(Op::Boolean(*value));
+            }
+            ExprKind::String(value) => self.constant(Types::String(value.clone())),
+            ExprKind::Nil => {
+                self.emit(Op::Nil);
+            }
+            ExprKind::List(items) => {
+                items.iter().for_each(|item| self.expr(item));
+                self.emit(Op::List(items.len()));
+            }
+            ExprKind::Identifier(name) => {
+                let name = self.name(name);
+                self.emit(Op::Get(name));
+            }
+            ExprKind::Local { name, depth, slot } => {
+                let name = self.name(name);
+                self.emit(Op::Local(name, *depth, *slot));
+            }
+            ExprKind::MathOp { lhs, op, rhs } => {
+                self.operands(lhs, rhs, op);
+                self.emit(Op::Math(*op));
+            }
+            ExprKind::BooleanOp { lhs, op, rhs } => {
+                self.operands(lhs, rhs, op);
+                self.emit(Op::Compare(*op));
+            }
+            ExprKind::Coalesce { lhs, rhs } => {
+                self.expr(lhs);
+                let jump = self.emit(Op::JumpUnlessNil(0));
+                self.expr(rhs);
+                self.patch(jump);
+            }
+            ExprKind::Access { .. } | ExprKind::Call { .. } | ExprKind::MethodCall { .. } => {
+                let mut skips = Vec::new();
+                self.link(kind, &mut skips);
+                skips.into_iter().for_each(|skip| self.patch(skip));
+            }
+            ExprKind::StructLiteral { name, fields } => {
+                let struct_name = self.name(name);
+                self.emit(Op::StructType(struct_name));
+                for (depth, (field, value)) in fields.iter().enumerate() {
+                    let field = self.name(field);
+                    self.emit(Op::CheckField(field, depth));
+                    self.expr(value);
+                }
+                let names = fields.iter().map(|(field, _)| field.clone()).collect();
+                self.chunk.records.push((name.clone(), names));
+                self.emit(Op::Record(self.chunk.records.len() - 1));
+            }
+            ExprKind::Variant { enum_name, variant } => {
+                let enum_name = self.name(enum_name);
+                let variant = self.name(variant);
+                self.emit(Op::Variant(enum_name, variant));
+            }
+            ExprKind::Match { scrutinee, arms } => self.match_expr(scrutinee, arms),
+            ExprKind::Block(stmts) => {
+                self.emit(Op::PushScope);
+                for (i, stmt) in stmts.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Op::Pop);
+                    }
+                    self.expr(stmt);
+                }
+                if stmts.is_empty() {
+                    self.emit(Op::Unit);
+                }
+                self.emit(Op::PopScope);
+            }
+            ExprKind::Throw(value) => {
+                self.expr(value);
+                self.emit(Op::Throw);
+            }
+            ExprKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => {
+                        self.emit(Op::Unit);
+                    }
+                }
+                self.emit(Op::Return);
+            }
+            ExprKind::Propagate(value) => {
+                self.expr(value);
+                self.emit(Op::Propagate);
+            }
+            ExprKind::Try {
+                body,
+                catch,
+                finally,
+            } => self.try_expr(body, catch, finally),
+            ExprKind::LetStmt {
+                identifier,
+                value,
+                constant,
+                ..
+            } => {
+                self.expr(value);
+                self.declare(identifier, *constant);
+            }
+            ExprKind::FnDecl {
+                name, params, body, ..
+            } => {
+                let function = Function {
+                    name: name.clone(),
+                    params: params.iter().map(|param| param.name.clone()).collect(),
+                    body: (**body).clone(),
+                    module: self.module.clone(),
+                };
+                self.chunk.functions.push(Rc::new(function));
+                self.emit(Op::Function(self.chunk.functions.len() - 1));
+                self.declare(name, false);
+            }
+            ExprKind::ImplBlock { type_name, methods } => {
+                let methods = methods
+                    .iter()
+                    .map(|method| impl_function(method, self.module.clone()).map(Rc::new))
+                    .collect();
+                match methods {
+                    Ok(methods) => self.chunk.impls.push((type_name.clone(), methods)),
+                    Err(e) => return self.internal(e),
+                }
+                self.emit(Op::Impl(self.chunk.impls.len() - 1));
+                self.emit(Op::Unit);
+            }
+            ExprKind::Import { path, alias } => {
+                let path = self.name(path);
+                self.emit(Op::Import(path));
+                self.declare(alias, false);
+            }
+            ExprKind::Export(declaration) => {
+                let name = match exported_name(declaration) {
+                    Ok(name) => self.name(&name),
+                    Err(e) => return self.internal(e),
+                };
+                self.expr(declaration);
+                self.emit(Op::Export(name));
+            }
+            ExprKind::StructDecl { name, fields, .. } => {
+                let fields = fields.iter().map(|field| field.name.clone()).collect();
+                self.constant(Types::StructType {
+                    name: name.clone(),
+                    fields,
+                });
+                self.declare(name, false);
+            }
+            ExprKind::EnumDecl { name, variants } => {
+                self.constant(Types::EnumType {
+                    name: name.clone(),
+                    variants: variants.clone(),
+                });
+                self.declare(name, false);
+            }
+        }
+    }
+
+    /// The value being matched stays on the stack under each arm, which tests a copy of it.
+    fn match_expr(&mut self, scrutinee: &Expression, arms: &[(Pattern, Expression)]) {
+        self.expr(scrutinee);
+        let patterns = arms.iter().map(|(pattern, _)| pattern.clone()).collect();
+        self.chunk.matches.push(patterns);
+        self.emit(Op::CheckExhaustive(self.chunk.matches.len() - 1));
+
+        let mut ends = Vec::new();
+        for (pattern, body) in arms {
+            self.emit(Op::Copy);
+            let mut fails = Vec::new();
+            let mut bound = 0;
+            self.pattern(pattern, 0, &mut bound, &mut fails);
+            self.emit(Op::Bindings(bound));
+            self.expr(body);
+            self.emit(Op::PopScope);
+            ends.push(self.emit(Op::Jump(0)));
+
+            let next = self.here();
+            for fail in fails {
+                self.chunk.fails[fail].target = next;
+            }
+        }
+        self.emit(Op::NoMatchingArm);
+
+        ends.into_iter().for_each(|end| self.patch(end));
+        self.emit(Op::Nip);
+    }
+
+    /// Tests the value on top of the stack against a pattern. `pending` payload items of the
+    /// patterns around it are still on the stack under the value, and `bound` counts the
+    /// variables bound so far.
+    fn pattern(
+        &mut self,
+        pattern: &Pattern,
+        pending: usize,
+        bound: &mut usize,
+        fails: &mut Vec<usize>,
+    ) {
+        match &pattern.kind {
+            PatternKind::Wildcard => {
+                self.emit(Op::Pop);
+            }
+            PatternKind::Binding(name) => {
+                let name = self.name(name);
+                self.emit(Op::Bind(name));
+                *bound += 1;
+            }
+            PatternKind::Literal(literal) => {
+                self.expr(literal);
+                let fail = self.fail(pending, *bound, fails);
+                self.emit(Op::MatchLiteral(fail));
+            }
+            PatternKind::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                let fail = self.fail(pending, *bound, fails);
+                let variant = (enum_name.clone(), variant.clone(), fields.len());
+                self.chunk.variants.push(variant);
+                self.emit(Op::MatchVariant(self.chunk.variants.len() - 1, fail));
+                for (i, field) in fields.iter().enumerate() {
+                    self.pattern(field, pending + fields.len() - 1 - i, bound, fails);
+                }
+            }
+        }
+    }
+
+    fn fail(&mut self, pending: usize, bound: usize, fails: &mut Vec<usize>) -> usize {
+        self.chunk.fails.push(Fail {
+            target: 0,
+            pending,
+            bound,
+        });
+        fails.push(self.chunk.fails.len() - 1);
+        self.chunk.fails.len() - 1
+    }
+
+    fn handler(&mut self, catch: bool) -> usize {
+        self.chunk.handlers.push(Handler { target: 0, catch });
+        self.emit(Op::PushHandler(self.chunk.handlers.len() - 1));
+        self.chunk.handlers.len() - 1
+    }
+
+    /// A `finally` block is compiled twice: once to run after the body or `catch` block finishes,
+    /// and once to run for an error before raising it again.
+    fn try_expr(
+        &mut self,
+        body: &Expression,
+        catch: &Option<(String, Box<Expression>)>,
+        finally: &Option<Box<Expression>>,
+    ) {
+        let finally_handler = finally.as_ref().map(|_| self.handler(false));
+        let catch_handler = catch.as_ref().map(|_| self.handler(true));
+        self.expr(body);
+
+        if let (Some((name, handler)), Some(catch_handler)) = (catch, catch_handler) {
+            self.emit(Op::PopHandler);
+            let skip = self.emit(Op::Jump(0));
+            self.chunk.handlers[catch_handler].target = self.here();
+            let name = self.name(name);
+            self.emit(Op::Bind(name));
+            self.emit(Op::Bindings(1));
+            self.expr(handler);
+            self.emit(Op::PopScope);
+            self.patch(skip);
+        }
+
+        if let (Some(finally), Some(finally_handler)) = (finally, finally_handler) {
+            self.emit(Op::PopHandler);
+            self.expr(finally);
+            self.emit(Op::Pop);
+            let skip = self.emit(Op::Jump(0));
+            self.chunk.handlers[finally_handler].target = self.here();
+            self.expr(finally);
+            self.emit(Op::Pop);
+            self.emit(Op::Rethrow);
+            self.patch(skip);
+        }
+    }
+}
@@ -0,0 +1,1282 @@
+use crate::twig_lexer::{Token, tokenize};
+use crate::twig_parser::{
+    Accessor, Annotated, BooleanOperator, ExprKind, Expression, MathOperator, Node, Operator,
+    ParseError, Pattern, PatternKind, Span, TypeExpr, TypeKind, get_operation, hint,
+};
+
+type Parsed<T> = Result<T, ParseError>;
+
+/// A recursive descent parser over the tokens of one input, building the same syntax tree, spans
+/// included, as the pest grammar does.
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(Token, Span)>,
+    next: usize,
+    /// The span of the last token taken.
+    last: Span,
+    /// Whether line breaks are whitespace, for each bracket or block being parsed. They are inside
+    /// brackets, while blocks and the top level use them to end statements.
+    contexts: Vec<bool>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Parser<'a> {
+        let tokens = tokenize(input);
+        let last = tokens[0].1;
+        Parser {
+            input,
+            tokens,
+            next: 0,
+            last,
+            contexts: Vec::new(),
+        }
+    }
+
+    fn in_brackets(&self) -> bool {
+        self.contexts.last() == Some(&true)
+    }
+
+    /// The first token from `i` on, skipping line breaks when `in_brackets`.
+    fn skip_from(&self, mut i: usize, in_brackets: bool) -> usize {
+        while in_brackets && self.tokens[i].0 == Token::Newline {
+            i += 1;
+        }
+        i
+    }
+
+    fn skip_newlines(&mut self) {
+        self.next = self.skip_from(self.next, true);
+    }
+
+    fn peek(&mut self) -> &Token {
+        self.next = self.skip_from(self.next, self.in_brackets());
+        &self.tokens[self.next].0
+    }
+
+    fn peek_span(&mut self) -> Span {
+        self.peek();
+        self.tokens[self.next].1
+    }
+
+    /// The token after the next one.
+    fn second(&mut self) -> &Token {
+        self.peek();
+        let i = (self.next + 1).min(self.tokens.len() - 1);
+        &self.tokens[self.skip_from(i, self.in_brackets())].0
+    }
+
+    fn bump(&mut self) -> (Token, Span) {
+        self.peek();
+        let (token, span) = self.tokens[self.next].clone();
+        if token != Token::Eof {
+            self.next += 1;
+        }
+        self.last = span;
+        (token, span)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == token;
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Parsed<Span> {
+        match self.eat(&token) {
+            true => Ok(self.last),
+            false => Err(self.error(expected)),
+        }
+    }
+
+    /// An error at the next token, which was found where `expected` should have been.
+    fn error(&mut self, expected: &str) -> ParseError {
+        let span = self.peek_span();
+        let message = match self.peek() {
+            Token::Invalid(message) => message.clone(),
+            found => format!("expected {}, found {}", expected, found),
+        };
+        ParseError {
+            message,
+            span,
+            hint: hint(self.input, span.start),
+        }
+    }
+
+    fn name(&mut self, expected: &str) -> Parsed<String> {
+        match self.peek().clone() {
+            Token::Identifier(name) => {
+                self.bump();
+                Ok(name)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    /// Takes the closing bracket of the one opened at `open`, leaving its context.
+    fn close(&mut self, open: Span, close: Token, separated: bool) -> Parsed<()> {
+        if self.peek() != &close {
+            let comma = if separated { "`,` or " } else { "" };
+            let opener = &self.input[open.start..open.end];
+            let expected = format!("{}{} to close the `{}` at {}", comma, close, opener, open);
+            return Err(self.error(&expected));
+        }
+        self.contexts.pop();
+        self.bump();
+        Ok(())
+    }
+
+    /// Items with `,` between them, up to but not including `close`.
+    fn items<T>(
+        &mut self,
+        close: &Token,
+        trailing: bool,
+        mut item: impl FnMut(&mut Self) -> Parsed<T>,
+    ) -> Parsed<Vec<T>> {
+        let mut items = Vec::new();
+        if self.peek() == close {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if !self.eat(&Token::Comma) || trailing && self.peek() == close {
+                return Ok(items);
+            }
+        }
+    }
+
+    /// A bracket of items with `,` between them, from just after its opening bracket at `open`.
+    fn separated<T>(
+        &mut self,
+        open: Span,
+        close: Token,
+        trailing: bool,
+        item: impl FnMut(&mut Self) -> Parsed<T>,
+    ) -> Parsed<Vec<T>> {
+        self.contexts.push(true);
+        let items = self.items(&close, trailing, item)?;
+        self.close(open, close, true)?;
+        Ok(items)
+    }
+
+    /// A bracket around a single item, from just after its opening bracket at `open`.
+    fn bracketed<T>(
+        &mut self,
+        open: Span,
+        close: Token,
+        item: impl FnOnce(&mut Self) -> Parsed<T>,
+    ) -> Parsed<T> {
+        self.contexts.push(true);
+        let item = item(self)?;
+        self.close(open, close, false)?;
+        Ok(item)
+    }
+
+    /// Joins the `///` lines at the front of a declaration, dropping the space that usually
+    /// follows the slashes.
+    fn doc(&mut self) -> Option<String> {
+        let mut lines = Vec::new();
+        while let Token::DocComment(line) = self.peek() {
+            lines.push(String::from(line.strip_prefix(' ').unwrap_or(line)));
+            self.bump();
+        }
+
+        match lines.is_empty() {
+            true => None,
+            false => Some(lines.join("\n")),
+        }
+    }
+
+    fn statement(&mut self) -> Parsed<Expression> {
+        let start = self.peek_span();
+        let doc = self.doc();
+        let statement = match self.peek() {
+            Token::Export => self.export(doc)?,
+            Token::Let | Token::Const => self.let_stmt(doc)?,
+            Token::Fn => self.fn_decl(doc)?,
+            Token::Import => self.import()?,
+            Token::Struct => self.struct_decl()?,
+            Token::Enum => self.enum_decl()?,
+            Token::Impl => self.impl_block()?,
+            _ => return self.expr(),
+        };
+        Ok(Expression::new(statement, start.to(self.last)))
+    }
+
+    fn export(&mut self, doc: Option<String>) -> Parsed<ExprKind> {
+        self.bump();
+        let start = self.peek_span();
+        // A doc comment before `export` wins over one after it.
+        let doc = doc.or(self.doc());
+        let declaration = match self.peek() {
+            Token::Let | Token::Const => self.let_stmt(doc)?,
+            Token::Fn => self.fn_decl(doc)?,
+            Token::Struct => self.struct_decl()?,
+            Token::Enum => self.enum_decl()?,
+            _ => return Err(self.error("a `let`, `const`, `fn`, `struct` or `enum` to export")),
+        };
+        let span = start.to(self.last);
+        Ok(ExprKind::Export(Box::new(Expression::new(
+            declaration,
+            span,
+        ))))
+    }
+
+    fn let_stmt(&mut self, doc: Option<String>) -> Parsed<ExprKind> {
+        let constant = self.bump().0 == Token::Const;
+        let identifier = self.name("a name for the variable")?;
+        let annotation = match self.eat(&Token::Colon) {
+            true => Some(self.type_expr()?),
+            false => None,
+        };
+        if self.peek() == &Token::DoubleEqual {
+            let mut e = self.error("`=`");
+            e.hint = Some(String::from("did you mean `=`? `==` compares two values"));
+            return Err(e);
+        }
+        match annotation {
+            Some(_) => self.expect(Token::Equal, "`=`")?,
+            None => self.expect(Token::Equal, "`:` or `=`")?,
+        };
+        // A line break straight after the `=` carries on to the value on the next line.
+        self.skip_newlines();
+        let value = self.expr()?;
+        Ok(ExprKind::LetStmt {
+            doc,
+            identifier,
+            value: Box::new(value),
+            constant,
+            annotation,
+        })
+    }
+
+    fn fn_decl(&mut self, doc: Option<String>) -> Parsed<ExprKind> {
+        self.bump();
+        let name = self.name("a name for the function")?;
+        let type_params = self.type_params()?;
+        let open = self.expect(Token::LeftParen, "`(` and the function's parameters")?;
+        let params = self.separated(open, Token::RightParen, true, Self::annotated)?;
+        let return_type = match self.eat(&Token::ThinArrow) {
+            true => Some(self.type_expr()?),
+            false => None,
+        };
+        let body = self.block()?;
+        Ok(ExprKind::FnDecl {
+            doc,
+            name,
+            type_params,
+            params,
+            return_type,
+            body: Box::new(body),
+        })
+    }
+
+    /// A parameter or field with an optional type annotation.
+    fn annotated(&mut self) -> Parsed<Annotated> {
+        let start = self.peek_span();
+        let name = self.name("a name")?;
+        let annotation = match self.eat(&Token::Colon) {
+            true => Some(self.type_expr()?),
+            false => None,
+        };
+        Ok(Annotated {
+            name,
+            annotation,
+            span: Some(start.to(self.last)),
+        })
+    }
+
+    fn type_params(&mut self) -> Parsed<Vec<String>> {
+        let mut params = Vec::new();
+        if self.eat(&Token::Less) {
+            params.push(self.name("a type parameter")?);
+            while self.eat(&Token::Comma) {
+                params.push(self.name("a type parameter")?);
+            }
+            self.expect(Token::Greater, "`,` or `>`")?;
+        }
+        Ok(params)
+    }
+
+    fn import(&mut self) -> Parsed<ExprKind> {
+        self.bump();
+        let Token::String(path) = self.peek().clone() else {
+            return Err(self.error("the path of the file to import in quotes"));
+        };
+        self.bump();
+        self.expect(Token::As, "`as`")?;
+        let alias = self.name("a name for the imported file")?;
+        Ok(ExprKind::Import { path, alias })
+    }
+
+    fn struct_decl(&mut self) -> Parsed<ExprKind> {
+        self.bump();
+        let name = self.name("a name for the struct")?;
+        let type_params = self.type_params()?;
+        let open = self.expect(Token::LeftBrace, "`{` and the struct's fields")?;
+        let fields = self.separated(open, Token::RightBrace, true, Self::annotated)?;
+        Ok(ExprKind::StructDecl {
+            name,
+            type_params,
+            fields,
+        })
+    }
+
+    fn enum_decl(&mut self) -> Parsed<ExprKind> {
+        self.bump();
+        let name = self.name("a name for the enum")?;
+        let open = self.expect(Token::LeftBrace, "`{` and the enum's variants")?;
+        let variants = self.separated(open, Token::RightBrace, true, |parser| {
+            let variant = parser.name("a variant")?;
+            let fields = match parser.peek() {
+                Token::LeftParen => {
+                    let open = parser.bump().1;
+                    parser.separated(open, Token::RightParen, false, |parser| {
+                        parser.name("a field name")
+                    })?
+                }
+                _ => Vec::new(),
+            };
+            Ok((variant, fields))
+        })?;
+        Ok(ExprKind::EnumDecl { name, variants })
+    }
+
+    fn impl_block(&mut self) -> Parsed<ExprKind> {
+        self.bump();
+        let type_name = self.name("the name of a struct or enum")?;
+        let open = self.expect(Token::LeftBrace, "`{` and the methods")?;
+        self.contexts.push(true);
+        let mut methods = Vec::new();
+        while self.peek() != &Token::RightBrace {
+            let start = self.peek_span();
+            let doc = self.doc();
+            if self.peek() != &Token::Fn {
+                return Err(self.error(&format!("`fn` or `}}` to close the `{{` at {}", open)));
+            }
+            let method = self.fn_decl(doc)?;
+            methods.push(Expression::new(method, start.to(self.last)));
+            self.eat(&Token::Semicolon);
+        }
+        self.close(open, Token::RightBrace, false)?;
+        Ok(ExprKind::ImplBlock { type_name, methods })
+    }
+
+    fn type_expr(&mut self) -> Parsed<TypeExpr> {
+        let span = self.peek_span();
+        let ty = match self.peek().clone() {
+            Token::LeftBracket => {
+                self.bump();
+                let item = self.type_expr()?;
+                self.expect(Token::RightBracket, "`]`")?;
+                TypeKind::List(Box::new(item))
+            }
+            Token::Fn => {
+                self.bump();
+                self.expect(Token::LeftParen, "`(` and the parameter types")?;
+                let params = self.items(&Token::RightParen, false, Self::type_expr)?;
+                self.expect(Token::RightParen, "`,` or `)`")?;
+                self.expect(Token::ThinArrow, "`->` and the return type")?;
+                TypeKind::Function {
+                    params,
+                    ret: Box::new(self.type_expr()?),
+                }
+            }
+            Token::Identifier(name) => {
+                self.bump();
+                match self.eat(&Token::Less) {
+                    true => {
+                        let mut args = vec![self.type_expr()?];
+                        while self.eat(&Token::Comma) {
+                            args.push(self.type_expr()?);
+                        }
+                        self.expect(Token::Greater, "`,` or `>`")?;
+                        TypeKind::Generic { name, args }
+                    }
+                    false => TypeKind::Named(name),
+                }
+            }
+            Token::Nil => {
+                self.bump();
+                TypeKind::Named(String::from("nil"))
+            }
+            _ => return Err(self.error("a type")),
+        };
+        Ok(TypeExpr::new(ty, span.to(self.last)))
+    }
+
+    fn block(&mut self) -> Parsed<Expression> {
+        let open = self.expect(Token::LeftBrace, "`{`")?;
+        self.contexts.push(false);
+        let mut statements = Vec::new();
+        let close = format!("`}}` to close the `{{` at {}", open);
+        loop {
+            while matches!(self.peek(), Token::Newline | Token::Semicolon) {
+                self.bump();
+            }
+            match self.peek() {
+                Token::RightBrace => break,
+                Token::Eof => return Err(self.error(&close)),
+                _ => statements.push(self.statement()?),
+            }
+            if !matches!(
+                self.peek(),
+                Token::Newline | Token::Semicolon | Token::RightBrace
+            ) {
+                return Err(self.error(&format!("`;`, a line break or {}", close)));
+            }
+        }
+        self.contexts.pop();
+        self.bump();
+        Ok(Expression::new(
+            ExprKind::Block(statements),
+            open.to(self.last),
+        ))
+    }
+
+    /// An operation as a whole, spanning every operand and operator in it.
+    fn expr(&mut self) -> Parsed<Expression> {
+        let start = self.peek_span();
+        let operation = self.operation(0)?;
+        Ok(Expression::new(operation.kind, start.to(self.last)))
+    }
+
+    /// Operands joined by operators that bind at least as tightly as `precedence`, grouping to
+    /// the left.
+    fn operation(&mut self, precedence: u8) -> Parsed<Expression> {
+        let mut lhs = self.operand()?;
+        while let Some((op, op_precedence)) = self.operator() {
+            if op_precedence < precedence {
+                break;
+            }
+            self.bump();
+            // A line break straight after an operator continues the expression on the next line.
+            self.skip_newlines();
+            let rhs = self.operation(op_precedence + 1)?;
+            lhs = get_operation(lhs, op, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn operator(&mut self) -> Option<(Operator, u8)> {
+        let operator = match self.peek() {
+            Token::DoubleQuestion => (Operator::Coalesce, 1),
+            Token::DoubleEqual => (Operator::Bool(BooleanOperator::Eqaul), 2),
+            Token::Greater => (Operator::Bool(BooleanOperator::GreaterThan), 2),
+            Token::GreaterEqual => (Operator::Bool(BooleanOperator::GreaterThanEqual), 2),
+            Token::Less => (Operator::Bool(BooleanOperator::LessThan), 2),
+            Token::LessEqual => (Operator::Bool(BooleanOperator::LessThanEqual), 2),
+            Token::Plus => (Operator::Math(MathOperator::Add), 3),
+            Token::Minus => (Operator::Math(MathOperator::Subtract), 3),
+            Token::Star => (Operator::Math(MathOperator::Multiply), 4),
+            Token::Slash => (Operator::Math(MathOperator::Divide), 4),
+            _ => return None,
+        };
+        Some(operator)
+    }
+
+    /// A primary expression followed by any fields, indexes, calls and `?`s.
+    fn operand(&mut self) -> Parsed<Expression> {
+        let mut target = self.primary()?;
+        loop {
+            let span = target.span;
+            let postfix = match self.peek() {
+                Token::Dot => {
+                    self.bump();
+                    let field = self.name("a field name after `.`")?;
+                    access(target, Accessor::Field(field), false)
+                }
+                Token::QuestionDot => {
+                    let question = self.bump().1;
+                    // `?.[` is written without spaces, like the `?.` it starts with.
+                    if self.peek() == &Token::LeftBracket && self.peek_span().start == question.end
+                    {
+                        let open = self.bump().1;
+                        let index = self.bracketed(open, Token::RightBracket, Self::expr)?;
+                        access(target, Accessor::Index(Box::new(index)), true)
+                    } else {
+                        let field = self.name("a field name or `[` after `?.`")?;
+                        access(target, Accessor::Field(field), true)
+                    }
+                }
+                Token::LeftBracket => {
+                    let open = self.bump().1;
+                    let index = self.bracketed(open, Token::RightBracket, Self::expr)?;
+                    access(target, Accessor::Index(Box::new(index)), false)
+                }
+                Token::LeftParen => {
+                    let open = self.bump().1;
+                    let args = self.separated(open, Token::RightParen, true, Self::expr)?;
+                    // `a.b(...)` is a method call on `a` rather than a call of the field `b`.
+                    match target.kind {
+                        ExprKind::Access {
+                            target,
+                            accessor: Accessor::Field(method),
+                            optional,
+                        } => ExprKind::MethodCall {
+                            receiver: target,
+                            method,
+                            args,
+                            optional,
+                        },
+                        kind => ExprKind::Call {
+                            callee: Box::new(Node {
+                                kind,
+                                span: target.span,
+                            }),
+                            args,
+                        },
+                    }
+                }
+                Token::Question => {
+                    self.bump();
+                    ExprKind::Propagate(Box::new(target))
+                }
+                _ => return Ok(target),
+            };
+            target = Node {
+                kind: postfix,
+                span: span.map(|span| span.to(self.last)),
+            };
+        }
+    }
+
+    /// Whether the next token can start an expression.
+    fn at_expr(&mut self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Integer(_)
+                | Token::Identifier(_)
+                | Token::String(_)
+                | Token::True
+                | Token::False
+                | Token::Nil
+                | Token::LeftBracket
+                | Token::LeftBrace
+                | Token::LeftParen
+                | Token::Match
+                | Token::Try
+                | Token::Throw
+                | Token::Return
+        )
+    }
+
+    fn primary(&mut self) -> Parsed<Expression> {
+        let span = self.peek_span();
+        let expr = match self.peek().clone() {
+            Token::Integer(i) => {
+                self.bump();
+                ExprKind::Integer(i)
+            }
+            token @ (Token::True | Token::False) => {
+                self.bump();
+                ExprKind::Boolean(token == Token::True)
+            }
+            Token::Nil => {
+                self.bump();
+                ExprKind::Nil
+            }
+            Token::String(s) => {
+                self.bump();
+                return Ok(Expression::new(ExprKind::String(s), string_span(span)));
+            }
+            Token::LeftBracket => {
+                let open = self.bump().1;
+                self.contexts.push(true);
+                // `[,]` is an empty list too.
+                let items = match self.eat(&Token::Comma) {
+                    true => Vec::new(),
+                    false => self.items(&Token::RightBracket, true, Self::expr)?,
+                };
+                self.close(open, Token::RightBracket, true)?;
+                ExprKind::List(items)
+            }
+            Token::Match => self.match_expr()?,
+            Token::Try => self.try_expr()?,
+            Token::Throw => {
+                self.bump();
+                ExprKind::Throw(Box::new(self.expr()?))
+            }
+            Token::Return => {
+                self.bump();
+                match self.at_expr() {
+                    true => ExprKind::Return(Some(Box::new(self.expr()?))),
+                    false => ExprKind::Return(None),
+                }
+            }
+            Token::LeftBrace => return self.block(),
+            Token::Identifier(name) if self.second() == &Token::DoubleColon => {
+                self.bump();
+                self.bump();
+                let variant = self.name("a variant after `::`")?;
+                ExprKind::Variant {
+                    enum_name: name,
+                    variant,
+                }
+            }
+            Token::Identifier(name) if self.at_struct_literal() => {
+                self.bump();
+                let open = self.bump().1;
+                let fields = self.separated(open, Token::RightBrace, true, |parser| {
+                    let field = parser.name("a field name")?;
+                    parser.expect(Token::Colon, "`:`")?;
+                    Ok((field, parser.expr()?))
+                })?;
+                ExprKind::StructLiteral { name, fields }
+            }
+            Token::Identifier(name) => {
+                self.bump();
+                ExprKind::Identifier(name)
+            }
+            Token::LeftParen => {
+                let open = self.bump().1;
+                let operation = self.bracketed(open, Token::RightParen, |p| p.operation(0))?;
+                operation.kind
+            }
+            _ => return Err(self.error("an expression")),
+        };
+        Ok(Expression::new(expr, span.to(self.last)))
+    }
+
+    /// Whether a name is followed by the `{` of a struct literal, rather than by a block, as in
+    /// `match x { ... }`. The literal's braces have to be empty or start with `field:`.
+    fn at_struct_literal(&mut self) -> bool {
+        if self.second() != &Token::LeftBrace {
+            return false;
+        }
+        let brace = self.skip_from(self.next + 1, self.in_brackets());
+        let first = self.skip_from(brace + 1, true);
+        match self.tokens[first].0 {
+            Token::RightBrace => true,
+            Token::Identifier(_) => self.tokens[self.skip_from(first + 1, true)].0 == Token::Colon,
+            _ => false,
+        }
+    }
+
+    fn match_expr(&mut self) -> Parsed<ExprKind> {
+        self.bump();
+        let scrutinee = self.expr()?;
+        let open = self.expect(Token::LeftBrace, "`{` and the match arms")?;
+        self.contexts.push(true);
+        if self.peek() == &Token::RightBrace {
+            return Err(self.error("a match arm"));
+        }
+        let arms = self.items(&Token::RightBrace, true, |parser| {
+            let pattern = parser.pattern()?;
+            parser.expect(Token::FatArrow, "`=>`")?;
+            Ok((pattern, parser.expr()?))
+        })?;
+        self.close(open, Token::RightBrace, true)?;
+        Ok(ExprKind::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+        })
+    }
+
+    fn try_expr(&mut self) -> Parsed<ExprKind> {
+        self.bump();
+        let body = self.block()?;
+        let catch = match self.eat(&Token::Catch) {
+            true => {
+                let name = self.name("a name for the caught value")?;
+                Some((name, Box::new(self.block()?)))
+            }
+            false => None,
+        };
+        let finally = match self.eat(&Token::Finally) {
+            true => Some(Box::new(self.block()?)),
+            false => None,
+        };
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error("`catch` or `finally` after the `try` block"));
+        }
+        Ok(ExprKind::Try {
+            body: Box::new(body),
+            catch,
+            finally,
+        })
+    }
+
+    fn pattern(&mut self) -> Parsed<Pattern> {
+        let span = self.peek_span();
+        let literal = |expr| PatternKind::Literal(Box::new(Expression::new(expr, span)));
+        let pattern = match self.peek().clone() {
+            Token::Underscore => PatternKind::Wildcard,
+            Token::Identifier(name) => {
+                self.bump();
+                if self.eat(&Token::DoubleColon) {
+                    let variant = self.name("a variant after `::`")?;
+                    let fields = match self.peek() {
+                        Token::LeftParen => {
+                            let open = self.bump().1;
+                            self.separated(open, Token::RightParen, false, Self::pattern)?
+                        }
+                        _ => Vec::new(),
+                    };
+                    let pattern = PatternKind::Variant {
+                        enum_name: name,
+                        variant,
+                        fields,
+                    };
+                    return Ok(Pattern::new(pattern, span.to(self.last)));
+                }
+                if (name == "Ok" || name == "Err") && self.peek() == &Token::LeftParen {
+                    let open = self.bump().1;
+                    let field = self.bracketed(open, Token::RightParen, Self::pattern)?;
+                    let pattern = PatternKind::Variant {
+                        enum_name: String::from("Result"),
+                        variant: name,
+                        fields: vec![field],
+                    };
+                    return Ok(Pattern::new(pattern, span.to(self.last)));
+                }
+                return Ok(Pattern::new(PatternKind::Binding(name), span));
+            }
+            Token::Integer(i) => literal(ExprKind::Integer(i)),
+            token @ (Token::True | Token::False) => {
+                literal(ExprKind::Boolean(token == Token::True))
+            }
+            Token::Nil => literal(ExprKind::Nil),
+            Token::String(s) => {
+                let literal = Expression::new(ExprKind::String(s), string_span(span));
+                self.bump();
+                return Ok(Pattern::new(
+                    PatternKind::Literal(Box::new(literal)),
+                    string_span(span),
+                ));
+            }
+            _ => return Err(self.error("a pattern")),
+        };
+        self.bump();
+        Ok(Pattern::new(pattern, span))
+    }
+
+    /// Skips the rest of a statement that didn't parse, so the next one can be tried. It ends at
+    /// the first `;` or line break outside of brackets from where the error was found, unless the
+    /// error was at the start of a line. Then the statement before had run on into it after a
+    /// trailing operator, and the line is parsed again as a statement of its own. A bracket that's
+    /// never closed would take the rest of the input with it, so then only the brackets opened
+    /// from the error on count.
+    fn recover(&mut self, start: usize) {
+        let failed = self.next;
+        self.contexts.clear();
+        let line_start = failed > start && self.tokens[failed - 1].0 == Token::Newline;
+        self.next = self
+            .resync(start, failed, line_start)
+            .or_else(|| self.resync(failed, failed, line_start))
+            .unwrap_or(self.tokens.len() - 1);
+    }
+
+    /// Where a statement that failed at `failed` ends, counting brackets from `from`, or `None` if
+    /// one of them is never closed.
+    fn resync(&self, from: usize, failed: usize, line_start: bool) -> Option<usize> {
+        let mut depth = 0usize;
+        for i in from..self.tokens.len() {
+            let token = &self.tokens[i].0;
+            if i >= failed && depth == 0 {
+                let ends = matches!(token, Token::Newline | Token::Semicolon | Token::Eof);
+                if ends || i == failed && line_start {
+                    return Some(i);
+                }
+            }
+            match token {
+                Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth += 1,
+                Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                    depth = depth.saturating_sub(1)
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+fn access(target: Expression, accessor: Accessor, optional: bool) -> ExprKind {
+    ExprKind::Access {
+        target: Box::new(target),
+        accessor,
+        optional,
+    }
+}
+
+/// Strings are spanned by the text between their quotes.
+fn string_span(span: Span) -> Span {
+    Span {
+        start: span.start + 1,
+        end: span.end - 1,
+        column: span.column + 1,
+        ..span
+    }
+}
+
+/// Parses a single statement, the way the REPL reads its input.
+pub fn parse_statement(input: &str) -> Result<Expression, ParseError> {
+    let mut parser = Parser::new(input);
+    let statement = parser.statement()?;
+    match parser.peek() {
+        Token::Eof => Ok(statement),
+        _ => Err(parser.error("the end of the statement")),
+    }
+}
+
+/// Parses a whole script file into its statements.
+///
+/// A statement that doesn't parse is skipped to carry on with the next one, so that every syntax
+/// error in the file is found in one go.
+pub fn parse_statements(input: &str) -> Result<Vec<Expression>, Vec<ParseError>> {
+    let mut parser = Parser::new(input);
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    loop {
+        while matches!(parser.peek(), Token::Newline | Token::Semicolon) {
+            parser.bump();
+        }
+        if parser.peek() == &Token::Eof {
+            break;
+        }
+
+        let start = parser.next;
+        let result = match parser.statement() {
+            Ok(statement)
+                if matches!(
+                    parser.peek(),
+                    Token::Newline | Token::Semicolon | Token::Eof
+                ) =>
+            {
+                Ok(statement)
+            }
+            Ok(_) => Err(parser.error("`;` or a line break after the statement")),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(statement) => statements.push(statement),
+            Err(e) => {
+                errors.push(e);
+                parser.recover(start);
+            }
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(statements),
+        false => Err(errors),
+    }
+}
+
+#[cfg(test)]
+mod twig_descent_tests {
+    use super::*;
+    use crate::twig_interp::{Env, InterpErrors, Types, interp_program};
+    use crate::twig_parser::{SyntaxError, parse_file, parse_program};
+    use crate::twig_resolver::resolve_program;
+    use crate::twig_vm::run_program;
+    use crate::{GrammarParser, Rule};
+    use pest::Parser as _;
+
+    /// Programs both parsers are run on. Each has to parse to the same syntax tree with either.
+    const VALID: [&str; 45] = [
+        "let total = (1 + 2) * 3\ntotal >= 9\n\"hello\"\nlet xs = [1, 2, 3]\nxs[0]",
+        "const limit = 10\nlet limit = 20\n{ let limit = 20; limit }",
+        "let xs = [1, 2]\nxs?.[5] ?? 0\nn?.a.b",
+        "struct Point { x, y }\nlet p = Point { x: 1, y: 2 }\np.x + p.y\np == Point { x: 1, y: 2 }",
+        "enum Shape { Circle(r), Rect(w, h), Empty }\nlet s = Shape::Rect(2, 3)\nmatch s { Shape::Circle(r) => r * r * 3, Shape::Rect(w, h) => w * h, Shape::Empty => 0 }",
+        "fn square(n) { n * n }\nimpl Point { fn new(x, y) { Point { x: x, y: y } } fn dist(self) { square(self.x) + square(self.y) } }\nPoint::new(3, 4).dist()",
+        "fn shout(s: string) -> string { s.upper() }\nlet n: int = shout(\"hi\")",
+        "struct Pair<A, B> { first: A, second: B }\nfn first<T>(xs: [T]) -> T { xs[0] }\nlet p: Pair<int, string> = Pair { first: first([1, 2]), second: \"b\" }",
+        "try { 1 / 0 } catch e { e.kind }\ntry { throw \"oops\" } catch e { e } finally { cleanup() }\ntry { 1 } finally { 2 }",
+        "fn half(n) { match n / 2 * 2 == n { true => Ok(n / 2), false => Err(\"odd\") } }\nfn quarter(n) { let h = half(n)?; half(h) }\nmatch quarter(6) { Ok(q) => q, Err(e) => 0 }",
+        "import \"lib/math.twig\" as m\nprint(m.sum_squares(3, 4))",
+        "fn square(n) { n * n }\nexport fn sum_squares(a, b) { square(a) + square(b) }\nexport struct P { x }\nexport enum E { A }",
+        "/// Squares a number.\nfn square(n) { n * n /* no overflow checks */ }",
+        "fn area(shape) {\n    let scale = 2\n    match shape {\n        Shape::Rect(w, h) => w * h *\n            scale,\n        _ => 0,\n    }\n}",
+        "// not a doc\n/// Adds one.\n/// Really.\nfn inc(n) { n + 1 }",
+        "/// Outer.\nexport /// Inner.\nlet x = 1\n/// Only inner.\nexport fn f() { 1 }\nexport /// Inner.\nconst c = 2",
+        "/// Dropped.\nexport struct S { a }",
+        "/// Stray.\n1 + 2 /// after\n/// Apart.\n\nfn f() {}",
+        "impl P {\n  /// Stray.\n\n  fn a(self) { 1 }\n  /// Stray.\n}\n{ 1 /// stray\n}",
+        "/// Doc.\n  /// More.\n   let a = 1\n/// Stray at the end.",
+        "let\ta =\t1\r\nlet b = 2\r\n",
+        "f(\n  1,\n  [2,\n   3],\n)\n[,]\n[1,]\nf()",
+        "1 +\n\n 2 ??\n nil\nlet x =\n\n  5",
+        "{\n\tlet a = 1\n\n\ta\n};;\n\n;",
+        "(1 + 2)\n((1))\n(1 +\n 2) * 3",
+        "a ?? b ?? c == d > e >= f < g <= h - i / j * k + l",
+        "\"a\".len()\n\"\".upper().lower()\n\"two\nlines\"",
+        "fn f(a: nil, b, g: fn(int) -> bool, h: fn() -> [int],) -> Pair<[int], fn(A, B) -> C> { 1 }",
+        "match x { _ => 1, 2 => 3, true => 4, nil => 5, \"s\" => 6, y => y, }",
+        "match r { Ok(Ok(x)) => x, Err(_) => 0, E::A() => 1, E::B(a, _) => a, Okay => 2 }",
+        "fn f() { return }\nfn g() { return 1 }\nfn h() { return; 2 }\nreturn",
+        "x?\nx?.y?\nf(a)(b)[c].d?.e(f)?.[g]\nx? ?? y",
+        "throw Point { x: 1, y: [2] }\nthrow \"e\"",
+        "impl P {\n  /// Doc.\n  fn a(self) { 1 };\n  fn b(self) { 2 }\n}\nimpl Q {}",
+        "struct Empty {}\nEmpty {}\nenum N {}\nstruct T { a: int, b: [T], }",
+        "letter + trueish + nil_value + _unused + café + x1 + __",
+        "let s = \"a;\n)\" /* ; \n */\nlet t = 1",
+        "[{ 1\n 2 }, { let a = 1; a }]\nmatch x {\n  1 =>\n   2\n}",
+        "let a = [\n  1, // one\n  2 /* two */,\n]",
+        "/* nested /* comment */ here */ 1 // after",
+        "x.y(1).z\na.b.c(1, 2,)",
+        "Ok(1)\nErr(\"e\")\nE::V(1)",
+        "let p = match q { a => b }\nlet y = {\n  1\n}",
+        "2147483647 + 1\n65536 * 65536\n0 - 2147483647 - 2\n(0 - 2147483647 - 1) / (0 - 1)",
+        "fn g(n) { g(n + 1) }\ntry { g(0) } catch e { e.kind }",
+    ];
+
+    /// Programs neither parser accepts.
+    const INVALID: [&str; 27] = [
+        "1 +* 2",
+        "let a = *",
+        "let b == 3",
+        "a = 1",
+        "let x = \"open",
+        "let x = [1, 2",
+        "f(1 2)",
+        "1\n+ 2",
+        "match x {}",
+        "match x\n{ _ => 1 }",
+        "try { 1 }",
+        "try { 1 }\ncatch e { 2 }",
+        "export /// Not a doc.\nstruct S {}",
+        "export 1",
+        "import x as y",
+        "fn f()\n{ 1 }",
+        "let let = 1",
+        "x?. [0]",
+        "[,1]",
+        "match x { E::V(a,) => 1 }",
+        "let t: Pair<> = 1",
+        "let t: [\nint] = 1",
+        "fn f<T,\nU>() {}",
+        "1 # 2",
+        "/* open",
+        "{ 1 2 }",
+        "_",
+    ];
+
+    fn pest_file(input: &str) -> Result<Vec<Expression>, Vec<SyntaxError>> {
+        parse_file(input)
+    }
+
+    fn pest_statement(input: &str) -> Option<Expression> {
+        let mut pairs = GrammarParser::parse(Rule::program, input).ok()?;
+        parse_program(pairs.next()?.into_inner()).ok()
+    }
+
+    /// The syntax trees written out with the ends of their spans left out. pest spans take in any
+    /// spaces and comments after an operation, while the hand-written parser's stop at its last
+    /// token, so only where each node starts is compared.
+    fn shape(program: &[Expression]) -> String {
+        let tree = format!("{:#?}", program);
+        tree.lines()
+            .filter(|line| !line.trim_start().starts_with("end: "))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_parse_statements_matches_pest() {
+        for input in VALID {
+            let expected = pest_file(input).unwrap();
+            let result = parse_statements(input).unwrap_or_else(|e| panic!("{}: {:?}", input, e));
+
+            assert_eq!(shape(&result), shape(&expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_statement_matches_pest() {
+        for input in VALID.iter().flat_map(|input| input.lines()) {
+            let result = parse_statement(input).ok();
+            let expected = pest_statement(input);
+
+            assert_eq!(
+                result.map(|e| shape(&[e])),
+                expected.map(|e| shape(&[e])),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_statements_rejects_what_pest_does() {
+        for input in INVALID {
+            assert!(pest_file(input).is_err(), "pest accepted {}", input);
+            assert!(parse_statements(input).is_err(), "accepted {}", input);
+            assert_eq!(pest_statement(input), None, "pest accepted {}", input);
+            assert!(parse_statement(input).is_err(), "accepted {}", input);
+        }
+    }
+
+    fn errors(input: &str) -> Vec<(String, usize, usize, Option<String>)> {
+        parse_statements(input)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.message, e.span.line, e.span.column, e.hint))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_statements_error_messages() {
+        let result = errors("let a = *\nf(1 2)\nlet xs = [1, 2\n");
+
+        assert_eq!(
+            result,
+            [
+                (
+                    String::from("expected an expression, found `*`"),
+                    1,
+                    9,
+                    None
+                ),
+                (
+                    String::from(
+                        "expected `,` or `)` to close the `(` at line 2, column 2, found `2`"
+                    ),
+                    2,
+                    5,
+                    None
+                ),
+                (
+                    String::from(
+                        "expected `,` or `]` to close the `[` at line 3, column 10, found the end of the input"
+                    ),
+                    4,
+                    1,
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_statements_hints() {
+        let result = errors("let b == 3\na = 1\nlet s = \"open\nlet t = 2");
+        let hints: Vec<_> = result
+            .into_iter()
+            .map(|(_, line, _, hint)| (line, hint))
+            .collect();
+
+        assert_eq!(
+            hints,
+            [
+                (
+                    1,
+                    Some(String::from("did you mean `=`? `==` compares two values"))
+                ),
+                (
+                    2,
+                    Some(String::from(
+                        "did you mean `==`? `=` only gives a value to a `let`"
+                    ))
+                ),
+                (
+                    3,
+                    Some(String::from(
+                        "the string on line 3 might be missing its closing `\"`"
+                    ))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_statements_recovers_after_trailing_operator() {
+        let result = errors("let a = 1 +\n\nlet b = *\nlet c = 2");
+        let lines: Vec<_> = result
+            .iter()
+            .map(|(_, line, column, _)| (*line, *column))
+            .collect();
+
+        assert_eq!(lines, [(3, 1), (3, 9)]);
+    }
+
+    #[test]
+    fn test_parse_statements_recovers_after_unclosed_bracket() {
+        let result = errors("let a = f(1, 2\nlet b = *\nlet c = [1 +* 2\nlet d = *");
+        let lines: Vec<_> = result
+            .iter()
+            .map(|(_, line, column, _)| (*line, *column))
+            .collect();
+
+        assert_eq!(lines, [(2, 1), (2, 9), (3, 13), (4, 9)]);
+    }
+
+    #[test]
+    fn test_parse_statements_no_string_hint_away_from_the_string() {
+        let result = errors("let s = \"two\nlines\" + *");
+
+        assert_eq!(result[0].3, None);
+    }
+
+    #[test]
+    fn test_parse_statement_reports_invalid_tokens() {
+        let e = parse_statement("1 + 99999999999").unwrap_err();
+
+        assert_eq!(
+            e.message,
+            "`99999999999` is too big to be an integer, which goes up to 2147483647"
+        );
+        assert_eq!((e.span.start, e.span.end), (4, 15));
+    }
+
+    /// Pieces random programs are made from. Builtins like `print` that write to the terminal are
+    /// left out. Recursion that never ends stops at `MAX_CALL_DEPTH` with an error.
+    const PIECES: [&str; 60] = [
+        "let",
+        "const",
+        "fn",
+        "x",
+        "y",
+        "f",
+        "P",
+        "E",
+        "=",
+        "1",
+        "0",
+        "65536",
+        "2147483647",
+        "99999999999",
+        "\"s\"",
+        "\"",
+        "true",
+        "nil",
+        "(",
+        ")",
+        "[",
+        "]",
+        "{",
+        "}",
+        "+",
+        "-",
+        "*",
+        "/",
+        "==",
+        ">=",
+        "<",
+        ">",
+        "??",
+        "?.",
+        "?",
+        ".",
+        ",",
+        ";",
+        ":",
+        "::",
+        "->",
+        "=>",
+        "_",
+        "match",
+        "try",
+        "catch",
+        "finally",
+        "throw",
+        "return",
+        "struct",
+        "enum",
+        "impl",
+        "export",
+        "import",
+        "as",
+        "/*",
+        "*/",
+        "///",
+        "\n",
+        " ",
+    ];
+
+    const CHARACTERS: [char; 26] = [
+        'x', '1', '(', ')', '{', '}', '[', ']', '"', '/', '*', '\n', '\r', ' ', 'é', '+', '=', '?',
+        '.', ':', ',', ';', '_', '<', '>', '-',
+    ];
+
+    /// A small linear congruential generator, so every run tries the same programs.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % n
+        }
+    }
+
+    /// A result as it would be printed, which is the same whether or not the functions in it had
+    /// their local variables resolved.
+    fn shown(result: &Result<Types, InterpErrors>) -> Result<String, String> {
+        match result {
+            Ok(value) => Ok(value.to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn run(program: &[Expression]) {
+        let _ = crate::twig_types::check_program(program, false);
+        let _ = crate::twig_types::check_program(program, true);
+        // The VM runs everything alongside the tree-walker and has to agree with it, and so do
+        // both of them once local variables are resolved to slots.
+        let env = Env::new();
+        let mut resolved = program.to_vec();
+        let resolved = resolve_program(&mut resolved, &env)
+            .is_ok()
+            .then_some(resolved);
+        let (mut walker, mut vm) = (env.clone(), env.clone());
+        let (mut resolved_walker, mut resolved_vm) = (env.clone(), env);
+        for (i, statement) in program.iter().enumerate() {
+            let result = interp_program(statement, &mut walker);
+            assert_eq!(run_program(statement, &mut vm), result, "{statement:?}");
+            if let Some(resolved) = &resolved {
+                let walked = interp_program(&resolved[i], &mut resolved_walker);
+                let compiled = run_program(&resolved[i], &mut resolved_vm);
+                assert_eq!(compiled, walked, "{:?}", resolved[i]);
+                assert_eq!(shown(&walked), shown(&result), "{:?}", resolved[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_input_never_panics() {
+        let mut random = Random(7);
+        for i in 0..3000 {
+            let len = 1 + random.below(12);
+            let input: String = match i % 4 {
+                // Raw characters find the lexer's corners, pieces get past it into the parsers.
+                0 => (0..len)
+                    .map(|_| CHARACTERS[random.below(CHARACTERS.len())])
+                    .collect(),
+                1 => (0..len)
+                    .map(|_| PIECES[random.below(PIECES.len())])
+                    .collect(),
+                // Valid programs with a piece swapped in get furthest, into the interpreter.
+                _ => {
+                    let program = VALID[random.below(VALID.len())];
+                    let boundaries: Vec<usize> = program
+                        .char_indices()
+                        .map(|(i, _)| i)
+                        .chain([program.len()])
+                        .collect();
+                    let start = boundaries[random.below(boundaries.len())];
+                    let end = boundaries[random.below(boundaries.len())].max(start);
+                    let end = end.min(start + len);
+                    let end = *boundaries.iter().find(|&&b| b >= end).unwrap();
+                    let piece = PIECES[random.below(PIECES.len())];
+                    format!("{}{}{}", &program[..start], piece, &program[end..])
+                }
+            };
+
+            if let Ok(program) = parse_statements(&input) {
+                run(&program);
+            }
+            if let Ok(program) = pest_file(&input) {
+                run(&program);
+            }
+            if let Some(statement) = pest_statement(&input) {
+                run(&[statement]);
+            }
+            let _ = parse_statement(&input);
+        }
+    }
+}
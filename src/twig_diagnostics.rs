@@ -0,0 +1,330 @@
+use pest::error::{Error, InputLocation, LineColLocation};
+
+use crate::Rule;
+use crate::twig_interp::InterpErrors;
+use crate::twig_parser::{ParseError, Span, SyntaxError};
+use crate::twig_types::TypeError;
+
+/// An error ready to be shown, in the same shape whether it came from the parser, the type checker
+/// or a running program.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    /// `P` codes are syntax errors, `T` codes type errors and `R` codes runtime errors.
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            code,
+            message,
+            span: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Option<Span>) -> Diagnostic {
+        self.span = span;
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.notes.push(String::from(note));
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Diagnostic {
+        self.help = Some(String::from(help));
+        self
+    }
+
+    /// Writes the diagnostic out with the line of `source` it points at, underlining the part of
+    /// the line its span covers.
+    ///
+    /// ```text
+    /// error[R0013]: Division by zero
+    ///  --> line 1, column 5
+    ///   |
+    /// 1 | 1 + 2 / 0
+    ///   |     ^^^^^
+    ///   = help: check that the divisor isn't 0 first
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let mut lines = vec![format!("error[{}]: {}", self.code, self.message)];
+
+        let snippet = self.span.filter(|span| span.start <= source.len());
+        let gutter = snippet.map_or(0, |span| span.line.to_string().len());
+        let pad = " ".repeat(gutter);
+        if let Some(span) = snippet {
+            let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = source[span.start..]
+                .find(['\n', '\r'])
+                .map_or(source.len(), |i| span.start + i);
+            let line = &source[line_start..line_end];
+
+            // Tabs are kept so that the underline lines up however wide they're shown.
+            let indent: String = source[line_start..span.start]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let width = source[span.start..span.end.min(line_end)].chars().count();
+
+            lines.push(format!("{}--> {}", pad, span));
+            lines.push(format!("{} |", pad));
+            lines.push(format!("{} | {}", span.line, line));
+            lines.push(format!("{} | {}{}", pad, indent, "^".repeat(width.max(1))));
+        }
+        for note in &self.notes {
+            lines.push(format!("{} = note: {}", pad, note));
+        }
+        if let Some(help) = &self.help {
+            lines.push(format!("{} = help: {}", pad, help));
+        }
+
+        lines.join("\n")
+    }
+}
+
+impl From<&Error<Rule>> for Diagnostic {
+    fn from(e: &Error<Rule>) -> Diagnostic {
+        let (start, end) = match e.location {
+            InputLocation::Pos(pos) => (pos, pos),
+            InputLocation::Span(span) => span,
+        };
+        let (line, column) = match e.line_col {
+            LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+        };
+        let span = Span {
+            start,
+            end,
+            line,
+            column,
+        };
+
+        let message = format!("Syntax error: {}", e.variant.message());
+        Diagnostic::new("P0001", message).with_span(Some(span))
+    }
+}
+
+impl From<&SyntaxError> for Diagnostic {
+    fn from(e: &SyntaxError) -> Diagnostic {
+        let diagnostic = Diagnostic::from(&e.error);
+        match &e.hint {
+            Some(hint) => diagnostic.with_help(hint),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(e: &ParseError) -> Diagnostic {
+        let diagnostic = Diagnostic::new("P0001", format!("Syntax error: {}", e.message))
+            .with_span(Some(e.span));
+        match &e.hint {
+            Some(hint) => diagnostic.with_help(hint),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&TypeError> for Diagnostic {
+    fn from(e: &TypeError) -> Diagnostic {
+        Diagnostic::new("T0001", e.message.clone())
+            .with_span(e.span)
+            .with_note("nothing was run, since the program has to type check first")
+    }
+}
+
+impl From<&InterpErrors> for Diagnostic {
+    /// The stack trace comes last, innermost call first.
+    fn from(e: &InterpErrors) -> Diagnostic {
+        let frames = e.trace().unwrap_or_default();
+        frames.iter().rev().fold(describe(e), |diagnostic, frame| {
+            diagnostic.with_note(&format!("in {}", frame))
+        })
+    }
+}
+
+/// Everything about the error apart from its stack trace.
+fn describe(e: &InterpErrors) -> Diagnostic {
+    let diagnostic = |code| Diagnostic::new(code, e.to_string());
+    match e {
+        InterpErrors::At(span, e) => describe(e).with_span(Some(*span)),
+        InterpErrors::Context(context, e) => {
+            describe(e).with_note(&format!("while evaluating {}", context))
+        }
+        InterpErrors::Traced(_, e) => describe(e),
+        InterpErrors::MismatchedTypeError(..) => diagnostic("R0001")
+            .with_help("only values of the same type can be compared, apart from `nil`"),
+        InterpErrors::InvalidTypeError(_) => diagnostic("R0002"),
+        InterpErrors::VariableDoesNotExist {
+            suggestion: None, ..
+        } => diagnostic("R0003").with_help("declare it with `let` before using it"),
+        InterpErrors::VariableDoesNotExist { .. } => diagnostic("R0003"),
+        InterpErrors::IndexOutOfBounds { .. } => diagnostic("R0004")
+            .with_help("use `?.[i]` or `get(i)` to get `nil` instead of an error"),
+        InterpErrors::FieldDoesNotExist(_) => {
+            diagnostic("R0005").with_help("use `?.` to get `nil` instead of an error")
+        }
+        InterpErrors::MissingFieldError(_) => diagnostic("R0006")
+            .with_note("a struct literal has to give every field of the struct a value"),
+        InterpErrors::WrongArgumentCount { .. } => diagnostic("R0007"),
+        InterpErrors::NotCallableError(_) => diagnostic("R0008"),
+        InterpErrors::VariantDoesNotExist(_) => diagnostic("R0009"),
+        InterpErrors::NonExhaustiveMatch(_) => {
+            diagnostic("R0010").with_help("add arms for the missing variants or a `_` arm")
+        }
+        InterpErrors::NoMatchingArm(_) => {
+            diagnostic("R0011").with_help("add a `_` arm to handle any other value")
+        }
+        InterpErrors::MethodDoesNotExist(..) => diagnostic("R0012"),
+        InterpErrors::DivisionByZero => {
+            diagnostic("R0013").with_help("check that the divisor isn't 0 first")
+        }
+        InterpErrors::Thrown(_) => {
+            diagnostic("R0014").with_help("catch it with `try { ... } catch e { ... }`")
+        }
+        InterpErrors::ModuleNotFound(_) => {
+            diagnostic("R0015").with_note("paths are relative to the importing file")
+        }
+        InterpErrors::ModuleParseError(_) => diagnostic("R0016"),
+        InterpErrors::ModuleTypeError(_) => diagnostic("R0017"),
+        InterpErrors::CyclicImport(_) => {
+            diagnostic("R0018").with_note("files can't import each other in a loop")
+        }
+        InterpErrors::AssignToConstant(_) => {
+            diagnostic("R0019").with_help("declare it with `let` if it needs to change")
+        }
+        InterpErrors::Return(_) => diagnostic("R0020"),
+        InterpErrors::IntegerOverflow(_) => {
+            diagnostic("R0022").with_note("integers go from -2147483648 to 2147483647")
+        }
+        InterpErrors::StackOverflow => {
+            diagnostic("R0021").with_help("check that the recursion reaches a case that stops it")
+        }
+        InterpErrors::Internal(_) => diagnostic("R0023")
+            .with_note("this is a bug in the interpreter rather than in the program"),
+    }
+}
+
+#[cfg(test)]
+mod twig_diagnostics_tests {
+    use super::*;
+    use crate::GrammarParser;
+    use crate::twig_interp::{Env, Frame, interp_program};
+    use crate::twig_parser::{parse_file, parse_program};
+    use crate::twig_types::check_program;
+    use pest::Parser;
+    use std::path::PathBuf;
+
+    fn run(input: &str) -> InterpErrors {
+        let mut pairs = GrammarParser::parse(Rule::program, input).unwrap();
+        let program = parse_program(pairs.next().unwrap().into_inner()).unwrap();
+        interp_program(&program, &mut Env::new()).unwrap_err()
+    }
+
+    #[test]
+    fn test_render_runtime_error() {
+        let input = "[1,\n 2 / 0]";
+        let result = Diagnostic::from(&run(input)).render(input);
+        let expected = [
+            "error[R0013]: Division by zero",
+            " --> line 2, column 2",
+            "  |",
+            "2 |  2 / 0]",
+            "  |  ^^^^^",
+            "  = help: check that the divisor isn't 0 first",
+        ]
+        .join("\n");
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_render_underlines_first_line() {
+        let input = "[1,\n2][5]";
+        let result = Diagnostic::from(&run(input)).render(input);
+
+        assert!(result.contains("1 | [1,\n  | ^^^\n"));
+    }
+
+    #[test]
+    fn test_render_parse_error() {
+        let input = "1 +* 2";
+        let e = GrammarParser::parse(Rule::program, input).unwrap_err();
+        let result = Diagnostic::from(&e).render(input);
+
+        assert!(result.starts_with("error[P0001]: Syntax error: expected"));
+        assert!(result.ends_with(" --> line 1, column 4\n  |\n1 | 1 +* 2\n  |    ^"));
+    }
+
+    #[test]
+    fn test_render_syntax_error_hint() {
+        let input = "let b == 3";
+        let e = parse_file(input).unwrap_err().remove(0);
+        let result = Diagnostic::from(&e).render(input);
+
+        assert!(result.ends_with(
+            "1 | let b == 3\n  |        ^\n  = help: did you mean `=`? `==` compares two values"
+        ));
+    }
+
+    #[test]
+    fn test_render_type_error() {
+        let input = "let y: int = true";
+        let mut pairs = GrammarParser::parse(Rule::program, input).unwrap();
+        let program = parse_program(pairs.next().unwrap().into_inner()).unwrap();
+        let errors = check_program(&[program], false).unwrap_err();
+        let result = Diagnostic::from(&errors[0]);
+
+        assert_eq!(result.code, "T0001");
+        assert!(result.render(input).contains("  |              ^^^^\n"));
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let diagnostic = Diagnostic::new("R0014", String::from("Thrown: 1")).with_note("a note");
+
+        assert_eq!(
+            diagnostic.render(""),
+            "error[R0014]: Thrown: 1\n = note: a note"
+        );
+    }
+
+    #[test]
+    fn test_render_stack_trace() {
+        let e = InterpErrors::Traced(
+            vec![Frame {
+                function: String::from("f"),
+                file: Some(PathBuf::from("main.twig")),
+                span: Some(Span {
+                    start: 4,
+                    end: 7,
+                    line: 1,
+                    column: 5,
+                }),
+            }],
+            Box::new(InterpErrors::Context(
+                String::from("the left operand of `+`"),
+                Box::new(InterpErrors::VariableDoesNotExist {
+                    name: String::from("x"),
+                    suggestion: None,
+                }),
+            )),
+        );
+        let result = Diagnostic::from(&e);
+
+        assert_eq!(
+            result.notes,
+            [
+                "while evaluating the left operand of `+`",
+                "in `f` called from line 1, column 5 of main.twig"
+            ]
+        );
+    }
+}
@@ -1,359 +1,3283 @@
 use core::fmt;
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::twig_builtins::{Builtin, call_builtin, call_builtin_method};
+use crate::twig_modules::{Module, ModuleLoader, import};
+use crate::twig_parser::{
+    Accessor, Annotated, BooleanOperator, ExprKind, Expression, MathOperator, Pattern, PatternKind,
+    Span,
+};
+
+/// How many calls can be running at once before a program is stopped with a stack overflow.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// How close to the end of the stack evaluation can get before it's moved onto a new piece of
+/// stack, and how big a piece that is.
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_GROWTH: usize = 4 * 1024 * 1024;
 
-use crate::twig_parser::{BooleanOperator, Expression, MathOperator};
+#[derive(Debug, Clone, PartialEq)]
+pub enum Types {
+    Integer(i32),
+    Boolean(bool),
+    String(String),
+    Nil,
+    List(Vec<Types>),
+    Record {
+        name: String,
+        fields: Vec<(String, Types)>,
+    },
+    StructType {
+        name: String,
+        fields: Vec<String>,
+    },
+    EnumValue {
+        enum_name: String,
+        variant: String,
+        payload: Vec<Types>,
+    },
+    EnumType {
+        name: String,
+        variants: Vec<(String, Vec<String>)>,
+    },
+    VariantConstructor {
+        enum_name: String,
+        variant: String,
+        arity: usize,
+    },
+    Function(Rc<Function>),
+    Builtin(Builtin),
+    Module(Rc<Module>),
+    Unit,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expression,
+    /// The file the function was declared in, or `None` for the REPL.
+    pub module: Option<PathBuf>,
+}
+
+/// A call that was still running when an error happened.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    pub function: String,
+    /// The file the call was made from, or `None` for the REPL.
+    pub file: Option<PathBuf>,
+    /// Where the call was made, when the program was parsed with spans.
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum InterpErrors {
+    /// Values of two different types were compared, named by their types.
+    MismatchedTypeError(String, String),
+    /// A value of the wrong type was used, along with what was expected of it.
+    InvalidTypeError(String),
+    /// The name that was used, along with the closest name that exists, if any is close.
+    VariableDoesNotExist {
+        name: String,
+        suggestion: Option<String>,
+    },
+    IndexOutOfBounds {
+        index: i32,
+        len: usize,
+    },
+    FieldDoesNotExist(String),
+    MissingFieldError(String),
+    WrongArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+    /// The type of the value that was called.
+    NotCallableError(String),
+    /// The variant as written, like `Shape::Hexagon`.
+    VariantDoesNotExist(String),
+    /// The variants the match leaves out.
+    NonExhaustiveMatch(Vec<String>),
+    /// The value no arm matched.
+    NoMatchingArm(Types),
+    /// The type of the receiver and the method.
+    MethodDoesNotExist(String, String),
+    DivisionByZero,
+    /// The operation whose result doesn't fit in an integer, like `2147483647 + 1`.
+    IntegerOverflow(String),
+    Thrown(Types),
+    ModuleNotFound(String),
+    ModuleParseError(String),
+    ModuleTypeError(String),
+    CyclicImport(Vec<String>),
+    AssignToConstant(String),
+    /// More than `MAX_CALL_DEPTH` calls were running at once.
+    StackOverflow,
+    /// Something that can't happen unless the parser, resolver, compiler and VM disagree, like
+    /// the VM finding its stack empty. It says what went wrong.
+    Internal(String),
+    /// Not an error, but unwinds like one from a `return` or `?` up to the enclosing call.
+    Return(Types),
+    /// An error along with the innermost expression it came from.
+    At(Span, Box<InterpErrors>),
+    /// An error along with what was being evaluated when it happened, like "the left operand of
+    /// `+`".
+    Context(String, Box<InterpErrors>),
+    /// An error along with the calls that were running when it happened, outermost first.
+    Traced(Vec<Frame>, Box<InterpErrors>),
+}
+
+/// The variables of one scope, in slots numbered in the order they were first declared, along
+/// with which of them were declared with `const` and so can't be declared again in it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Scope {
+    slots: Vec<Types>,
+    names: BTreeMap<String, usize>,
+    constants: BTreeSet<String>,
+}
+
+impl Scope {
+    pub(crate) fn new() -> Scope {
+        Scope::default()
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&Types> {
+        self.names.get(name).map(|&slot| &self.slots[slot])
+    }
+
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.keys().map(String::as_str)
+    }
+
+    fn insert(&mut self, name: String, value: Types, constant: bool) -> Result<(), InterpErrors> {
+        if self.constants.contains(&name) {
+            return Err(InterpErrors::AssignToConstant(name));
+        }
+        if constant {
+            self.constants.insert(name.clone());
+        }
+        // Declaring a name again replaces it in the slot it already has.
+        match self.names.get(&name) {
+            Some(&slot) => self.slots[slot] = value,
+            None => {
+                self.names.insert(name, self.slots.len());
+                self.slots.push(value);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<(String, Types)> for Scope {
+    fn from_iter<I: IntoIterator<Item = (String, Types)>>(iter: I) -> Scope {
+        let mut scope = Scope::new();
+        for (name, value) in iter {
+            // Only constants can fail to be declared.
+            let _ = scope.insert(name, value, false);
+        }
+        scope
+    }
+}
+pub(crate) type Methods = BTreeMap<String, BTreeMap<String, Rc<Function>>>;
+
+/// Variables are looked up from the innermost local scope outwards and then in the globals of the
+/// file being run. Methods from `impl` blocks are kept per type name.
+///
+/// Once a file has been imported its globals are shared with the module cache, so they live
+/// behind an `Rc`. Functions always run against the globals of the file they were declared in.
+#[derive(Debug, Clone)]
+pub struct Env {
+    globals: Rc<Scope>,
+    scopes: Vec<Scope>,
+    methods: Methods,
+    exports: Vec<String>,
+    module: Option<PathBuf>,
+    /// The file and globals that were current before each call into another file's function.
+    homes: Vec<(Option<PathBuf>, Rc<Scope>)>,
+    /// The calls currently running, outermost first.
+    frames: Vec<Frame>,
+    /// The innermost expression being evaluated, which is where a call is made from.
+    site: Option<Span>,
+    loader: Rc<RefCell<ModuleLoader>>,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env {
+            globals: Rc::new(Scope::new()),
+            scopes: Vec::new(),
+            methods: Methods::new(),
+            exports: Vec::new(),
+            module: None,
+            homes: Vec::new(),
+            frames: Vec::new(),
+            site: None,
+            loader: Rc::new(RefCell::new(ModuleLoader::default())),
+        }
+    }
+
+    pub(crate) fn for_module(path: PathBuf, loader: Rc<RefCell<ModuleLoader>>) -> Env {
+        Env {
+            module: Some(path),
+            loader,
+            ..Env::new()
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Types> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    /// A local variable by the slot the resolver gave it, or `None` if the scopes aren't laid out
+    /// the way it expected.
+    pub(crate) fn local(&self, depth: usize, slot: usize) -> Option<&Types> {
+        let scope = self.scopes.len().checked_sub(depth + 1)?;
+        self.scopes[scope].slots.get(slot)
+    }
+
+    /// The names of the global variables declared so far.
+    pub(crate) fn global_names(&self) -> impl Iterator<Item = &str> {
+        self.globals.names()
+    }
+
+    /// Declares a variable in the innermost scope, failing if that scope already has a constant
+    /// with the same name.
+    pub fn insert(&mut self, name: String, value: Types) -> Result<(), InterpErrors> {
+        self.declare(name, value, false)
+    }
+
+    pub fn insert_constant(&mut self, name: String, value: Types) -> Result<(), InterpErrors> {
+        self.declare(name, value, true)
+    }
+
+    pub(crate) fn declare(
+        &mut self,
+        name: String,
+        value: Types,
+        constant: bool,
+    ) -> Result<(), InterpErrors> {
+        match self.scopes.last_mut() {
+            Some(scope) => scope.insert(name, value, constant),
+            None => Rc::make_mut(&mut self.globals).insert(name, value, constant),
+        }
+    }
+
+    /// The error for a name that isn't defined, suggesting the closest one that is, from the
+    /// variables in scope and the builtins.
+    pub fn undefined(&self, name: &str) -> InterpErrors {
+        let mut names: Vec<&str> = Builtin::ALL.iter().map(Builtin::name).collect();
+        names.extend(
+            self.scopes
+                .iter()
+                .chain([&*self.globals])
+                .flat_map(Scope::names),
+        );
+        InterpErrors::VariableDoesNotExist {
+            name: String::from(name),
+            suggestion: closest(name, names.into_iter()).map(String::from),
+        }
+    }
+
+    pub fn get_method(&self, type_name: &str, method: &str) -> Option<Rc<Function>> {
+        self.methods.get(type_name)?.get(method).cloned()
+    }
+
+    pub(crate) fn add_method(&mut self, type_name: String, function: Rc<Function>) {
+        self.methods
+            .entry(type_name)
+            .or_default()
+            .insert(function.name.clone(), function);
+    }
+
+    pub(crate) fn merge_methods(&mut self, methods: &Methods) {
+        for (type_name, functions) in methods {
+            let existing = self.methods.entry(type_name.clone()).or_default();
+            for (name, function) in functions {
+                existing
+                    .entry(name.clone())
+                    .or_insert_with(|| function.clone());
+            }
+        }
+    }
+
+    pub(crate) fn module(&self) -> Option<&PathBuf> {
+        self.module.as_ref()
+    }
+
+    pub(crate) fn loader(&self) -> Rc<RefCell<ModuleLoader>> {
+        self.loader.clone()
+    }
+
+    /// Hands back everything an importer needs once a file has finished running: its globals,
+    /// methods, and the values of the names it exported.
+    pub(crate) fn into_module_parts(self) -> (Rc<Scope>, Methods, BTreeMap<String, Types>) {
+        let exports = self
+            .exports
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.globals.get(name)?.clone())))
+            .collect();
+        (self.globals, self.methods, exports)
+    }
+
+    pub(crate) fn export(&mut self, name: String) {
+        self.exports.push(name);
+    }
+
+    pub(crate) fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub(crate) fn site(&self) -> Option<Span> {
+        self.site
+    }
+
+    pub(crate) fn push_scope(&mut self, scope: Scope) {
+        self.scopes.push(scope);
+    }
+
+    pub(crate) fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// The number of local scopes, which `truncate_scopes` can go back to after an error leaves
+    /// some of them behind.
+    pub(crate) fn scope_depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    pub(crate) fn truncate_scopes(&mut self, depth: usize) {
+        self.scopes.truncate(depth);
+    }
+
+    /// Functions only see globals and their own arguments, so the caller's local scopes are set
+    /// aside for the duration of the call and handed back to be restored afterwards.
+    fn enter_function(&mut self, scope: Scope) -> Vec<Scope> {
+        std::mem::replace(&mut self.scopes, vec![scope])
+    }
+
+    fn leave_function(&mut self, caller: Vec<Scope>) {
+        self.scopes = caller;
+    }
+
+    /// Switches to the globals of the file a function was declared in, returning whether
+    /// `leave_home` needs to switch back afterwards.
+    fn enter_home(&mut self, home: &Option<PathBuf>) -> Result<bool, InterpErrors> {
+        if *home == self.module {
+            return Ok(false);
+        }
+
+        let globals = match self.homes.iter().rev().find(|(module, _)| module == home) {
+            Some((_, globals)) => Some(globals.clone()),
+            None => home
+                .as_ref()
+                .and_then(|path| self.loader.borrow().globals(path)),
+        };
+        let Some(globals) = globals else {
+            let path = home.as_ref().map(|path| path.display().to_string());
+            return Err(InterpErrors::ModuleNotFound(path.unwrap_or_default()));
+        };
+
+        let globals = std::mem::replace(&mut self.globals, globals);
+        let module = std::mem::replace(&mut self.module, home.clone());
+        self.homes.push((module, globals));
+        Ok(true)
+    }
+
+    fn leave_home(&mut self) {
+        if let Some((module, globals)) = self.homes.pop() {
+            self.module = module;
+            self.globals = globals;
+        }
+    }
+
+    /// Starts a call to `function` made from `site`. The call is noted for stack traces, the
+    /// globals of the file the function was declared in are switched to, and the function gets a
+    /// scope of its own holding its arguments.
+    pub(crate) fn enter_call(
+        &mut self,
+        function: &Function,
+        args: Vec<Types>,
+        site: Option<Span>,
+    ) -> Result<Caller, InterpErrors> {
+        if args.len() != function.params.len() {
+            return Err(InterpErrors::WrongArgumentCount {
+                expected: function.params.len(),
+                found: args.len(),
+            });
+        }
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(InterpErrors::StackOverflow);
+        }
+
+        let scope = function.params.iter().cloned().zip(args).collect();
+
+        // The call is made from the caller's file, so it's noted before switching to the callee's.
+        let frame = Frame {
+            function: function.name.clone(),
+            file: self.module.clone(),
+            span: site,
+        };
+        let switched = self.enter_home(&function.module)?;
+        self.frames.push(frame);
+        let scopes = self.enter_function(scope);
+        Ok(Caller { scopes, switched })
+    }
+
+    /// Ends a call started with `enter_call`, turning a `return` into the value of the call.
+    pub(crate) fn leave_call(
+        &mut self,
+        caller: Caller,
+        mut result: Result<Types, InterpErrors>,
+    ) -> Result<Types, InterpErrors> {
+        self.leave_function(caller.scopes);
+        self.frames.pop();
+        if caller.switched {
+            self.leave_home();
+            // A span in another file would be read against the wrong source, so the error is
+            // reported at the call instead.
+            result = result.map_err(InterpErrors::without_span);
+        }
+
+        match result {
+            Err(InterpErrors::Return(value)) => Ok(value),
+            result => result,
+        }
+    }
+}
+
+/// The caller's side of a call, which `Env::leave_call` goes back to once it's over.
+#[derive(Debug)]
+pub(crate) struct Caller {
+    scopes: Vec<Scope>,
+    switched: bool,
+}
+
+impl fmt::Display for InterpErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpErrors::MismatchedTypeError(lhs, rhs) => {
+                write!(f, "Mismatched types: cannot compare {} with {}", lhs, rhs)
+            }
+            InterpErrors::InvalidTypeError(e) => write!(f, "Invalid type: {}", e),
+            InterpErrors::VariableDoesNotExist { name, suggestion } => {
+                write!(f, "Undefined variable `{}`", name)?;
+                match suggestion {
+                    Some(suggestion) => write!(f, "; did you mean `{}`?", suggestion),
+                    None => Ok(()),
+                }
+            }
+            InterpErrors::IndexOutOfBounds { index, len } => write!(
+                f,
+                "Index out of bounds: {} is past the end of a list of length {}",
+                index, len
+            ),
+            InterpErrors::FieldDoesNotExist(field) => {
+                write!(f, "Field does not exist: `{}`", field)
+            }
+            InterpErrors::MissingFieldError(field) => write!(f, "Missing field: `{}`", field),
+            InterpErrors::WrongArgumentCount { expected, found } => write!(
+                f,
+                "Wrong number of arguments: expected {}, found {}",
+                expected, found
+            ),
+            InterpErrors::NotCallableError(type_name) => {
+                write!(f, "Value is not callable: {} is not a function", type_name)
+            }
+            InterpErrors::VariantDoesNotExist(variant) => {
+                write!(f, "Variant does not exist: `{}`", variant)
+            }
+            InterpErrors::NonExhaustiveMatch(missing) => write!(
+                f,
+                "Match does not cover every variant: missing `{}`",
+                missing.join("`, `")
+            ),
+            InterpErrors::NoMatchingArm(value) => {
+                write!(f, "No match arm matched the value {}", value)
+            }
+            InterpErrors::MethodDoesNotExist(type_name, method) => {
+                write!(
+                    f,
+                    "Method does not exist: {} has no method `{}`",
+                    type_name, method
+                )
+            }
+            InterpErrors::DivisionByZero => write!(f, "Division by zero"),
+            InterpErrors::IntegerOverflow(operation) => {
+                write!(
+                    f,
+                    "Integer overflow: `{}` doesn't fit in an integer",
+                    operation
+                )
+            }
+            InterpErrors::Thrown(value) => write!(f, "Uncaught exception: {}", value),
+            InterpErrors::ModuleNotFound(path) => write!(f, "Module not found: {}", path),
+            InterpErrors::ModuleParseError(e) => write!(f, "Module Parse Error: {}", e),
+            InterpErrors::ModuleTypeError(e) => write!(f, "Module Type Error: {}", e),
+            InterpErrors::CyclicImport(chain) => write!(f, "Cyclic import: {}", chain.join(" -> ")),
+            InterpErrors::AssignToConstant(name) => {
+                write!(f, "Cannot redeclare constant: {}", name)
+            }
+            InterpErrors::StackOverflow => write!(
+                f,
+                "Stack overflow: more than {} calls were running at once",
+                MAX_CALL_DEPTH
+            ),
+            InterpErrors::Internal(what) => write!(f, "Internal error: {}", what),
+            InterpErrors::Return(value) => write!(f, "Returned: {}", value),
+            InterpErrors::At(span, e) => write!(f, "{} at {}", e, span),
+            InterpErrors::Context(context, e) => write!(f, "{} (while evaluating {})", e, context),
+            InterpErrors::Traced(frames, e) => {
+                write!(f, "{}", e)?;
+                for frame in frames.iter().rev() {
+                    write!(f, "\n  in {}", frame)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`", self.function)?;
+        if let Some(span) = self.span {
+            write!(f, " called from {}", span)?;
+        }
+        match &self.file {
+            Some(file) => write!(f, " of {}", file.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl InterpErrors {
+    /// The `kind` field of the error value a `catch` block receives.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InterpErrors::MismatchedTypeError(..) => "MismatchedTypeError",
+            InterpErrors::InvalidTypeError(_) => "InvalidTypeError",
+            InterpErrors::VariableDoesNotExist { .. } => "VariableDoesNotExist",
+            InterpErrors::IndexOutOfBounds { .. } => "IndexOutOfBounds",
+            InterpErrors::FieldDoesNotExist(_) => "FieldDoesNotExist",
+            InterpErrors::MissingFieldError(_) => "MissingFieldError",
+            InterpErrors::WrongArgumentCount { .. } => "WrongArgumentCount",
+            InterpErrors::NotCallableError(_) => "NotCallableError",
+            InterpErrors::VariantDoesNotExist(_) => "VariantDoesNotExist",
+            InterpErrors::NonExhaustiveMatch(_) => "NonExhaustiveMatch",
+            InterpErrors::NoMatchingArm(_) => "NoMatchingArm",
+            InterpErrors::MethodDoesNotExist(..) => "MethodDoesNotExist",
+            InterpErrors::DivisionByZero => "DivisionByZero",
+            InterpErrors::IntegerOverflow(_) => "IntegerOverflow",
+            InterpErrors::Thrown(_) => "Thrown",
+            InterpErrors::ModuleNotFound(_) => "ModuleNotFound",
+            InterpErrors::ModuleParseError(_) => "ModuleParseError",
+            InterpErrors::ModuleTypeError(_) => "ModuleTypeError",
+            InterpErrors::CyclicImport(_) => "CyclicImport",
+            InterpErrors::AssignToConstant(_) => "AssignToConstant",
+            InterpErrors::StackOverflow => "StackOverflow",
+            InterpErrors::Internal(_) => "Internal",
+            InterpErrors::Return(_) => "Return",
+            InterpErrors::At(_, e) | InterpErrors::Context(_, e) | InterpErrors::Traced(_, e) => {
+                e.kind()
+            }
+        }
+    }
+
+    /// Records where the error came from, unless it already knows. Returns pass through as they
+    /// are, since they aren't errors.
+    pub fn at(self, span: Span) -> InterpErrors {
+        match self {
+            InterpErrors::Return(_) => self,
+            e if e.span().is_some() => e,
+            e => InterpErrors::At(span, Box::new(e)),
+        }
+    }
+
+    /// Records what was being evaluated when the error happened, keeping the error itself as it
+    /// was.
+    pub fn context(self, context: String) -> InterpErrors {
+        match self {
+            InterpErrors::Return(_) => self,
+            e => InterpErrors::Context(context, Box::new(e)),
+        }
+    }
+
+    /// Records the calls that were running when the error happened, unless it already knows.
+    pub(crate) fn traced(self, frames: &[Frame]) -> InterpErrors {
+        match self {
+            InterpErrors::Return(_) => self,
+            e if frames.is_empty() || e.trace().is_some() => e,
+            e => InterpErrors::Traced(frames.to_vec(), Box::new(e)),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            InterpErrors::At(span, _) => Some(*span),
+            InterpErrors::Context(_, e) | InterpErrors::Traced(_, e) => e.span(),
+            _ => None,
+        }
+    }
+
+    /// The calls that were running when the error happened, outermost first.
+    pub fn trace(&self) -> Option<&[Frame]> {
+        match self {
+            InterpErrors::Traced(frames, _) => Some(frames),
+            InterpErrors::At(_, e) | InterpErrors::Context(_, e) => e.trace(),
+            _ => None,
+        }
+    }
+
+    /// The error without where it came from.
+    pub fn without_span(self) -> InterpErrors {
+        match self {
+            InterpErrors::At(_, e) => *e,
+            InterpErrors::Context(context, e) => {
+                InterpErrors::Context(context, Box::new(e.without_span()))
+            }
+            InterpErrors::Traced(frames, e) => {
+                InterpErrors::Traced(frames, Box::new(e.without_span()))
+            }
+            e => e,
+        }
+    }
+
+    /// The value a `catch` block binds. Thrown values are caught as they are, while runtime errors
+    /// become an `Error` record with a `kind` and `message`.
+    pub fn into_value(self) -> Types {
+        match self {
+            InterpErrors::Thrown(value) => value,
+            InterpErrors::At(_, e) | InterpErrors::Context(_, e) | InterpErrors::Traced(_, e) => {
+                e.into_value()
+            }
+            e => Types::Record {
+                name: String::from("Error"),
+                fields: vec![
+                    (String::from("kind"), Types::String(String::from(e.kind()))),
+                    (String::from("message"), Types::String(e.to_string())),
+                ],
+            },
+        }
+    }
+}
+
+impl fmt::Display for Types {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Types::Integer(i) => write!(f, "{}", i),
+            Types::Boolean(b) => write!(f, "{}", b),
+            Types::String(s) => write!(f, "\"{}\"", s),
+            Types::Nil => write!(f, "nil"),
+            Types::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Types::Record { name, fields } => {
+                write!(f, "{} {{", name)?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    let separator = if i > 0 { "," } else { "" };
+                    write!(f, "{} {}: {}", separator, field, value)?;
+                }
+                write!(f, " }}")
+            }
+            Types::StructType { name, fields } => {
+                write!(f, "struct {} {{ {} }}", name, fields.join(", "))
+            }
+            Types::EnumValue {
+                enum_name,
+                variant,
+                payload,
+            } => {
+                // Results are written the same way they are built, without the enum name.
+                match enum_name.as_str() {
+                    "Result" => write!(f, "{}", variant)?,
+                    _ => write!(f, "{}::{}", enum_name, variant)?,
+                }
+                if !payload.is_empty() {
+                    let payload: Vec<String> = payload.iter().map(|v| v.to_string()).collect();
+                    write!(f, "({})", payload.join(", "))?;
+                }
+                Ok(())
+            }
+            Types::EnumType { name, variants } => {
+                let variants: Vec<String> = variants
+                    .iter()
+                    .map(|(variant, fields)| match fields.is_empty() {
+                        true => variant.clone(),
+                        false => format!("{}({})", variant, fields.join(", ")),
+                    })
+                    .collect();
+                write!(f, "enum {} {{ {} }}", name, variants.join(", "))
+            }
+            Types::VariantConstructor {
+                enum_name, variant, ..
+            } => write!(f, "<constructor {}::{}>", enum_name, variant),
+            Types::Function(function) => {
+                write!(f, "<fn {}({})>", function.name, function.params.join(", "))
+            }
+            Types::Builtin(builtin) => write!(f, "<builtin {}>", builtin.name()),
+            Types::Module(module) => write!(f, "<module {}>", module.path.display()),
+            Types::Unit => write!(f, "()"),
+        }
+    }
+}
+
+impl Types {
+    /// The name `type_of` reports for a value. Records report the name of their struct.
+    pub fn type_name(&self) -> String {
+        match self {
+            Types::Integer(_) => String::from("int"),
+            Types::Boolean(_) => String::from("bool"),
+            Types::String(_) => String::from("string"),
+            Types::Nil => String::from("nil"),
+            Types::List(_) => String::from("list"),
+            Types::Record { name, .. } => name.clone(),
+            Types::StructType { .. } => String::from("struct"),
+            Types::EnumValue { enum_name, .. } => enum_name.clone(),
+            Types::EnumType { .. } => String::from("enum"),
+            Types::VariantConstructor { .. } => String::from("constructor"),
+            Types::Function(_) => String::from("function"),
+            Types::Builtin(_) => String::from("builtin"),
+            Types::Module(_) => String::from("module"),
+            Types::Unit => String::from("unit"),
+        }
+    }
+}
+
+impl std::error::Error for InterpErrors {}
+
+fn get_variants(enum_name: &str, env: &Env) -> Result<Vec<(String, Vec<String>)>, InterpErrors> {
+    match env.get(enum_name) {
+        Some(Types::EnumType { variants, .. }) => Ok(variants.clone()),
+        Some(value) => Err(not_a_type(enum_name, "an enum", value)),
+        // `Ok` and `Err` values belong to a built-in `Result` enum.
+        None if enum_name == "Result" => Ok(vec![
+            (String::from("Ok"), vec![String::from("value")]),
+            (String::from("Err"), vec![String::from("error")]),
+        ]),
+        None => Err(env.undefined(enum_name)),
+    }
+}
+
+/// The number of characters that have to be inserted, removed or swapped to turn one string into
+/// the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The name closest to `name`, as long as it's within a third of its length of it. Ties go to the
+/// first name in alphabetical order.
+pub(crate) fn closest<'a>(name: &str, names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = name.chars().count().max(3) / 3;
+    names
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The error for a name used as a struct or enum that holds something else.
+fn not_a_type(name: &str, expected: &str, value: &Types) -> InterpErrors {
+    InterpErrors::InvalidTypeError(format!(
+        "`{}` should be {}, but it's a {}",
+        name,
+        expected,
+        value.type_name()
+    ))
+}
+
+fn is_irrefutable(pattern: &Pattern) -> bool {
+    match pattern.kind {
+        PatternKind::Wildcard | PatternKind::Binding(_) => true,
+        PatternKind::Literal(_) | PatternKind::Variant { .. } => false,
+    }
+}
+
+/// Checks that a match on a value of an enum only names variants that exist and mentions every
+/// one of them unless it has a catch-all arm. This is only
+/// "exhaustive-ish": a variant counts as covered when an arm matches all of its payloads, but
+/// nested enums inside a payload are not checked.
+pub(crate) fn check_exhaustive<'a>(
+    value: &Types,
+    mut patterns: impl Iterator<Item = &'a Pattern> + Clone,
+    env: &Env,
+) -> Result<(), InterpErrors> {
+    let Types::EnumValue { enum_name, .. } = value else {
+        return Ok(());
+    };
+
+    let variants = get_variants(enum_name, env)?;
+    let mut covered = Vec::new();
+    for pattern in patterns.clone() {
+        if let PatternKind::Variant {
+            enum_name: name,
+            variant,
+            fields,
+        } = &pattern.kind
+        {
+            if name == enum_name && !variants.iter().any(|(v, _)| v == variant) {
+                let variant = format!("{}::{}", name, variant);
+                return Err(InterpErrors::VariantDoesNotExist(variant));
+            }
+            if name == enum_name && fields.iter().all(is_irrefutable) {
+                covered.push(variant);
+            }
+        }
+    }
+
+    let has_catch_all = patterns.any(is_irrefutable);
+    let missing: Vec<String> = variants
+        .into_iter()
+        .filter(|(variant, _)| !covered.contains(&variant))
+        .map(|(variant, _)| format!("{}::{}", enum_name, variant))
+        .collect();
+    match has_catch_all || missing.is_empty() {
+        true => Ok(()),
+        false => Err(InterpErrors::NonExhaustiveMatch(missing)),
+    }
+}
+
+/// Tests a value against a pattern, collecting the variables it binds.
+fn match_pattern(
+    pattern: &Pattern,
+    value: Types,
+    env: &mut Env,
+    bindings: &mut Vec<(String, Types)>,
+) -> Result<bool, InterpErrors> {
+    match &pattern.kind {
+        PatternKind::Wildcard => Ok(true),
+        PatternKind::Binding(name) => {
+            bindings.push((name.clone(), value));
+            Ok(true)
+        }
+        PatternKind::Literal(literal) => Ok(interp_program(literal, env)? == value),
+        PatternKind::Variant {
+            enum_name,
+            variant,
+            fields,
+        } => {
+            let Types::EnumValue {
+                enum_name: value_enum,
+                variant: value_variant,
+                payload,
+            } = value
+            else {
+                return Ok(false);
+            };
+            if *enum_name != value_enum || *variant != value_variant {
+                return Ok(false);
+            }
+            if fields.len() != payload.len() {
+                return Err(InterpErrors::WrongArgumentCount {
+                    expected: payload.len(),
+                    found: fields.len(),
+                });
+            }
+
+            for (field, value) in fields.iter().zip(payload) {
+                if !match_pattern(field, value, env, bindings)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Evaluates an expression in a new scope holding the given variables.
+fn interp_with_bindings(
+    expr: &Expression,
+    bindings: Vec<(String, Types)>,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    env.push_scope(bindings.into_iter().collect());
+    let result = interp_program(expr, env);
+    env.pop_scope();
+
+    result
+}
+
+fn call_function(
+    function: &Function,
+    args: Vec<Types>,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let caller = env.enter_call(function, args, env.site)?;
+    let result = interp_program(&function.body, env);
+    env.leave_call(caller, result)
+}
+
+/// What a method call turns out to be once its receiver is known.
+pub(crate) enum Method {
+    /// A call to a function or other callable value, with the arguments it gets.
+    Call(Types, Vec<Types>),
+    /// A builtin method, which has already run.
+    Done(Types),
+}
+
+pub(crate) fn find_method(
+    receiver: Types,
+    method: String,
+    mut args: Vec<Types>,
+    env: &Env,
+) -> Result<Method, InterpErrors> {
+    // Calling a method on a struct or enum type itself reaches its associated functions, which
+    // don't take `self`.
+    if let Types::StructType { name, .. } | Types::EnumType { name, .. } = &receiver {
+        return match env.get_method(name, &method) {
+            Some(function) => Ok(Method::Call(Types::Function(function), args)),
+            None => Err(InterpErrors::MethodDoesNotExist(name.clone(), method)),
+        };
+    }
+
+    // Calling a method on a module calls the function it exported under that name.
+    if let Types::Module(module) = &receiver {
+        return match module.exports.get(&method) {
+            Some(callee) => Ok(Method::Call(callee.clone(), args)),
+            None => Err(InterpErrors::FieldDoesNotExist(method)),
+        };
+    }
+
+    if let Some(function) = env.get_method(&receiver.type_name(), &method) {
+        args.insert(0, receiver);
+        return Ok(Method::Call(Types::Function(function), args));
+    }
+
+    // A record field holding something callable can be called like a method.
+    if let Types::Record { fields, .. } = &receiver
+        && let Some((_, callee)) = fields.iter().find(|(name, _)| *name == method)
+    {
+        return Ok(Method::Call(callee.clone(), args));
+    }
+
+    call_builtin_method(receiver, &method, args).map(Method::Done)
+}
+
+fn interp_method_call(
+    receiver: Types,
+    method: String,
+    args: Vec<Types>,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    match find_method(receiver, method, args, env)? {
+        Method::Call(callee, args) => call_value(callee, args, env),
+        Method::Done(value) => Ok(value),
+    }
+}
+
+pub(crate) fn call_value(
+    callee: Types,
+    args: Vec<Types>,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    match callee {
+        Types::Function(function) => call_function(&function, args, env),
+        Types::Builtin(builtin) => call_builtin(builtin, args),
+        Types::VariantConstructor {
+            enum_name,
+            variant,
+            arity,
+        } => match args.len() == arity {
+            true => Ok(Types::EnumValue {
+                enum_name,
+                variant,
+                payload: args,
+            }),
+            false => Err(InterpErrors::WrongArgumentCount {
+                expected: arity,
+                found: args.len(),
+            }),
+        },
+        callee => Err(InterpErrors::NotCallableError(callee.type_name())),
+    }
+}
+
+/// What an error from one of the operands of a binary operator is noted with.
+pub(crate) fn operand(side: &str, op: &dyn fmt::Display) -> String {
+    format!("the {} operand of `{}`", side, op)
+}
+
+/// Evaluates both operands of a binary operator in order, noting which one failed.
+fn operands(
+    lhs: &Expression,
+    rhs: &Expression,
+    op: &dyn fmt::Display,
+    env: &mut Env,
+) -> Result<(Types, Types), InterpErrors> {
+    let left = interp_program(lhs, env).map_err(|e| e.context(operand("left", op)))?;
+    let right = interp_program(rhs, env).map_err(|e| e.context(operand("right", op)))?;
+    Ok((left, right))
+}
+
+pub(crate) fn math(op: MathOperator, left: Types, right: Types) -> Result<Types, InterpErrors> {
+    let (left, right) = match (left, right) {
+        (Types::Integer(left), Types::Integer(right)) => (left, right),
+        (left, right) => {
+            return Err(InterpErrors::InvalidTypeError(format!(
+                "`{}` needs two integers, found {} and {}",
+                op,
+                left.type_name(),
+                right.type_name()
+            )));
+        }
+    };
+
+    let result = match op {
+        MathOperator::Add => left.checked_add(right),
+        MathOperator::Subtract => left.checked_sub(right),
+        MathOperator::Multiply => left.checked_mul(right),
+        MathOperator::Divide if right == 0 => return Err(InterpErrors::DivisionByZero),
+        MathOperator::Divide => left.checked_div(right),
+    };
+
+    match result {
+        Some(result) => Ok(Types::Integer(result)),
+        None => Err(InterpErrors::IntegerOverflow(format!(
+            "{} {} {}",
+            left, op, right
+        ))),
+    }
+}
+
+pub(crate) fn compare(op: BooleanOperator, l: Types, r: Types) -> Result<Types, InterpErrors> {
+    let cannot_order = |value: &Types| {
+        InterpErrors::InvalidTypeError(format!(
+            "`{}` cannot order values of type {}",
+            op,
+            value.type_name()
+        ))
+    };
+
+    let (left, right) = match (l, r) {
+        // nil is only ever equal to itself, so equality against it never mismatches.
+        (l @ Types::Nil, r) | (l, r @ Types::Nil) => match op {
+            BooleanOperator::Eqaul => return Ok(Types::Boolean(l == r)),
+            _ => return Err(cannot_order(&Types::Nil)),
+        },
+        (Types::Integer(i), Types::Integer(j)) => (i, j),
+        (Types::Boolean(i), Types::Boolean(j)) => (i as i32, j as i32),
+        // Everything else can only be compared for equality, which is structural: records of the
+        // same struct are equal when all of their fields are.
+        (l, r) if l.type_name() == r.type_name() => match op {
+            BooleanOperator::Eqaul => return Ok(Types::Boolean(l == r)),
+            _ => return Err(cannot_order(&l)),
+        },
+        (l, r) => {
+            return Err(InterpErrors::MismatchedTypeError(
+                l.type_name(),
+                r.type_name(),
+            ));
+        }
+    };
+
+    let result = match op {
+        BooleanOperator::Eqaul => left == right,
+        BooleanOperator::GreaterThan => left > right,
+        BooleanOperator::GreaterThanEqual => left >= right,
+        BooleanOperator::LessThan => left < right,
+        BooleanOperator::LessThanEqual => left <= right,
+    };
+
+    Ok(Types::Boolean(result))
+}
+
+pub(crate) fn get_index(
+    items: Vec<Types>,
+    index: Types,
+    optional: bool,
+) -> Result<Types, InterpErrors> {
+    let i = match index {
+        Types::Integer(i) => i,
+        index => {
+            return Err(InterpErrors::InvalidTypeError(format!(
+                "list indexes must be integers, found {}",
+                index.type_name()
+            )));
+        }
+    };
+
+    let len = items.len();
+    match usize::try_from(i)
+        .ok()
+        .and_then(|i| items.into_iter().nth(i))
+    {
+        Some(item) => Ok(item),
+        None if optional => Ok(Types::Nil),
+        None => Err(InterpErrors::IndexOutOfBounds { index: i, len }),
+    }
+}
+
+/// The error for indexing anything but a list, which is found before the index is evaluated.
+pub(crate) fn not_indexable(value: &Types) -> InterpErrors {
+    InterpErrors::InvalidTypeError(format!(
+        "only lists can be indexed, found {}",
+        value.type_name()
+    ))
+}
+
+pub(crate) fn get_field(value: Types, field: &str, optional: bool) -> Result<Types, InterpErrors> {
+    let found = match value {
+        Types::Module(module) => module.exports.get(field).cloned(),
+        Types::Record { fields, .. } => fields
+            .into_iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value),
+        value => {
+            return Err(InterpErrors::InvalidTypeError(format!(
+                "{} has no fields, so it can't have `{}`",
+                value.type_name(),
+                field
+            )));
+        }
+    };
+
+    match found {
+        Some(value) => Ok(value),
+        None if optional => Ok(Types::Nil),
+        None => Err(InterpErrors::FieldDoesNotExist(String::from(field))),
+    }
+}
+
+/// The value of a variable, or of the builtin with its name.
+pub(crate) fn lookup(name: &str, env: &Env) -> Result<Types, InterpErrors> {
+    match env.get(name) {
+        // Clone to get a copy of the value in env.
+        Some(value) => Ok(value.clone()),
+        None => match Builtin::from_name(name) {
+            Some(builtin) => Ok(Types::Builtin(builtin)),
+            None => Err(env.undefined(name)),
+        },
+    }
+}
+
+/// The fields of the struct a literal names, in the order they were declared.
+pub(crate) fn struct_fields(name: &str, env: &Env) -> Result<Vec<String>, InterpErrors> {
+    match env.get(name) {
+        Some(Types::StructType { fields, .. }) => Ok(fields.clone()),
+        Some(value) => Err(not_a_type(name, "a struct", value)),
+        None => Err(env.undefined(name)),
+    }
+}
+
+/// Builds a record from the fields of a literal, which are already known to be declared.
+pub(crate) fn build_record(
+    name: String,
+    declared: Vec<String>,
+    mut values: Vec<(String, Types)>,
+) -> Result<Types, InterpErrors> {
+    // Store fields in declaration order so structural equality doesn't depend on the order they
+    // were written in the literal.
+    let mut record = Vec::with_capacity(declared.len());
+    for field in declared {
+        let Some(position) = values.iter().position(|(name, _)| *name == field) else {
+            return Err(InterpErrors::MissingFieldError(field));
+        };
+        record.push(values.swap_remove(position));
+    }
+
+    Ok(Types::Record {
+        name,
+        fields: record,
+    })
+}
+
+pub(crate) fn propagate(value: Types) -> Result<Types, InterpErrors> {
+    match value {
+        Types::EnumValue {
+            enum_name,
+            variant,
+            mut payload,
+        } if enum_name == "Result" => match variant.as_str() {
+            "Ok" => Ok(payload.pop().unwrap_or(Types::Unit)),
+            _ => Err(InterpErrors::Return(Types::EnumValue {
+                enum_name,
+                variant,
+                payload,
+            })),
+        },
+        value => Err(InterpErrors::InvalidTypeError(format!(
+            "`?` needs a Result, found {}",
+            value.type_name()
+        ))),
+    }
+}
+
+/// The value of `Type::name`, which is either one of the enum's variants or an associated
+/// function from an `impl` block.
+pub(crate) fn get_variant(
+    enum_name: &str,
+    variant: &str,
+    env: &Env,
+) -> Result<Types, InterpErrors> {
+    // `Type::name` also reaches associated functions from an `impl` block.
+    if let Some(function) = env.get_method(enum_name, variant) {
+        return Ok(Types::Function(function));
+    }
+
+    let variants = get_variants(enum_name, env)?;
+    let Some((_, fields)) = variants.into_iter().find(|(name, _)| *name == variant) else {
+        let variant = format!("{}::{}", enum_name, variant);
+        return Err(InterpErrors::VariantDoesNotExist(variant));
+    };
+
+    // Variants without a payload are values on their own, the rest need to be called.
+    match fields.is_empty() {
+        true => Ok(Types::EnumValue {
+            enum_name: String::from(enum_name),
+            variant: String::from(variant),
+            payload: Vec::new(),
+        }),
+        false => Ok(Types::VariantConstructor {
+            enum_name: String::from(enum_name),
+            variant: String::from(variant),
+            arity: fields.len(),
+        }),
+    }
+}
+
+/// Checks that an `impl` block is for a struct or enum.
+pub(crate) fn check_impl_type(type_name: &str, env: &Env) -> Result<(), InterpErrors> {
+    match env.get(type_name) {
+        Some(Types::StructType { .. } | Types::EnumType { .. }) => Ok(()),
+        Some(value) => Err(not_a_type(type_name, "a struct or enum", value)),
+        None => Err(env.undefined(type_name)),
+    }
+}
+
+/// A method from an `impl` block, to be run in the file it's declared in.
+pub(crate) fn impl_function(
+    declaration: &Expression,
+    module: Option<PathBuf>,
+) -> Result<Function, InterpErrors> {
+    let ExprKind::FnDecl {
+        name, params, body, ..
+    } = &declaration.kind
+    else {
+        return Err(InterpErrors::Internal(String::from(
+            "an `impl` block holds something other than a function",
+        )));
+    };
+    Ok(Function {
+        name: name.clone(),
+        params: params.iter().map(|param| param.name.clone()).collect(),
+        body: (**body).clone(),
+        module,
+    })
+}
+
+/// The name an `export` declares.
+pub(crate) fn exported_name(declaration: &Expression) -> Result<String, InterpErrors> {
+    match &declaration.kind {
+        ExprKind::LetStmt { identifier, .. } => Ok(identifier.clone()),
+        ExprKind::FnDecl { name, .. }
+        | ExprKind::StructDecl { name, .. }
+        | ExprKind::EnumDecl { name, .. } => Ok(name.clone()),
+        _ => Err(InterpErrors::Internal(String::from(
+            "an `export` holds something other than a declaration",
+        ))),
+    }
+}
+
+pub fn interp_program(
+    expr: &Expression,
+    env: &mut Env,
+) -> std::result::Result<Types, InterpErrors> {
+    within(expr, env, interp_node)
+}
+
+/// Evaluates a node with `f`, noting an error coming out of it with the node's span.
+fn within<T>(
+    expr: &Expression,
+    env: &mut Env,
+    f: impl FnOnce(&ExprKind, &mut Env) -> Result<T, InterpErrors>,
+) -> Result<T, InterpErrors> {
+    // Every call in the program nests a few of these, so deep recursion would run out of stack
+    // long before `MAX_CALL_DEPTH` without growing it.
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || {
+        let Some(span) = expr.span else {
+            return f(&expr.kind, env);
+        };
+        let site = env.site.replace(span);
+        let result = f(&expr.kind, env);
+        env.site = site;
+        result.map_err(|e| e.at(span).traced(&env.frames))
+    })
+}
+
+/// Evaluates a node with its span already taken care of. Anything that needs more than a few
+/// temporaries is evaluated by a function of its own, so that this one's stack frame stays small:
+/// every call in a program goes through it a few times, which limits how deep recursion can go.
+fn interp_node(kind: &ExprKind, env: &mut Env) -> Result<Types, InterpErrors> {
+    match kind {
+        ExprKind::Integer(value) => Ok(Types::Integer(*value)),
+        ExprKind::Boolean(value) => Ok(Types::Boolean(*value)),
+        ExprKind::String(value) => Ok(Types::String(value.clone())),
+        ExprKind::Nil => Ok(Types::Nil),
+        ExprKind::List(items) => interp_list(items, env),
+        ExprKind::MathOp { lhs, op, rhs } => interp_math(lhs, *op, rhs, env),
+        ExprKind::BooleanOp { lhs, op, rhs } => interp_compare(lhs, *op, rhs, env),
+        ExprKind::Coalesce { lhs, rhs } => interp_coalesce(lhs, rhs, env),
+        ExprKind::Access { .. } | ExprKind::Call { .. } | ExprKind::MethodCall { .. } => {
+            Ok(interp_link(kind, env)?.unwrap_or(Types::Nil))
+        }
+        ExprKind::StructLiteral { name, fields } => interp_struct_literal(name, fields, env),
+        ExprKind::LetStmt {
+            identifier,
+            value,
+            constant,
+            ..
+        } => interp_let(identifier, value, *constant, env),
+        ExprKind::Block(stmts) => interp_block(stmts, env),
+        ExprKind::Throw(value) => interp_throw(value, env),
+        ExprKind::Return(value) => interp_return(value.as_deref(), env),
+        ExprKind::Propagate(value) => interp_propagate(value, env),
+        ExprKind::Try {
+            body,
+            catch,
+            finally,
+        } => interp_try(body, catch, finally, env),
+        ExprKind::FnDecl {
+            name, params, body, ..
+        } => interp_fn_decl(name, params, body, env),
+        ExprKind::ImplBlock { type_name, methods } => interp_impl(type_name, methods, env),
+        ExprKind::Import { path, alias } => interp_import(path, alias, env),
+        ExprKind::Export(declaration) => interp_export(declaration, env),
+        ExprKind::Variant { enum_name, variant } => get_variant(enum_name, variant, env),
+        ExprKind::Match { scrutinee, arms } => interp_match(scrutinee, arms, env),
+        ExprKind::EnumDecl { name, variants } => interp_enum_decl(name, variants, env),
+        ExprKind::StructDecl { name, fields, .. } => interp_struct_decl(name, fields, env),
+        ExprKind::Identifier(var) => lookup(var, env),
+        ExprKind::Local { name, depth, slot } => interp_local(name, *depth, *slot, env),
+    }
+}
+
+fn interp_local(name: &str, depth: usize, slot: usize, env: &Env) -> Result<Types, InterpErrors> {
+    env.local(depth, slot)
+        .cloned()
+        .ok_or_else(|| missing_local(name))
+}
+
+/// The error for a local variable that isn't in the slot the resolver gave it, which means the
+/// resolver and the engine running the program disagree about its scopes.
+pub(crate) fn missing_local(name: &str) -> InterpErrors {
+    InterpErrors::Internal(format!("`{}` isn't in the slot it was resolved to", name))
+}
+
+fn interp_math(
+    lhs: &Expression,
+    op: MathOperator,
+    rhs: &Expression,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let (left, right) = operands(lhs, rhs, &op, env)?;
+    math(op, left, right)
+}
+
+fn interp_compare(
+    lhs: &Expression,
+    op: BooleanOperator,
+    rhs: &Expression,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let (left, right) = operands(lhs, rhs, &op, env)?;
+    compare(op, left, right)
+}
+
+fn interp_coalesce(
+    lhs: &Expression,
+    rhs: &Expression,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    match interp_program(lhs, env)? {
+        Types::Nil => interp_program(rhs, env),
+        value => Ok(value),
+    }
+}
+
+fn interp_propagate(value: &Expression, env: &mut Env) -> Result<Types, InterpErrors> {
+    propagate(interp_program(value, env)?)
+}
+
+fn interp_throw(value: &Expression, env: &mut Env) -> Result<Types, InterpErrors> {
+    Err(InterpErrors::Thrown(interp_program(value, env)?))
+}
+
+fn interp_return(value: Option<&Expression>, env: &mut Env) -> Result<Types, InterpErrors> {
+    match value {
+        Some(value) => Err(InterpErrors::Return(interp_program(value, env)?)),
+        None => Err(InterpErrors::Return(Types::Unit)),
+    }
+}
+
+fn interp_fn_decl(
+    name: &str,
+    params: &[Annotated],
+    body: &Expression,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let function = Function {
+        name: String::from(name),
+        params: params.iter().map(|param| param.name.clone()).collect(),
+        body: body.clone(),
+        module: env.module.clone(),
+    };
+    env.insert(String::from(name), Types::Function(Rc::new(function)))?;
+    Ok(Types::Unit)
+}
+
+fn interp_import(path: &str, alias: &str, env: &mut Env) -> Result<Types, InterpErrors> {
+    let module = import(path, env)?;
+    env.insert(String::from(alias), Types::Module(module))?;
+    Ok(Types::Unit)
+}
+
+fn interp_enum_decl(
+    name: &str,
+    variants: &[(String, Vec<String>)],
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let enumeration = Types::EnumType {
+        name: String::from(name),
+        variants: variants.to_vec(),
+    };
+    env.insert(String::from(name), enumeration)?;
+    Ok(Types::Unit)
+}
+
+fn interp_struct_decl(
+    name: &str,
+    fields: &[Annotated],
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    // Field types are only read by the type checker.
+    let structure = Types::StructType {
+        name: String::from(name),
+        fields: fields.iter().map(|field| field.name.clone()).collect(),
+    };
+    env.insert(String::from(name), structure)?;
+    Ok(Types::Unit)
+}
+
+fn interp_list(items: &[Expression], env: &mut Env) -> Result<Types, InterpErrors> {
+    let mut values = Vec::with_capacity(items.len());
+    for item in items {
+        values.push(interp_program(item, env)?);
+    }
+    Ok(Types::List(values))
+}
+
+/// Evaluates one link of a chain of accesses and calls, like the `.c` of `a?.b.c`, or `None` when
+/// a `?.` earlier in the chain found `nil`, which skips the rest of the chain.
+fn interp_link(kind: &ExprKind, env: &mut Env) -> Result<Option<Types>, InterpErrors> {
+    match kind {
+        ExprKind::Access {
+            target,
+            accessor,
+            optional,
+        } => interp_access(target, accessor, *optional, env),
+        ExprKind::Call { callee, args } => interp_call(callee, args, env),
+        ExprKind::MethodCall {
+            receiver,
+            method,
+            args,
+            optional,
+        } => interp_method(receiver, method, args, *optional, env),
+        kind => interp_node(kind, env).map(Some),
+    }
+}
+
+fn interp_access(
+    target: &Expression,
+    accessor: &Accessor,
+    optional: bool,
+    env: &mut Env,
+) -> Result<Option<Types>, InterpErrors> {
+    let Some(value) = within(target, env, interp_link)? else {
+        return Ok(None);
+    };
+    if optional && value == Types::Nil {
+        return Ok(None);
+    }
+
+    let value = match (value, accessor) {
+        (Types::List(items), Accessor::Index(index)) => {
+            get_index(items, interp_program(index, env)?, optional)
+        }
+        (value, Accessor::Index(_)) => Err(not_indexable(&value)),
+        (value, Accessor::Field(field)) => get_field(value, field, optional),
+    };
+    value.map(Some)
+}
+
+fn interp_call(
+    callee: &Expression,
+    args: &[Expression],
+    env: &mut Env,
+) -> Result<Option<Types>, InterpErrors> {
+    let Some(callee) = within(callee, env, interp_link)? else {
+        return Ok(None);
+    };
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        values.push(interp_program(arg, env)?);
+    }
+
+    call_value(callee, values, env).map(Some)
+}
+
+fn interp_method(
+    receiver: &Expression,
+    method: &str,
+    args: &[Expression],
+    optional: bool,
+    env: &mut Env,
+) -> Result<Option<Types>, InterpErrors> {
+    let Some(receiver) = within(receiver, env, interp_link)? else {
+        return Ok(None);
+    };
+    if optional && receiver == Types::Nil {
+        return Ok(None);
+    }
+
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        values.push(interp_program(arg, env)?);
+    }
+
+    interp_method_call(receiver, String::from(method), values, env).map(Some)
+}
+
+fn interp_struct_literal(
+    name: &str,
+    fields: &[(String, Expression)],
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let declared = struct_fields(name, env)?;
+    let mut values = Vec::with_capacity(fields.len());
+    for (field, value) in fields {
+        if !declared.contains(field) {
+            return Err(InterpErrors::FieldDoesNotExist(field.clone()));
+        }
+        values.push((field.clone(), interp_program(value, env)?));
+    }
+
+    build_record(String::from(name), declared, values)
+}
+
+fn interp_let(
+    identifier: &str,
+    value: &Expression,
+    constant: bool,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let value = interp_program(value, env)?;
+    env.declare(String::from(identifier), value, constant)?;
+    Ok(Types::Unit)
+}
+
+fn interp_block(stmts: &[Expression], env: &mut Env) -> Result<Types, InterpErrors> {
+    env.push_scope(Scope::new());
+    let mut result = Ok(Types::Unit);
+    for stmt in stmts {
+        result = interp_program(stmt, env);
+        if result.is_err() {
+            break;
+        }
+    }
+    env.pop_scope();
+    result
+}
+
+fn interp_try(
+    body: &Expression,
+    catch: &Option<(String, Box<Expression>)>,
+    finally: &Option<Box<Expression>>,
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let result = match (interp_program(body, env), catch) {
+        (Err(InterpErrors::Return(value)), _) => Err(InterpErrors::Return(value)),
+        (Err(e), Some((name, handler))) => {
+            interp_with_bindings(handler, vec![(name.clone(), e.into_value())], env)
+        }
+        (result, _) => result,
+    };
+
+    // An error from the finally block replaces whatever the try or catch produced.
+    match finally {
+        Some(finally) => interp_program(finally, env).and(result),
+        None => result,
+    }
+}
+
+fn interp_impl(
+    type_name: &str,
+    methods: &[Expression],
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    check_impl_type(type_name, env)?;
+    for method in methods {
+        let function = impl_function(method, env.module.clone())?;
+        env.add_method(String::from(type_name), Rc::new(function));
+    }
+    Ok(Types::Unit)
+}
+
+fn interp_export(declaration: &Expression, env: &mut Env) -> Result<Types, InterpErrors> {
+    let name = exported_name(declaration)?;
+    let result = interp_program(declaration, env)?;
+    env.exports.push(name);
+    Ok(result)
+}
+
+fn interp_match(
+    scrutinee: &Expression,
+    arms: &[(Pattern, Expression)],
+    env: &mut Env,
+) -> Result<Types, InterpErrors> {
+    let value = interp_program(scrutinee, env)?;
+    check_exhaustive(&value, arms.iter().map(|(pattern, _)| pattern), env)?;
+
+    for (pattern, body) in arms {
+        let mut bindings = Vec::new();
+        if match_pattern(pattern, value.clone(), env, &mut bindings)? {
+            return interp_with_bindings(body, bindings, env);
+        }
+    }
+    Err(InterpErrors::NoMatchingArm(value))
+}
+
+#[cfg(test)]
+mod twig_interp_tests {
+    use super::*;
+    use crate::twig_resolver::resolve_program;
+    use crate::twig_vm::run_program;
+
+    /// Runs every test on the VM as well, which has to give the same result and leave the same
+    /// variables and methods behind. When every name it uses exists, it's also run on both with
+    /// its local variables resolved to slots, which has to give the same result again.
+    fn interp_program(expr: &Expression, env: &mut Env) -> Result<Types, InterpErrors> {
+        let original = env.clone();
+        let mut copy = env.clone();
+        let compiled = run_program(expr, &mut copy);
+        let result = super::interp_program(expr, env);
+        assert_eq!(compiled, result);
+        assert_eq!(copy.globals, env.globals);
+        assert_eq!(copy.methods, env.methods);
+
+        let mut resolved = expr.clone();
+        if resolve_program(std::slice::from_mut(&mut resolved), &original).is_ok() {
+            let (mut walked, mut compiled) = (original.clone(), original);
+            assert_eq!(super::interp_program(&resolved, &mut walked), result);
+            assert_eq!(run_program(&resolved, &mut compiled), result);
+            assert_eq!(walked.globals, compiled.globals);
+            assert_eq!(walked.methods, compiled.methods);
+        }
+        result
+    }
+
+    #[test]
+    fn test_interp_program_int() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Integer(1).into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+        let expected = Types::Integer(1);
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_bool() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Boolean(true).into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+        let expected = Types::Boolean(true);
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_string() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::String(String::from("string")).into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+        let expected = Types::String(String::from("string"));
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_int_id() {
+        let mut env = Env::new();
+        let id = "a";
+        env.insert(String::from(id), Types::Integer(1)).unwrap();
+        let e: Expression = ExprKind::Identifier(String::from(id)).into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+        let expected = Types::Integer(1);
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_bool_id() {
+        let mut env = Env::new();
+        let id = "a";
+        env.insert(String::from(id), Types::Boolean(true)).unwrap();
+        let e: Expression = ExprKind::Identifier(String::from(id)).into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+        let expected = Types::Boolean(true);
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_string_id() {
+        let mut env = Env::new();
+        let id = "a";
+        let value = "value";
+        env.insert(String::from(id), Types::String(String::from(value)))
+            .unwrap();
+        let e: Expression = ExprKind::Identifier(String::from(id)).into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+        let expected = Types::String(String::from(value));
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_bad_id() {
+        let mut env = Env::new();
+        let id = "a";
+        let value = "value";
+        env.insert(String::from("b"), Types::String(String::from(value)))
+            .unwrap();
+        let e: Expression = ExprKind::Identifier(String::from(id)).into();
+
+        let result = interp_program(&e, &mut env);
+
+        assert_eq!(result.unwrap_err(), undefined("a", Some("b")));
+    }
+
+    #[test]
+    fn test_interp_program_let_stmt() {
+        let mut env = Env::new();
+        let id = "a";
+        let e: Expression = ExprKind::LetStmt {
+            doc: None,
+            identifier: String::from(id),
+            value: Box::new(ExprKind::Integer(1).into()),
+            constant: false,
+            annotation: None,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+        let env_result = env.get(&String::from(id)).unwrap();
+
+        assert_eq!(result, Types::Unit);
+        assert_eq!(*env_result, Types::Integer(1));
+    }
+
+    #[test]
+    fn test_interp_program_math_op_add() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: MathOperator::Add,
+            rhs: Box::new(ExprKind::Integer(1).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(2));
+    }
+
+    #[test]
+    fn test_interp_program_math_op_sub() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: MathOperator::Subtract,
+            rhs: Box::new(ExprKind::Integer(1).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(0));
+    }
+
+    #[test]
+    fn test_interp_program_math_op_mult() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Integer(2).into()),
+            op: MathOperator::Multiply,
+            rhs: Box::new(ExprKind::Integer(3).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(6));
+    }
+
+    #[test]
+    fn test_interp_program_math_op_div() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Integer(100).into()),
+            op: MathOperator::Divide,
+            rhs: Box::new(ExprKind::Integer(10).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(10));
+    }
+
+    #[test]
+    fn test_interp_program_math_op_err() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Boolean(true).into()),
+            op: MathOperator::Divide,
+            rhs: Box::new(ExprKind::Integer(10).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::InvalidTypeError(String::from(
+                "`/` needs two integers, found bool and int"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_eq() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(ExprKind::Boolean(true).into()),
+            op: BooleanOperator::Eqaul,
+            rhs: Box::new(ExprKind::Boolean(false).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Boolean(false));
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_le() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: BooleanOperator::LessThan,
+            rhs: Box::new(ExprKind::Integer(10).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Boolean(true));
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_leq() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: BooleanOperator::LessThanEqual,
+            rhs: Box::new(ExprKind::Integer(10).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Boolean(true));
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_ge() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: BooleanOperator::GreaterThan,
+            rhs: Box::new(ExprKind::Integer(10).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Boolean(false));
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_geq() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: BooleanOperator::GreaterThanEqual,
+            rhs: Box::new(ExprKind::Integer(10).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Boolean(false));
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_mismatch_type_err() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(ExprKind::Boolean(true).into()),
+            op: BooleanOperator::GreaterThanEqual,
+            rhs: Box::new(ExprKind::Integer(10).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::MismatchedTypeError(String::from("bool"), String::from("int"))
+        );
+    }
+
+    #[test]
+    fn test_interp_program_nil() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Nil.into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Nil);
+    }
+
+    #[test]
+    fn test_interp_program_list() {
+        let mut env = Env::new();
+        let e: Expression =
+            ExprKind::List(vec![ExprKind::Integer(1).into(), ExprKind::Nil.into()]).into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::List(vec![Types::Integer(1), Types::Nil]));
+    }
+
+    #[test]
+    fn test_interp_program_coalesce_nil() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Coalesce {
+            lhs: Box::new(ExprKind::Nil.into()),
+            rhs: Box::new(ExprKind::Integer(2).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(2));
+    }
+
+    #[test]
+    fn test_interp_program_coalesce_value() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Coalesce {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            rhs: Box::new(ExprKind::Identifier(String::from("missing")).into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(1));
+    }
+
+    #[test]
+    fn test_interp_program_index() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Access {
+            target: Box::new(ExprKind::List(vec![ExprKind::Integer(1).into()]).into()),
+            accessor: Accessor::Index(Box::new(ExprKind::Integer(0).into())),
+            optional: false,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(1));
+    }
+
+    #[test]
+    fn test_interp_program_index_out_of_bounds() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Access {
+            target: Box::new(ExprKind::List(vec![]).into()),
+            accessor: Accessor::Index(Box::new(ExprKind::Integer(0).into())),
+            optional: false,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(result, InterpErrors::IndexOutOfBounds { index: 0, len: 0 });
+    }
+
+    #[test]
+    fn test_interp_program_optional_index_out_of_bounds() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Access {
+            target: Box::new(ExprKind::List(vec![]).into()),
+            accessor: Accessor::Index(Box::new(ExprKind::Integer(0).into())),
+            optional: true,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Nil);
+    }
+
+    #[test]
+    fn test_interp_program_optional_field_on_nil() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Access {
+            target: Box::new(ExprKind::Nil.into()),
+            accessor: Accessor::Field(String::from("a")),
+            optional: true,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Nil);
+    }
+
+    fn interp_lines(input: &str) -> Result<Types, InterpErrors> {
+        let mut env = Env::new();
+        let mut result = Ok(Types::Unit);
+        for program in crate::twig_parser::parse_file(input).unwrap() {
+            result = interp_program(&program, &mut env);
+        }
+        result
+    }
+
+    #[test]
+    fn test_interp_program_optional_chain_short_circuits() {
+        let result = interp_lines("let a = nil\n[a?.b.c, a?.b[0], a?.b.len(), a?.f().g]").unwrap();
+
+        assert_eq!(result, Types::List(vec![Types::Nil; 4]));
+    }
+
+    #[test]
+    fn test_interp_program_optional_chain_stops_at_non_nil() {
+        let input = "struct Holder { b }\nlet a = Holder { b: nil }\na?.b.c";
+
+        let result = interp_lines(input).unwrap_err().without_span();
+
+        assert_eq!(
+            result,
+            InterpErrors::InvalidTypeError(String::from("nil has no fields, so it can't have `c`"))
+        );
+    }
+
+    #[test]
+    fn test_interp_program_optional_chain_ends_at_operand() {
+        let result = interp_lines("[nil?.a].len()").unwrap();
+
+        assert_eq!(result, Types::Integer(1));
+    }
+
+    #[test]
+    fn test_interp_program_field_on_nil_err() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Access {
+            target: Box::new(ExprKind::Nil.into()),
+            accessor: Accessor::Field(String::from("a")),
+            optional: false,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::InvalidTypeError(String::from("nil has no fields, so it can't have `a`"))
+        );
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_eq_nil() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: BooleanOperator::Eqaul,
+            rhs: Box::new(ExprKind::Nil.into()),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Boolean(false));
+    }
+
+    fn point_env() -> Env {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::StructDecl {
+            name: String::from("Point"),
+            type_params: Vec::new(),
+            fields: vec![Annotated::new("x"), Annotated::new("y")],
+        }
+        .into();
+        interp_program(&e, &mut env).unwrap();
+        env
+    }
+
+    fn point_literal(x: i32, y: i32) -> Expression {
+        ExprKind::StructLiteral {
+            name: String::from("Point"),
+            fields: vec![
+                (String::from("y"), ExprKind::Integer(y).into()),
+                (String::from("x"), ExprKind::Integer(x).into()),
+            ],
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_interp_program_struct_literal() {
+        let mut env = point_env();
+
+        let result = interp_program(&point_literal(1, 2), &mut env).unwrap();
+        let expected = Types::Record {
+            name: String::from("Point"),
+            fields: vec![
+                (String::from("x"), Types::Integer(1)),
+                (String::from("y"), Types::Integer(2)),
+            ],
+        };
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_struct_literal_missing_field() {
+        let mut env = point_env();
+        let e: Expression = ExprKind::StructLiteral {
+            name: String::from("Point"),
+            fields: vec![(String::from("x"), ExprKind::Integer(1).into())],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(result, InterpErrors::MissingFieldError(String::from("y")));
+    }
+
+    #[test]
+    fn test_interp_program_struct_literal_unknown_field() {
+        let mut env = point_env();
+        let e: Expression = ExprKind::StructLiteral {
+            name: String::from("Point"),
+            fields: vec![(String::from("z"), ExprKind::Integer(1).into())],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(result, InterpErrors::FieldDoesNotExist(String::from("z")));
+    }
+
+    #[test]
+    fn test_interp_program_field_access() {
+        let mut env = point_env();
+        let e: Expression = ExprKind::Access {
+            target: Box::new(point_literal(1, 2)),
+            accessor: Accessor::Field(String::from("y")),
+            optional: false,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(2));
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_eq_record() {
+        let mut env = point_env();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(point_literal(1, 2)),
+            op: BooleanOperator::Eqaul,
+            rhs: Box::new(point_literal(1, 2)),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Boolean(true));
+    }
+
+    #[test]
+    fn test_interp_program_bool_op_lt_record_err() {
+        let mut env = point_env();
+        let e: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(point_literal(1, 2)),
+            op: BooleanOperator::LessThan,
+            rhs: Box::new(point_literal(1, 2)),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::InvalidTypeError(String::from("`<` cannot order values of type Point"))
+        );
+    }
+
+    #[test]
+    fn test_interp_program_type_of() {
+        let mut env = point_env();
+        let e: Expression = ExprKind::Call {
+            callee: Box::new(ExprKind::Identifier(String::from("type_of")).into()),
+            args: vec![point_literal(1, 2)],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::String(String::from("Point")));
+    }
+
+    fn shape_env() -> Env {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::EnumDecl {
+            name: String::from("Shape"),
+            variants: vec![
+                (String::from("Circle"), vec![String::from("r")]),
+                (String::from("Empty"), vec![]),
+            ],
+        }
+        .into();
+        interp_program(&e, &mut env).unwrap();
+        env
+    }
+
+    fn circle(r: i32) -> Expression {
+        ExprKind::Call {
+            callee: Box::new(
+                ExprKind::Variant {
+                    enum_name: String::from("Shape"),
+                    variant: String::from("Circle"),
+                }
+                .into(),
+            ),
+            args: vec![ExprKind::Integer(r).into()],
+        }
+        .into()
+    }
+
+    fn circle_pattern(field: Pattern) -> Pattern {
+        PatternKind::Variant {
+            enum_name: String::from("Shape"),
+            variant: String::from("Circle"),
+            fields: vec![field],
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_interp_program_enum_constructor() {
+        let mut env = shape_env();
+
+        let result = interp_program(&circle(2), &mut env).unwrap();
+        let expected = Types::EnumValue {
+            enum_name: String::from("Shape"),
+            variant: String::from("Circle"),
+            payload: vec![Types::Integer(2)],
+        };
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_enum_unit_variant() {
+        let mut env = shape_env();
+        let e: Expression = ExprKind::Variant {
+            enum_name: String::from("Shape"),
+            variant: String::from("Empty"),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+        let expected = Types::EnumValue {
+            enum_name: String::from("Shape"),
+            variant: String::from("Empty"),
+            payload: vec![],
+        };
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_enum_bad_variant() {
+        let mut env = shape_env();
+        let e: Expression = ExprKind::Variant {
+            enum_name: String::from("Shape"),
+            variant: String::from("Square"),
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::VariantDoesNotExist(String::from("Shape::Square"))
+        );
+    }
+
+    #[test]
+    fn test_interp_program_match_variant() {
+        let mut env = shape_env();
+        let e: Expression = ExprKind::Match {
+            scrutinee: Box::new(circle(2)),
+            arms: vec![
+                (
+                    circle_pattern(PatternKind::Binding(String::from("r")).into()),
+                    ExprKind::Identifier(String::from("r")).into(),
+                ),
+                (
+                    PatternKind::Variant {
+                        enum_name: String::from("Shape"),
+                        variant: String::from("Empty"),
+                        fields: vec![],
+                    }
+                    .into(),
+                    ExprKind::Integer(0).into(),
+                ),
+            ],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(2));
+        assert_eq!(env.get("r"), None);
+    }
+
+    #[test]
+    fn test_interp_program_match_non_exhaustive() {
+        let mut env = shape_env();
+        let e: Expression = ExprKind::Match {
+            scrutinee: Box::new(circle(2)),
+            arms: vec![(
+                circle_pattern(PatternKind::Wildcard.into()),
+                ExprKind::Integer(1).into(),
+            )],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::NonExhaustiveMatch(vec![String::from("Shape::Empty")])
+        );
+    }
+
+    #[test]
+    fn test_interp_program_match_literal_fallthrough() {
+        let mut env = shape_env();
+        let e: Expression = ExprKind::Match {
+            scrutinee: Box::new(circle(2)),
+            arms: vec![
+                (
+                    circle_pattern(
+                        PatternKind::Literal(Box::new(ExprKind::Integer(1).into())).into(),
+                    ),
+                    ExprKind::Integer(1).into(),
+                ),
+                (PatternKind::Wildcard.into(), ExprKind::Integer(2).into()),
+            ],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(2));
+    }
+
+    #[test]
+    fn test_interp_program_match_no_arm() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Match {
+            scrutinee: Box::new(ExprKind::Integer(3).into()),
+            arms: vec![(
+                PatternKind::Literal(Box::new(ExprKind::Integer(1).into())).into(),
+                ExprKind::Integer(1).into(),
+            )],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(result, InterpErrors::NoMatchingArm(Types::Integer(3)));
+    }
+
+    #[test]
+    fn test_interp_program_match_restores_shadowed() {
+        let mut env = Env::new();
+        env.insert(String::from("x"), Types::Integer(1)).unwrap();
+        let e: Expression = ExprKind::Match {
+            scrutinee: Box::new(ExprKind::Integer(5).into()),
+            arms: vec![(
+                PatternKind::Binding(String::from("x")).into(),
+                ExprKind::Identifier(String::from("x")).into(),
+            )],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(5));
+        assert_eq!(*env.get("x").unwrap(), Types::Integer(1));
+    }
+
+    fn double_fn() -> Expression {
+        ExprKind::FnDecl {
+            doc: None,
+            name: String::from("double"),
+            type_params: Vec::new(),
+            params: vec![Annotated::new("self")],
+            return_type: None,
+            body: Box::new(
+                ExprKind::Block(vec![
+                    ExprKind::LetStmt {
+                        doc: None,
+                        identifier: String::from("result"),
+                        value: Box::new(
+                            ExprKind::MathOp {
+                                lhs: Box::new(ExprKind::Identifier(String::from("self")).into()),
+                                op: MathOperator::Multiply,
+                                rhs: Box::new(ExprKind::Integer(2).into()),
+                            }
+                            .into(),
+                        ),
+                        constant: false,
+                        annotation: None,
+                    }
+                    .into(),
+                    ExprKind::Identifier(String::from("result")).into(),
+                ])
+                .into(),
+            ),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_interp_program_fn_call() {
+        let mut env = Env::new();
+        interp_program(&double_fn(), &mut env).unwrap();
+        let e: Expression = ExprKind::Call {
+            callee: Box::new(ExprKind::Identifier(String::from("double")).into()),
+            args: vec![ExprKind::Integer(4).into()],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(8));
+        assert_eq!(env.get("result"), None);
+    }
+
+    #[test]
+    fn test_interp_program_fn_call_wrong_argument_count() {
+        let mut env = Env::new();
+        interp_program(&double_fn(), &mut env).unwrap();
+        let e: Expression = ExprKind::Call {
+            callee: Box::new(ExprKind::Identifier(String::from("double")).into()),
+            args: vec![],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Types {
-    Integer(i32),
-    Boolean(bool),
-    String(String),
-    Unit,
-}
+        assert_eq!(
+            result,
+            InterpErrors::WrongArgumentCount {
+                expected: 1,
+                found: 0
+            }
+        );
+    }
 
-#[derive(Debug, PartialEq)]
-pub enum InterpErrors {
-    MismatchedTypeError,
-    InvalidTypeError,
-    VariableDoesNotExist,
-}
+    #[test]
+    fn test_interp_program_fn_cannot_see_caller_locals() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::FnDecl {
+            doc: None,
+            name: String::from("f"),
+            type_params: Vec::new(),
+            params: vec![],
+            return_type: None,
+            body: Box::new(ExprKind::Identifier(String::from("local")).into()),
+        }
+        .into();
+        interp_program(&e, &mut env).unwrap();
+        let e: Expression = ExprKind::Match {
+            scrutinee: Box::new(ExprKind::Integer(1).into()),
+            arms: vec![(
+                PatternKind::Binding(String::from("local")).into(),
+                ExprKind::Call {
+                    callee: Box::new(ExprKind::Identifier(String::from("f")).into()),
+                    args: vec![],
+                }
+                .into(),
+            )],
+        }
+        .into();
 
-pub type Env = BTreeMap<String, Box<Types>>;
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-impl fmt::Display for InterpErrors {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            InterpErrors::MismatchedTypeError => write!(f, "Mismatched types"),
-            InterpErrors::InvalidTypeError => write!(f, "InvalidTypeError"),
-            InterpErrors::VariableDoesNotExist => write!(f, "Variable does not exist"),
+        assert_eq!(result, undefined("local", None));
+    }
+
+    #[test]
+    fn test_interp_program_method_call() {
+        let mut env = point_env();
+        let e: Expression = ExprKind::ImplBlock {
+            type_name: String::from("Point"),
+            methods: vec![
+                ExprKind::FnDecl {
+                    doc: None,
+                    name: String::from("x"),
+                    type_params: Vec::new(),
+                    params: vec![Annotated::new("self")],
+                    return_type: None,
+                    body: Box::new(
+                        ExprKind::Access {
+                            target: Box::new(ExprKind::Identifier(String::from("self")).into()),
+                            accessor: Accessor::Field(String::from("x")),
+                            optional: false,
+                        }
+                        .into(),
+                    ),
+                }
+                .into(),
+            ],
         }
+        .into();
+        interp_program(&e, &mut env).unwrap();
+        let e: Expression = ExprKind::MethodCall {
+            receiver: Box::new(point_literal(3, 4)),
+            method: String::from("x"),
+            args: vec![],
+            optional: false,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(3));
     }
-}
 
-impl std::error::Error for InterpErrors {}
+    #[test]
+    fn test_interp_program_impl_unknown_type() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::ImplBlock {
+            type_name: String::from("Point"),
+            methods: vec![double_fn()],
+        }
+        .into();
 
-pub fn interp_program(expr: Expression, env: &mut Env) -> std::result::Result<Types, InterpErrors> {
-    match expr {
-        Expression::Integer(value) => Ok(Types::Integer(value)),
-        Expression::Boolean(value) => Ok(Types::Boolean(value)),
-        Expression::String(value) => Ok(Types::String(value)),
-        Expression::MathOp { lhs, op, rhs } => {
-            let (Ok(Types::Integer(left)), Ok(Types::Integer(right))) =
-                (interp_program(*lhs, env), interp_program(*rhs, env))
-            else {
-                return Err(InterpErrors::InvalidTypeError);
-            };
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-            let result = match op {
-                MathOperator::Add => left + right,
-                MathOperator::Subtract => left - right,
-                MathOperator::Multiply => left * right,
-                MathOperator::Divide => left / right,
-            };
+        assert_eq!(result, undefined("Point", None));
+    }
 
-            Ok(Types::Integer(result))
+    #[test]
+    fn test_interp_program_builtin_method_call() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::MethodCall {
+            receiver: Box::new(ExprKind::List(vec![ExprKind::Integer(1).into()]).into()),
+            method: String::from("len"),
+            args: vec![],
+            optional: false,
         }
-        Expression::BooleanOp { lhs, op, rhs } => {
-            let (Ok(l), Ok(r)) = (interp_program(*lhs, env), interp_program(*rhs, env)) else {
-                return Err(InterpErrors::InvalidTypeError);
-            };
+        .into();
 
-            let (left, right) = match (l, r) {
-                (Types::Integer(i), Types::Integer(j)) => (i, j),
-                (Types::Boolean(i), Types::Boolean(j)) => (i as i32, j as i32),
-                (Types::String(_), Types::String(_)) => return Err(InterpErrors::InvalidTypeError),
-                _ => return Err(InterpErrors::MismatchedTypeError),
-            };
+        let result = interp_program(&e, &mut env).unwrap();
 
-            let result = match op {
-                BooleanOperator::Eqaul => left == right,
-                BooleanOperator::GreaterThan => left > right,
-                BooleanOperator::GreaterThanEqual => left >= right,
-                BooleanOperator::LessThan => left < right,
-                BooleanOperator::LessThanEqual => left <= right,
-            };
+        assert_eq!(result, Types::Integer(1));
+    }
 
-            Ok(Types::Boolean(result))
+    #[test]
+    fn test_interp_program_math_op_div_by_zero() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: MathOperator::Divide,
+            rhs: Box::new(ExprKind::Integer(0).into()),
         }
-        Expression::LetStmt { identifier, value } => match interp_program(*value, env) {
-            Ok(result) => {
-                env.insert(identifier, Box::new(result));
-                Ok(Types::Unit)
-            }
-            Err(e) => Err(e),
-        },
-        Expression::Identifier(var) => match env.get(&var) {
-            // Need to do bit of a strange dereference here to get Types out of Box. Clone to get a
-            // copy of the value in env.
-            Some(value) => Ok(*(*value).clone()),
-            None => Err(InterpErrors::VariableDoesNotExist),
-        },
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(result, InterpErrors::DivisionByZero);
     }
-}
 
-#[cfg(test)]
-mod twig_interp_tests {
-    use super::*;
+    #[test]
+    fn test_interp_program_math_op_overflow() {
+        let overflow = |input: &str| interp_lines(input).unwrap_err().without_span();
+        let min = "(0 - 2147483647 - 1)";
+
+        assert_eq!(
+            overflow("2147483647 + 1"),
+            InterpErrors::IntegerOverflow(String::from("2147483647 + 1"))
+        );
+        assert_eq!(
+            overflow(&format!("{} - 1", min)),
+            InterpErrors::IntegerOverflow(String::from("-2147483648 - 1"))
+        );
+        assert_eq!(
+            overflow("65536 * 65536"),
+            InterpErrors::IntegerOverflow(String::from("65536 * 65536"))
+        );
+        assert_eq!(
+            overflow(&format!("{} / (0 - 1)", min)),
+            InterpErrors::IntegerOverflow(String::from("-2147483648 / -1"))
+        );
+    }
 
     #[test]
-    fn test_interp_program_int() {
+    fn test_interp_program_throw() {
         let mut env = Env::new();
-        let e = Expression::Integer(1);
+        let e: Expression = ExprKind::Throw(Box::new(ExprKind::Integer(1).into())).into();
 
-        let result = interp_program(e, &mut env).unwrap();
-        let expected = Types::Integer(1);
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-        assert_eq!(result, expected);
+        assert_eq!(result, InterpErrors::Thrown(Types::Integer(1)));
     }
 
     #[test]
-    fn test_interp_program_bool() {
+    fn test_interp_program_try_catch_thrown() {
         let mut env = Env::new();
-        let e = Expression::Boolean(true);
+        let e: Expression = ExprKind::Try {
+            body: Box::new(ExprKind::Throw(Box::new(ExprKind::Integer(1).into())).into()),
+            catch: Some((
+                String::from("e"),
+                Box::new(ExprKind::Identifier(String::from("e")).into()),
+            )),
+            finally: None,
+        }
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
-        let expected = Types::Boolean(true);
+        let result = interp_program(&e, &mut env).unwrap();
 
-        assert_eq!(result, expected);
+        assert_eq!(result, Types::Integer(1));
+        assert_eq!(env.get("e"), None);
     }
 
     #[test]
-    fn test_interp_program_string() {
+    fn test_interp_program_try_catch_runtime_error() {
         let mut env = Env::new();
-        let e = Expression::String(String::from("string"));
+        let e: Expression = ExprKind::Try {
+            body: Box::new(ExprKind::Identifier(String::from("missing")).into()),
+            catch: Some((
+                String::from("e"),
+                Box::new(
+                    ExprKind::Access {
+                        target: Box::new(ExprKind::Identifier(String::from("e")).into()),
+                        accessor: Accessor::Field(String::from("kind")),
+                        optional: false,
+                    }
+                    .into(),
+                ),
+            )),
+            finally: None,
+        }
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
-        let expected = Types::String(String::from("string"));
+        let result = interp_program(&e, &mut env).unwrap();
 
-        assert_eq!(result, expected);
+        assert_eq!(result, Types::String(String::from("VariableDoesNotExist")));
     }
 
     #[test]
-    fn test_interp_program_int_id() {
+    fn test_interp_program_try_finally_keeps_error() {
         let mut env = Env::new();
-        let id = "a";
-        env.insert(String::from(id), Box::new(Types::Integer(1)));
-        let e = Expression::Identifier(String::from(id));
+        let e: Expression = ExprKind::Try {
+            body: Box::new(ExprKind::Throw(Box::new(ExprKind::Integer(1).into())).into()),
+            catch: None,
+            finally: Some(Box::new(
+                ExprKind::LetStmt {
+                    doc: None,
+                    identifier: String::from("cleaned"),
+                    value: Box::new(ExprKind::Boolean(true).into()),
+                    constant: false,
+                    annotation: None,
+                }
+                .into(),
+            )),
+        }
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
-        let expected = Types::Integer(1);
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-        assert_eq!(result, expected);
+        assert_eq!(result, InterpErrors::Thrown(Types::Integer(1)));
+        assert_eq!(env.get("cleaned"), Some(&Types::Boolean(true)));
     }
 
     #[test]
-    fn test_interp_program_bool_id() {
+    fn test_interp_program_try_finally_error_wins() {
         let mut env = Env::new();
-        let id = "a";
-        env.insert(String::from(id), Box::new(Types::Boolean(true)));
-        let e = Expression::Identifier(String::from(id));
+        let e: Expression = ExprKind::Try {
+            body: Box::new(ExprKind::Integer(1).into()),
+            catch: None,
+            finally: Some(Box::new(
+                ExprKind::Throw(Box::new(ExprKind::Integer(2).into())).into(),
+            )),
+        }
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
-        let expected = Types::Boolean(true);
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-        assert_eq!(result, expected);
+        assert_eq!(result, InterpErrors::Thrown(Types::Integer(2)));
+    }
+
+    fn result_value(variant: &str, value: Types) -> Types {
+        Types::EnumValue {
+            enum_name: String::from("Result"),
+            variant: String::from(variant),
+            payload: vec![value],
+        }
+    }
+
+    fn result_expression(variant: &str, value: Expression) -> Expression {
+        ExprKind::Call {
+            callee: Box::new(ExprKind::Identifier(String::from(variant)).into()),
+            args: vec![value],
+        }
+        .into()
     }
 
     #[test]
-    fn test_interp_program_string_id() {
+    fn test_interp_program_propagate_ok() {
         let mut env = Env::new();
-        let id = "a";
-        let value = "value";
-        env.insert(
-            String::from(id),
-            Box::new(Types::String(String::from(value))),
-        );
-        let e = Expression::Identifier(String::from(id));
+        let e: Expression = ExprKind::Propagate(Box::new(result_expression(
+            "Ok",
+            ExprKind::Integer(1).into(),
+        )))
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
-        let expected = Types::String(String::from(value));
+        let result = interp_program(&e, &mut env).unwrap();
 
-        assert_eq!(result, expected);
+        assert_eq!(result, Types::Integer(1));
     }
 
     #[test]
-    fn test_interp_program_bad_id() {
+    fn test_interp_program_propagate_err() {
         let mut env = Env::new();
-        let id = "a";
-        let value = "value";
-        env.insert(
-            String::from("b"),
-            Box::new(Types::String(String::from(value))),
+        let e: Expression = ExprKind::Propagate(Box::new(result_expression(
+            "Err",
+            ExprKind::Integer(1).into(),
+        )))
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::Return(result_value("Err", Types::Integer(1)))
         );
-        let e = Expression::Identifier(String::from(id));
+    }
+
+    #[test]
+    fn test_interp_program_propagate_not_result() {
+        let mut env = Env::new();
+        let e: Expression = ExprKind::Propagate(Box::new(ExprKind::Integer(1).into())).into();
 
-        let result = interp_program(e, &mut env);
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-        assert_eq!(result.unwrap_err(), InterpErrors::VariableDoesNotExist);
+        assert_eq!(
+            result,
+            InterpErrors::InvalidTypeError(String::from("`?` needs a Result, found int"))
+        );
     }
 
     #[test]
-    fn test_interp_program_let_stmt() {
+    fn test_interp_program_propagate_returns_from_fn() {
         let mut env = Env::new();
-        let id = "a";
-        let e = Expression::LetStmt {
-            identifier: String::from(id),
-            value: Box::new(Expression::Integer(1)),
-        };
+        let e: Expression = ExprKind::FnDecl {
+            doc: None,
+            name: String::from("f"),
+            type_params: Vec::new(),
+            params: vec![],
+            return_type: None,
+            body: Box::new(
+                ExprKind::Block(vec![
+                    ExprKind::Propagate(Box::new(result_expression(
+                        "Err",
+                        ExprKind::Integer(1).into(),
+                    )))
+                    .into(),
+                    result_expression("Ok", ExprKind::Integer(2).into()),
+                ])
+                .into(),
+            ),
+        }
+        .into();
+        interp_program(&e, &mut env).unwrap();
+        let e: Expression = ExprKind::Call {
+            callee: Box::new(ExprKind::Identifier(String::from("f")).into()),
+            args: vec![],
+        }
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
-        let env_result = env.get(&String::from(id)).unwrap();
+        let result = interp_program(&e, &mut env).unwrap();
 
-        assert_eq!(result, Types::Unit);
-        assert_eq!(**env_result, Types::Integer(1));
+        assert_eq!(result, result_value("Err", Types::Integer(1)));
     }
 
     #[test]
-    fn test_interp_program_math_op_add() {
+    fn test_interp_program_try_does_not_catch_return() {
         let mut env = Env::new();
-        let e = Expression::MathOp {
-            lhs: Box::new(Expression::Integer(1)),
-            op: MathOperator::Add,
-            rhs: Box::new(Expression::Integer(1)),
-        };
+        let e: Expression = ExprKind::Try {
+            body: Box::new(ExprKind::Return(Some(Box::new(ExprKind::Integer(1).into()))).into()),
+            catch: Some((String::from("e"), Box::new(ExprKind::Integer(2).into()))),
+            finally: None,
+        }
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-        assert_eq!(result, Types::Integer(2));
+        assert_eq!(result, InterpErrors::Return(Types::Integer(1)));
     }
 
     #[test]
-    fn test_interp_program_math_op_sub() {
+    fn test_interp_program_match_result() {
         let mut env = Env::new();
-        let e = Expression::MathOp {
-            lhs: Box::new(Expression::Integer(1)),
-            op: MathOperator::Subtract,
-            rhs: Box::new(Expression::Integer(1)),
-        };
+        let e: Expression = ExprKind::Match {
+            scrutinee: Box::new(result_expression("Err", ExprKind::Integer(1).into())),
+            arms: vec![(
+                PatternKind::Variant {
+                    enum_name: String::from("Result"),
+                    variant: String::from("Ok"),
+                    fields: vec![PatternKind::Wildcard.into()],
+                }
+                .into(),
+                ExprKind::Integer(1).into(),
+            )],
+        }
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-        assert_eq!(result, Types::Integer(0));
+        assert_eq!(
+            result,
+            InterpErrors::NonExhaustiveMatch(vec![String::from("Result::Err")])
+        );
+    }
+
+    fn declaration(identifier: &str, value: i32, constant: bool) -> Expression {
+        ExprKind::LetStmt {
+            doc: None,
+            identifier: String::from(identifier),
+            value: Box::new(ExprKind::Integer(value).into()),
+            constant,
+            annotation: None,
+        }
+        .into()
     }
 
     #[test]
-    fn test_interp_program_math_op_mult() {
+    fn test_interp_program_const() {
         let mut env = Env::new();
-        let e = Expression::MathOp {
-            lhs: Box::new(Expression::Integer(2)),
-            op: MathOperator::Multiply,
-            rhs: Box::new(Expression::Integer(3)),
-        };
 
-        let result = interp_program(e, &mut env).unwrap();
+        interp_program(&declaration("a", 1, true), &mut env).unwrap();
 
-        assert_eq!(result, Types::Integer(6));
+        assert_eq!(env.get("a"), Some(&Types::Integer(1)));
     }
 
     #[test]
-    fn test_interp_program_math_op_div() {
+    fn test_interp_program_const_cannot_be_redeclared() {
         let mut env = Env::new();
-        let e = Expression::MathOp {
-            lhs: Box::new(Expression::Integer(100)),
-            op: MathOperator::Divide,
-            rhs: Box::new(Expression::Integer(10)),
-        };
+        interp_program(&declaration("a", 1, true), &mut env).unwrap();
 
-        let result = interp_program(e, &mut env).unwrap();
+        let result = interp_program(&declaration("a", 2, false), &mut env).unwrap_err();
+        assert_eq!(result, InterpErrors::AssignToConstant(String::from("a")));
 
-        assert_eq!(result, Types::Integer(10));
+        let result = interp_program(&declaration("a", 2, true), &mut env).unwrap_err();
+        assert_eq!(result, InterpErrors::AssignToConstant(String::from("a")));
+
+        assert_eq!(env.get("a"), Some(&Types::Integer(1)));
     }
 
     #[test]
-    fn test_interp_program_math_op_err() {
+    fn test_interp_program_const_blocks_other_declarations() {
         let mut env = Env::new();
-        let e = Expression::MathOp {
-            lhs: Box::new(Expression::Boolean(true)),
-            op: MathOperator::Divide,
-            rhs: Box::new(Expression::Integer(10)),
-        };
+        interp_program(&declaration("a", 1, true), &mut env).unwrap();
+        let e: Expression = ExprKind::FnDecl {
+            doc: None,
+            name: String::from("a"),
+            type_params: Vec::new(),
+            params: vec![],
+            return_type: None,
+            body: Box::new(ExprKind::Integer(2).into()),
+        }
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap_err();
+        let result = interp_program(&e, &mut env).unwrap_err();
 
-        assert_eq!(result, InterpErrors::InvalidTypeError);
+        assert_eq!(result, InterpErrors::AssignToConstant(String::from("a")));
     }
 
     #[test]
-    fn test_interp_program_bool_op_eq() {
+    fn test_interp_program_const_shadowed_in_inner_scope() {
         let mut env = Env::new();
-        let e = Expression::BooleanOp {
-            lhs: Box::new(Expression::Boolean(true)),
-            op: BooleanOperator::Eqaul,
-            rhs: Box::new(Expression::Boolean(false)),
-        };
+        interp_program(&declaration("a", 1, true), &mut env).unwrap();
+        let e: Expression = ExprKind::Block(vec![
+            declaration("a", 2, false),
+            ExprKind::Identifier(String::from("a")).into(),
+        ])
+        .into();
 
-        let result = interp_program(e, &mut env).unwrap();
+        let result = interp_program(&e, &mut env).unwrap();
 
-        assert_eq!(result, Types::Boolean(false));
+        assert_eq!(result, Types::Integer(2));
+        assert_eq!(env.get("a"), Some(&Types::Integer(1)));
     }
 
     #[test]
-    fn test_interp_program_bool_op_le() {
+    fn test_interp_program_let_can_be_redeclared() {
         let mut env = Env::new();
-        let e = Expression::BooleanOp {
-            lhs: Box::new(Expression::Integer(1)),
-            op: BooleanOperator::LessThan,
-            rhs: Box::new(Expression::Integer(10)),
-        };
+        interp_program(&declaration("a", 1, false), &mut env).unwrap();
+        interp_program(&declaration("a", 2, true), &mut env).unwrap();
 
-        let result = interp_program(e, &mut env).unwrap();
+        assert_eq!(env.get("a"), Some(&Types::Integer(2)));
+    }
 
-        assert_eq!(result, Types::Boolean(true));
+    #[test]
+    fn test_interp_program_error_span() {
+        let mut env = Env::new();
+        let input = "[1, 2 / (3 - 3)]";
+        let program = crate::twig_parser::parse_file(input).unwrap().remove(0);
+
+        let result = interp_program(&program, &mut env).unwrap_err();
+
+        let span = result.span().unwrap();
+        assert_eq!(&input[span.start..span.end], "2 / (3 - 3)");
+        assert_eq!((span.line, span.column), (1, 5));
+        assert_eq!(result.without_span(), InterpErrors::DivisionByZero);
     }
 
     #[test]
-    fn test_interp_program_bool_op_leq() {
+    fn test_interp_program_caught_error_has_no_span() {
         let mut env = Env::new();
-        let e = Expression::BooleanOp {
-            lhs: Box::new(Expression::Integer(1)),
-            op: BooleanOperator::LessThanEqual,
-            rhs: Box::new(Expression::Integer(10)),
-        };
+        let program = crate::twig_parser::parse_file("try { x } catch e { e.message }")
+            .unwrap()
+            .remove(0);
 
-        let result = interp_program(e, &mut env).unwrap();
+        let result = interp_program(&program, &mut env).unwrap();
 
-        assert_eq!(result, Types::Boolean(true));
+        assert_eq!(
+            result,
+            Types::String(String::from("Undefined variable `x`"))
+        );
+    }
+
+    fn undefined(name: &str, suggestion: Option<&str>) -> InterpErrors {
+        InterpErrors::VariableDoesNotExist {
+            name: String::from(name),
+            suggestion: suggestion.map(String::from),
+        }
+    }
+
+    fn operand_error(input: &str) -> InterpErrors {
+        let mut env = Env::new();
+        let mut result = Ok(Types::Unit);
+        for program in crate::twig_parser::parse_file(input).unwrap() {
+            result = interp_program(&program, &mut env);
+        }
+        result.unwrap_err().without_span()
+    }
+
+    fn operand(side: &str, op: &str, e: InterpErrors) -> InterpErrors {
+        InterpErrors::Context(format!("the {} operand of `{}`", side, op), Box::new(e))
     }
 
     #[test]
-    fn test_interp_program_bool_op_ge() {
+    fn test_interp_program_math_op_left_error() {
+        let result = operand_error("x + 1");
+        let expected = operand("left", "+", undefined("x", None));
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_math_op_right_error() {
+        let result = operand_error("1 - [1][2]");
+        let expected = operand(
+            "right",
+            "-",
+            InterpErrors::IndexOutOfBounds { index: 2, len: 1 },
+        );
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_math_op_nested_error() {
+        let result = operand_error("1 + 2 * (3 / 0)");
+        let expected = operand(
+            "right",
+            "+",
+            operand("right", "*", InterpErrors::DivisionByZero),
+        );
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_boolean_op_left_error() {
+        let result = operand_error("missing() == 1");
+        let expected = operand("left", "==", undefined("missing", None));
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_boolean_op_right_error() {
+        let result = operand_error("struct P { a }\n1 < P { a: 1 }.b");
+        let expected = operand(
+            "right",
+            "<",
+            InterpErrors::FieldDoesNotExist(String::from("b")),
+        );
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_boolean_op_nested_error() {
+        let result = operand_error("(x + 1) * 2 >= 3");
+        let expected = operand(
+            "left",
+            ">=",
+            operand("left", "*", operand("left", "+", undefined("x", None))),
+        );
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_operand_error_keeps_its_span() {
         let mut env = Env::new();
-        let e = Expression::BooleanOp {
-            lhs: Box::new(Expression::Integer(1)),
-            op: BooleanOperator::GreaterThan,
-            rhs: Box::new(Expression::Integer(10)),
-        };
+        let input = "1 + 2 / 0";
+        let program = crate::twig_parser::parse_file(input).unwrap().remove(0);
 
-        let result = interp_program(e, &mut env).unwrap();
+        let result = interp_program(&program, &mut env).unwrap_err();
 
-        assert_eq!(result, Types::Boolean(false));
+        let span = result.span().unwrap();
+        assert_eq!(&input[span.start..span.end], "2 / 0");
+        assert_eq!(result.kind(), "DivisionByZero");
     }
 
     #[test]
-    fn test_interp_program_bool_op_geq() {
+    fn test_interp_program_operand_error_is_caught_unchanged() {
         let mut env = Env::new();
-        let e = Expression::BooleanOp {
-            lhs: Box::new(Expression::Integer(1)),
-            op: BooleanOperator::GreaterThanEqual,
-            rhs: Box::new(Expression::Integer(10)),
-        };
+        let input = "try { 1 + (throw 2) } catch e { e }";
+        let program = crate::twig_parser::parse_file(input).unwrap().remove(0);
 
-        let result = interp_program(e, &mut env).unwrap();
+        let result = interp_program(&program, &mut env).unwrap();
 
-        assert_eq!(result, Types::Boolean(false));
+        assert_eq!(result, Types::Integer(2));
     }
 
     #[test]
-    fn test_interp_program_bool_op_mismatch_type_err() {
+    fn test_interp_program_operand_return_is_not_an_error() {
+        let result = operand_error("fn f() { 1 + Err(2)? }\nf() + x");
+        let expected = operand("right", "+", undefined("x", Some("f")));
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_interp_program_stack_trace() {
+        let input = "fn inner(n) { 10 / n }\nfn outer(n) {\n  inner(n - 1)\n}\nouter(1)";
         let mut env = Env::new();
-        let e = Expression::BooleanOp {
-            lhs: Box::new(Expression::Boolean(true)),
-            op: BooleanOperator::GreaterThanEqual,
-            rhs: Box::new(Expression::Integer(10)),
-        };
+        let mut result = Ok(Types::Unit);
+        for program in crate::twig_parser::parse_file(input).unwrap() {
+            result = interp_program(&program, &mut env);
+        }
+        let e = result.unwrap_err();
+
+        let trace: Vec<_> = e
+            .trace()
+            .unwrap()
+            .iter()
+            .map(|frame| {
+                let span = frame.span.unwrap();
+                (frame.function.as_str(), span.line, span.column, &frame.file)
+            })
+            .collect();
+        assert_eq!(trace, [("outer", 5, 1, &None), ("inner", 3, 3, &None)]);
+        assert_eq!((e.span().unwrap().line, e.kind()), (1, "DivisionByZero"));
+        assert!(env.frames.is_empty());
+    }
+
+    #[test]
+    fn test_interp_program_top_level_error_has_no_trace() {
+        let mut env = Env::new();
+        let program = crate::twig_parser::parse_file("fn f() { 1 }\n1 / 0").unwrap();
+        let mut result = Ok(Types::Unit);
+        for program in program {
+            result = interp_program(&program, &mut env);
+        }
+
+        assert_eq!(result.unwrap_err().trace(), None);
+    }
 
-        let result = interp_program(e, &mut env).unwrap_err();
+    #[test]
+    fn test_interp_program_deep_recursion() {
+        let input = "fn f(n) { match n { 0 => 0, _ => 1 + f(n - 1) } }\nf(5000)";
+        let mut env = Env::new();
+        let mut result = Ok(Types::Unit);
+        for program in crate::twig_parser::parse_file(input).unwrap() {
+            result = interp_program(&program, &mut env);
+        }
+
+        assert_eq!(result, Ok(Types::Integer(5000)));
+    }
+
+    #[test]
+    fn test_interp_program_stack_overflow() {
+        let mut env = Env::new();
+        let mut result = Ok(Types::Unit);
+        for program in crate::twig_parser::parse_file("fn f(n) { f(n + 1) }\nf(0)").unwrap() {
+            result = interp_program(&program, &mut env);
+        }
+        let e = result.unwrap_err();
+
+        assert_eq!(e.kind(), "StackOverflow");
+        assert_eq!(e.trace().unwrap().len(), MAX_CALL_DEPTH);
+        assert!(env.frames.is_empty());
+    }
+
+    #[test]
+    fn test_interp_program_undefined_suggests_variable() {
+        let mut env = Env::new();
+        env.insert(String::from("total"), Types::Integer(1))
+            .unwrap();
+        env.push_scope(Scope::new());
+        env.insert(String::from("count"), Types::Integer(2))
+            .unwrap();
+
+        let result = interp_program(&ExprKind::Identifier(String::from("totl")).into(), &mut env);
+        assert_eq!(result.unwrap_err(), undefined("totl", Some("total")));
+        let result = interp_program(&ExprKind::Identifier(String::from("cont")).into(), &mut env);
+        assert_eq!(result.unwrap_err(), undefined("cont", Some("count")));
+        let result = interp_program(&ExprKind::Identifier(String::from("zzz")).into(), &mut env);
+        assert_eq!(result.unwrap_err(), undefined("zzz", None));
+    }
+
+    #[test]
+    fn test_interp_program_undefined_suggests_builtin() {
+        let mut env = Env::new();
+
+        let result = interp_program(&ExprKind::Identifier(String::from("prnt")).into(), &mut env);
+
+        assert_eq!(result.unwrap_err(), undefined("prnt", Some("print")));
+        assert_eq!(
+            undefined("prnt", Some("print")).to_string(),
+            "Undefined variable `prnt`; did you mean `print`?"
+        );
+    }
+
+    #[test]
+    fn test_interp_program_internal_errors() {
+        let mut env = point_env();
+        let e: Expression = ExprKind::ImplBlock {
+            type_name: String::from("Point"),
+            methods: vec![ExprKind::Integer(1).into()],
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap_err();
+
+        assert_eq!(result.kind(), "Internal");
+        assert_eq!(
+            result.to_string(),
+            "Internal error: an `impl` block holds something other than a function"
+        );
+        let e: Expression = ExprKind::Export(Box::new(ExprKind::Integer(1).into())).into();
+        assert_eq!(interp_program(&e, &mut env).unwrap_err().kind(), "Internal");
+        // A local the resolver put in a slot that isn't there isn't looked up by name instead.
+        env.declare(String::from("x"), Types::Integer(1), false)
+            .unwrap();
+        let e: Expression = ExprKind::Local {
+            name: String::from("x"),
+            depth: 0,
+            slot: 0,
+        }
+        .into();
+        assert_eq!(
+            interp_program(&e, &mut env).unwrap_err().to_string(),
+            "Internal error: `x` isn't in the slot it was resolved to"
+        );
+    }
 
-        assert_eq!(result, InterpErrors::MismatchedTypeError);
+    #[test]
+    fn test_closest() {
+        let names = ["total", "totals", "other"];
+
+        assert_eq!(closest("totl", names.into_iter()), Some("total"));
+        assert_eq!(closest("totalss", names.into_iter()), Some("totals"));
+        assert_eq!(closest("x", names.into_iter()), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
     }
 }
@@ -0,0 +1,502 @@
+use core::fmt;
+
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+use crate::twig_parser::{Span, too_big};
+
+/// The words and symbols a TwigScript program is made of. Spaces and comments are dropped, apart
+/// from line breaks, which end statements everywhere but inside brackets.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Integer(i32),
+    Identifier(String),
+    /// The text between the quotes.
+    String(String),
+    /// A `///` line without its slashes. It takes the line break after it, since it belongs to
+    /// the declaration on the next line.
+    DocComment(String),
+    Let,
+    Const,
+    Fn,
+    Struct,
+    Enum,
+    Match,
+    Impl,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    Return,
+    Import,
+    Export,
+    As,
+    True,
+    False,
+    Nil,
+    /// `_` on its own, which is a pattern rather than a name.
+    Underscore,
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    Comma,
+    Colon,
+    DoubleColon,
+    Semicolon,
+    Dot,
+    QuestionDot,
+    Question,
+    DoubleQuestion,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    DoubleEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Equal,
+    FatArrow,
+    ThinArrow,
+    Newline,
+    /// Text that can't start a token, with what's wrong with it.
+    Invalid(String),
+    Eof,
+}
+
+const KEYWORDS: [(&str, Token); 18] = [
+    ("let", Token::Let),
+    ("const", Token::Const),
+    ("fn", Token::Fn),
+    ("struct", Token::Struct),
+    ("enum", Token::Enum),
+    ("match", Token::Match),
+    ("impl", Token::Impl),
+    ("throw", Token::Throw),
+    ("try", Token::Try),
+    ("catch", Token::Catch),
+    ("finally", Token::Finally),
+    ("return", Token::Return),
+    ("import", Token::Import),
+    ("export", Token::Export),
+    ("as", Token::As),
+    ("true", Token::True),
+    ("false", Token::False),
+    ("nil", Token::Nil),
+];
+
+/// How a token is shown in a syntax error.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Token::Integer(i) => return write!(f, "`{}`", i),
+            Token::Identifier(name) => return write!(f, "`{}`", name),
+            Token::String(s) => return write!(f, "`\"{}\"`", s),
+            Token::DocComment(_) => return write!(f, "a doc comment"),
+            Token::Newline => return write!(f, "a line break"),
+            Token::Invalid(_) => return write!(f, "invalid input"),
+            Token::Eof => return write!(f, "the end of the input"),
+            Token::Underscore => "_",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::DoubleColon => "::",
+            Token::Semicolon => ";",
+            Token::Dot => ".",
+            Token::QuestionDot => "?.",
+            Token::Question => "?",
+            Token::DoubleQuestion => "??",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::DoubleEqual => "==",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Equal => "=",
+            Token::FatArrow => "=>",
+            Token::ThinArrow => "->",
+            keyword => KEYWORDS
+                .iter()
+                .find(|(_, token)| token == keyword)
+                .map_or("?", |(word, _)| word),
+        };
+        write!(f, "`{}`", symbol)
+    }
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn rest(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    /// Moves past the next character. Lines and columns are counted the way pest counts them, so
+    /// `\r\n` is one line break and a `\r` on its own is just another character.
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        match c {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+            }
+            '\r' if self.peek() == Some('\n') => {}
+            _ => self.column += 1,
+        }
+        Some(c)
+    }
+
+    fn bump_while(&mut self, f: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&f) {
+            self.bump();
+        }
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.rest().starts_with(text);
+        if found {
+            text.chars().for_each(|_| {
+                self.bump();
+            });
+        }
+        found
+    }
+
+    fn span_from(&self, start: (usize, usize, usize)) -> Span {
+        let (start, line, column) = start;
+        Span {
+            start,
+            end: self.pos,
+            line,
+            column,
+        }
+    }
+
+    fn eat_newline(&mut self) -> bool {
+        self.eat("\r\n") || self.eat("\n") || self.eat("\r")
+    }
+
+    /// Skips a `/* */` comment, which can have others inside it. Returns whether it was closed.
+    fn block_comment(&mut self) -> bool {
+        let mut depth = 0;
+        loop {
+            if self.eat("/*") {
+                depth += 1;
+            } else if self.eat("*/") {
+                depth -= 1;
+                if depth == 0 {
+                    return true;
+                }
+            } else if self.bump().is_none() {
+                return false;
+            }
+        }
+    }
+
+    fn string(&mut self) -> Token {
+        self.bump();
+        match self.rest().find('"') {
+            Some(len) => {
+                let content = String::from(&self.rest()[..len]);
+                self.bump_while(|c| c != '"');
+                self.bump();
+                Token::String(content)
+            }
+            // Taking the rest of the file would hide every error after it, so only the line goes.
+            None => {
+                self.bump_while(|c| c != '\n' && c != '\r');
+                Token::Invalid(String::from("this string is missing its closing `\"`"))
+            }
+        }
+    }
+
+    fn word(&mut self) -> Token {
+        let start = self.pos;
+        self.bump();
+        self.bump_while(is_xid_continue);
+        let word = &self.input[start..self.pos];
+        match KEYWORDS.iter().find(|(keyword, _)| *keyword == word) {
+            Some((_, token)) => token.clone(),
+            None if word == "_" => Token::Underscore,
+            None => Token::Identifier(String::from(word)),
+        }
+    }
+
+    fn integer(&mut self) -> Token {
+        let start = self.pos;
+        self.bump_while(|c| c.is_ascii_digit());
+        let digits = &self.input[start..self.pos];
+        match digits.parse() {
+            Ok(i) => Token::Integer(i),
+            Err(_) => Token::Invalid(too_big(digits)),
+        }
+    }
+
+    fn symbol(&mut self) -> Token {
+        const SYMBOLS: [(&str, Token); 29] = [
+            ("::", Token::DoubleColon),
+            ("?.", Token::QuestionDot),
+            ("??", Token::DoubleQuestion),
+            ("==", Token::DoubleEqual),
+            (">=", Token::GreaterEqual),
+            ("<=", Token::LessEqual),
+            ("=>", Token::FatArrow),
+            ("->", Token::ThinArrow),
+            ("(", Token::LeftParen),
+            (")", Token::RightParen),
+            ("[", Token::LeftBracket),
+            ("]", Token::RightBracket),
+            ("{", Token::LeftBrace),
+            ("}", Token::RightBrace),
+            (",", Token::Comma),
+            (":", Token::Colon),
+            (";", Token::Semicolon),
+            (".", Token::Dot),
+            ("?", Token::Question),
+            ("+", Token::Plus),
+            ("-", Token::Minus),
+            ("*", Token::Star),
+            ("/", Token::Slash),
+            (">", Token::Greater),
+            ("<", Token::Less),
+            ("=", Token::Equal),
+            ("\r\n", Token::Newline),
+            ("\n", Token::Newline),
+            ("\r", Token::Newline),
+        ];
+        for (text, token) in &SYMBOLS {
+            if self.eat(text) {
+                return token.clone();
+            }
+        }
+        let c = self.bump().unwrap_or_default();
+        Token::Invalid(format!("`{}` isn't part of TwigScript", c))
+    }
+
+    /// The next token, or `None` for spaces and comments.
+    fn token(&mut self) -> Option<Token> {
+        let rest = self.rest();
+        let c = self.peek()?;
+        let token = if rest.starts_with("///") && !rest.starts_with("////") && documents(rest) {
+            self.eat("///");
+            let start = self.pos;
+            self.bump_while(|c| c != '\n' && c != '\r');
+            let text = String::from(&self.input[start..self.pos]);
+            self.eat_newline();
+            Token::DocComment(text)
+        } else if rest.starts_with("//") {
+            self.bump_while(|c| c != '\n' && c != '\r');
+            return None;
+        } else if rest.starts_with("/*") {
+            if self.block_comment() {
+                return None;
+            }
+            Token::Invalid(String::from("this comment is missing its closing `*/`"))
+        } else if c == '"' {
+            self.string()
+        } else if c.is_ascii_digit() {
+            self.integer()
+        } else if is_xid_start(c) || c == '_' {
+            self.word()
+        } else if c.is_whitespace() && c != '\n' && c != '\r' {
+            self.bump();
+            return None;
+        } else {
+            self.symbol()
+        };
+        Some(token)
+    }
+}
+
+/// Splits source code into tokens, each with the span it came from. The last one is always
+/// `Eof`, and text that isn't valid becomes an `Invalid` token rather than stopping the lexer, so
+/// the parser can report it where it's found.
+pub fn tokenize(input: &str) -> Vec<(Token, Span)> {
+    let mut lexer = Lexer {
+        input,
+        pos: 0,
+        line: 1,
+        column: 1,
+    };
+    let mut tokens = Vec::new();
+    while lexer.pos < input.len() {
+        let start = (lexer.pos, lexer.line, lexer.column);
+        if let Some(token) = lexer.token() {
+            tokens.push((token, lexer.span_from(start)));
+        }
+    }
+    let end = (lexer.pos, lexer.line, lexer.column);
+    tokens.push((Token::Eof, lexer.span_from(end)));
+    tokens
+}
+
+/// Whether the `///` line at the front of `rest` leads up to a `let`, `const`, `fn` or `export` on
+/// the next line, maybe through more `///` lines. Any other `///` line has nothing to document, so
+/// it's just a comment.
+fn documents(mut rest: &str) -> bool {
+    loop {
+        let Some(end) = rest.find(['\n', '\r']) else {
+            return false;
+        };
+        let next = match rest[end..].starts_with("\r\n") {
+            true => &rest[end + 2..],
+            false => &rest[end + 1..],
+        };
+        let next = next.trim_start_matches(|c: char| c.is_whitespace() && c != '\n' && c != '\r');
+        if next.starts_with("///") && !next.starts_with("////") {
+            rest = next;
+            continue;
+        }
+        let word = next
+            .find(|c| !is_xid_continue(c))
+            .map_or(next, |end| &next[..end]);
+        return matches!(word, "let" | "const" | "fn" | "export");
+    }
+}
+
+#[cfg(test)]
+mod twig_lexer_tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Token> {
+        tokenize(input)
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_let() {
+        let result = tokens("let x = 10 // ten");
+        let expected = [
+            Token::Let,
+            Token::Identifier(String::from("x")),
+            Token::Equal,
+            Token::Integer(10),
+            Token::Eof,
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_tokenize_symbols() {
+        let result = tokens("a?.b ?? c? :: => -> >= <= == /");
+        let expected = [
+            Token::Identifier(String::from("a")),
+            Token::QuestionDot,
+            Token::Identifier(String::from("b")),
+            Token::DoubleQuestion,
+            Token::Identifier(String::from("c")),
+            Token::Question,
+            Token::DoubleColon,
+            Token::FatArrow,
+            Token::ThinArrow,
+            Token::GreaterEqual,
+            Token::LessEqual,
+            Token::DoubleEqual,
+            Token::Slash,
+            Token::Eof,
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_tokenize_words() {
+        let result = tokens("_ _a letter nil café");
+        let expected = [
+            Token::Underscore,
+            Token::Identifier(String::from("_a")),
+            Token::Identifier(String::from("letter")),
+            Token::Nil,
+            Token::Identifier(String::from("café")),
+            Token::Eof,
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        let result = tokens("/* a /* nested */ comment */\n//// not a doc\n/// Doc.\nfn");
+        let expected = [
+            Token::Newline,
+            Token::Newline,
+            Token::DocComment(String::from(" Doc.")),
+            Token::Fn,
+            Token::Eof,
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_tokenize_stray_doc_comments() {
+        let result = tokens("/// Stray.\n1 /// After.\n/// Apart.\n\nfn");
+        let expected = [
+            Token::Newline,
+            Token::Integer(1),
+            Token::Newline,
+            Token::Newline,
+            Token::Newline,
+            Token::Fn,
+            Token::Eof,
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let result = tokenize("é \"ab\"\r\n  x");
+        let spans: Vec<_> = result
+            .iter()
+            .map(|(_, span)| (span.start, span.end, span.line, span.column))
+            .collect();
+
+        assert_eq!(
+            spans,
+            [
+                (0, 2, 1, 1),
+                (3, 7, 1, 3),
+                (7, 9, 1, 7),
+                (11, 12, 2, 3),
+                (12, 12, 2, 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_invalid() {
+        let result = tokens("1 # \"open\n99999999999 /* open");
+
+        assert!(matches!(result[1], Token::Invalid(_)));
+        assert!(matches!(result[2], Token::Invalid(_)));
+        assert_eq!(result[3], Token::Newline);
+        assert!(matches!(result[4], Token::Invalid(_)));
+        assert!(matches!(result[5], Token::Invalid(_)));
+        assert_eq!(result[6], Token::Eof);
+    }
+}
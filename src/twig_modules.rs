@@ -0,0 +1,417 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::twig_descent::parse_statements;
+use crate::twig_interp::{Env, InterpErrors, Methods, Scope, Types, interp_program};
+use crate::twig_parser::parse_file;
+use crate::twig_resolver::resolve_program;
+use crate::twig_types::check_program;
+use crate::twig_vm::run_program;
+
+/// A file loaded with `import`. Only the names it exported can be reached through it.
+#[derive(Debug, PartialEq)]
+pub struct Module {
+    pub path: PathBuf,
+    pub exports: BTreeMap<String, Types>,
+}
+
+#[derive(Debug)]
+struct LoadedModule {
+    module: Rc<Module>,
+    globals: Rc<Scope>,
+    methods: Methods,
+}
+
+/// Every file is evaluated once, the first time it is imported, and shared by everything that
+/// imports it after that. `loading` is the chain of imports currently being evaluated and is used
+/// to catch cycles. With `strict` set, every file has to pass the strict type checker, and with
+/// `pest` set files are parsed with the pest grammar instead of the hand-written parser. With `vm`
+/// set, files are compiled to bytecode and run on the VM rather than walked.
+#[derive(Debug, Default)]
+pub struct ModuleLoader {
+    loaded: BTreeMap<PathBuf, LoadedModule>,
+    loading: Vec<PathBuf>,
+    strict: bool,
+    pest: bool,
+    vm: bool,
+}
+
+impl ModuleLoader {
+    pub(crate) fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub(crate) fn set_pest(&mut self, pest: bool) {
+        self.pest = pest;
+    }
+
+    pub(crate) fn set_vm(&mut self, vm: bool) {
+        self.vm = vm;
+    }
+
+    pub(crate) fn globals(&self, path: &Path) -> Option<Rc<Scope>> {
+        self.loaded.get(path).map(|loaded| loaded.globals.clone())
+    }
+}
+
+/// Imports a file relative to the one being run, or to the working directory from the REPL.
+pub fn import(path: &str, env: &mut Env) -> Result<Rc<Module>, InterpErrors> {
+    let path = match env.module().and_then(|module| module.parent()) {
+        Some(dir) => dir.join(path),
+        None => PathBuf::from(path),
+    };
+
+    let loader = env.loader();
+    // Where an error happened inside the imported file means nothing next to the importing one's
+    // source, so it's reported at the import instead.
+    let module = load(&path, &loader).map_err(InterpErrors::without_span)?;
+    if let Some(loaded) = loader.borrow().loaded.get(&module.path) {
+        env.merge_methods(&loaded.methods);
+    }
+
+    Ok(module)
+}
+
+/// Runs a script file as the entry point of a program.
+pub fn run_file(
+    path: &Path,
+    strict: bool,
+    pest: bool,
+    vm: bool,
+) -> Result<Rc<Module>, InterpErrors> {
+    let loader = ModuleLoader {
+        strict,
+        pest,
+        vm,
+        ..ModuleLoader::default()
+    };
+    load(path, &Rc::new(RefCell::new(loader)))
+}
+
+fn load(path: &Path, loader: &Rc<RefCell<ModuleLoader>>) -> Result<Rc<Module>, InterpErrors> {
+    let not_found = || InterpErrors::ModuleNotFound(path.display().to_string());
+    let path = path.canonicalize().map_err(|_| not_found())?;
+
+    if let Some(loaded) = loader.borrow().loaded.get(&path) {
+        return Ok(loaded.module.clone());
+    }
+    if let Some(start) = loader.borrow().loading.iter().position(|p| *p == path) {
+        let chain = loader.borrow().loading[start..]
+            .iter()
+            .chain([&path])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(InterpErrors::CyclicImport(chain));
+    }
+
+    let source = fs::read_to_string(&path).map_err(|_| not_found())?;
+    let program = match loader.borrow().pest {
+        true => parse_file(&source).map_err(|errors| {
+            let path = path.display().to_string();
+            let errors: Vec<String> = errors
+                .into_iter()
+                .map(|e| e.error.with_path(&path).to_string())
+                .collect();
+            errors.join("\n")
+        }),
+        false => parse_statements(&source).map_err(|errors| {
+            let errors: Vec<String> = errors
+                .iter()
+                .map(|e| format!("{}: {}", path.display(), e))
+                .collect();
+            errors.join("\n")
+        }),
+    };
+    let mut program = program.map_err(InterpErrors::ModuleParseError)?;
+    let strict = loader.borrow().strict;
+    check_program(&program, strict).map_err(|errors| {
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|e| format!("{}: {}", path.display(), e))
+            .collect();
+        InterpErrors::ModuleTypeError(errors.join("\n"))
+    })?;
+
+    let mut env = Env::for_module(path.clone(), loader.clone());
+    // A name that doesn't exist anywhere is reported before any of the file runs, like an error
+    // from running it would be.
+    resolve_program(&mut program, &env).map_err(|mut errors| errors.swap_remove(0))?;
+
+    loader.borrow_mut().loading.push(path.clone());
+    let vm = loader.borrow().vm;
+    let result = program.iter().try_for_each(|stmt| match vm {
+        true => run_program(stmt, &mut env).map(|_| ()),
+        false => interp_program(stmt, &mut env).map(|_| ()),
+    });
+    loader.borrow_mut().loading.pop();
+
+    match result {
+        // A `return` at the top of a file just stops running it.
+        Ok(()) | Err(InterpErrors::Return(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    let (globals, methods, exports) = env.into_module_parts();
+    let module = Rc::new(Module {
+        path: path.clone(),
+        exports,
+    });
+    let loaded = LoadedModule {
+        module: module.clone(),
+        globals,
+        methods,
+    };
+    loader.borrow_mut().loaded.insert(path, loaded);
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod twig_modules_tests {
+    use super::*;
+    use crate::twig_parser::{ExprKind, Expression};
+    use tempfile::TempDir;
+
+    /// Writes each `(name, source)` pair into a fresh directory, which is deleted once the returned
+    /// `TempDir` is dropped at the end of the test.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("twigscript_{}_", test))
+            .tempdir()
+            .unwrap();
+        for (name, source) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_import_exports() {
+        let dir = write_files(
+            "exports",
+            &[(
+                "math.twig",
+                "fn helper(n) { n * 2 }\nexport fn double(n) { helper(n) }\nexport let ten = 10",
+            )],
+        );
+        let main = dir.path().join("main.twig");
+        let mut env = Env::for_module(main, Rc::new(RefCell::new(ModuleLoader::default())));
+
+        let module = import("math.twig", &mut env).unwrap();
+
+        assert_eq!(module.exports.len(), 2);
+        assert_eq!(module.exports.get("ten"), Some(&Types::Integer(10)));
+        assert_eq!(module.exports.get("helper"), None);
+    }
+
+    #[test]
+    fn test_import_calls_run_in_their_own_file() {
+        let dir = write_files(
+            "calls",
+            &[
+                (
+                    "lib/math.twig",
+                    "fn helper(n) { n * 2 }\nexport fn double(n) { helper(n) }",
+                ),
+                (
+                    "main.twig",
+                    "import \"lib/math.twig\" as m\nlet x = m.double(4)",
+                ),
+            ],
+        );
+        let loader = Rc::new(RefCell::new(ModuleLoader::default()));
+        let mut env = Env::for_module(dir.path().join("main.twig"), loader);
+        let e: Expression = ExprKind::Import {
+            path: String::from("lib/math.twig"),
+            alias: String::from("m"),
+        }
+        .into();
+        interp_program(&e, &mut env).unwrap();
+        let e: Expression = ExprKind::MethodCall {
+            receiver: Box::new(ExprKind::Identifier(String::from("m")).into()),
+            method: String::from("double"),
+            args: vec![ExprKind::Integer(4).into()],
+            optional: false,
+        }
+        .into();
+
+        let result = interp_program(&e, &mut env).unwrap();
+
+        assert_eq!(result, Types::Integer(8));
+    }
+
+    #[test]
+    fn test_import_is_cached() {
+        let dir = write_files("cached", &[("counter.twig", "export let n = 1")]);
+        let mut env = Env::for_module(
+            dir.path().join("main.twig"),
+            Rc::new(RefCell::new(ModuleLoader::default())),
+        );
+
+        let first = import("counter.twig", &mut env).unwrap();
+        let second = import("./counter.twig", &mut env).unwrap();
+
+        assert!(Rc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_import_cycle() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("a.twig", "import \"b.twig\" as b"),
+                ("b.twig", "import \"a.twig\" as a"),
+            ],
+        );
+
+        let result = run_file(&dir.path().join("a.twig"), false, false, false)
+            .unwrap_err()
+            .without_span();
+
+        let a = dir.path().join("a.twig").canonicalize().unwrap();
+        let b = dir.path().join("b.twig").canonicalize().unwrap();
+        let chain = [&a, &b, &a].map(|p| p.display().to_string()).to_vec();
+        assert_eq!(result, InterpErrors::CyclicImport(chain));
+    }
+
+    #[test]
+    fn test_import_missing_file() {
+        let mut env = Env::new();
+
+        let result = import("does/not/exist.twig", &mut env).unwrap_err();
+
+        assert_eq!(
+            result,
+            InterpErrors::ModuleNotFound(String::from("does/not/exist.twig"))
+        );
+    }
+
+    #[test]
+    fn test_run_file_type_error() {
+        let dir = write_files(
+            "type_error",
+            &[("main.twig", "print(\"started\")\nlet n: int = \"one\"")],
+        );
+        let path = dir.path().join("main.twig").canonicalize().unwrap();
+
+        let result = run_file(&path, false, false, false).unwrap_err();
+
+        let message = format!(
+            "{}: Mismatched types in the declaration of `n`: `\"one\"` is string, expected int",
+            path.display()
+        );
+        assert_eq!(result, InterpErrors::ModuleTypeError(message));
+    }
+
+    #[test]
+    fn test_run_file_reports_unresolved_names_before_running() {
+        let source = "import \"b.twig\" as b\nprint(mising)";
+        let dir = write_files("unresolved", &[("a.twig", source), ("b.twig", "1 / 0")]);
+
+        // The import would fail if it ran, but the name that doesn't exist is found first.
+        let e = run_file(&dir.path().join("a.twig"), false, false, false).unwrap_err();
+        assert_eq!(e.kind(), "VariableDoesNotExist");
+        assert_eq!(e.span().map(|span| (span.line, span.column)), Some((2, 7)));
+    }
+
+    #[test]
+    fn test_run_file_strict() {
+        let dir = write_files("strict", &[("main.twig", "fn inc(n) { n + 1 }\ninc(true)")]);
+        let path = dir.path().join("main.twig").canonicalize().unwrap();
+
+        // Without `--strict` the call is only caught once it runs.
+        let InterpErrors::Traced(frames, e) = run_file(&path, false, false, false).unwrap_err()
+        else {
+            panic!("expected an error from inside `inc`");
+        };
+        assert_eq!(frames[0].function, "inc");
+        assert_eq!(
+            e.without_span(),
+            InterpErrors::InvalidTypeError(String::from(
+                "`+` needs two integers, found bool and int"
+            ))
+        );
+
+        let message = format!(
+            "{}: Mismatched types in `inc(true)`: `true` is bool, expected int",
+            path.display()
+        );
+        assert_eq!(
+            run_file(&path, true, false, false).unwrap_err(),
+            InterpErrors::ModuleTypeError(message)
+        );
+    }
+
+    #[test]
+    fn test_run_file_stack_trace_across_files() {
+        let dir = write_files(
+            "stack_trace",
+            &[
+                ("lib.twig", "export fn div(n) { 10 / n }"),
+                (
+                    "main.twig",
+                    "import \"lib.twig\" as lib\nfn go(n) { lib.div(n) }\ngo(0)",
+                ),
+            ],
+        );
+        let path = dir.path().join("main.twig").canonicalize().unwrap();
+
+        let e = run_file(&path, false, false, false).unwrap_err();
+
+        let trace: Vec<_> = e
+            .trace()
+            .unwrap()
+            .iter()
+            .map(|frame| {
+                (
+                    frame.function.as_str(),
+                    frame.span.unwrap().line,
+                    &frame.file,
+                )
+            })
+            .collect();
+        let file = Some(path.clone());
+        assert_eq!(trace, [("go", 3, &file), ("div", 2, &file)]);
+        // The error itself is in `lib.twig`, so it's shown where `go` calls into it.
+        assert_eq!(e.span().unwrap().line, 2);
+    }
+
+    #[test]
+    fn test_run_file_reports_every_syntax_error() {
+        let dir = write_files(
+            "syntax_errors",
+            &[("main.twig", "let a = *\nprint(a)\nb = 2")],
+        );
+        let path = dir.path().join("main.twig").canonicalize().unwrap();
+
+        let InterpErrors::ModuleParseError(message) =
+            run_file(&path, false, false, false).unwrap_err()
+        else {
+            panic!("expected a parse error");
+        };
+
+        let lines: Vec<_> = message.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("found `*` at line 1, column 9"));
+        assert!(lines[1].ends_with("found `=` at line 3, column 3"));
+
+        // The pest grammar finds the same errors.
+        let InterpErrors::ModuleParseError(message) =
+            run_file(&path, false, true, false).unwrap_err()
+        else {
+            panic!("expected a parse error");
+        };
+        let locations: Vec<_> = message
+            .lines()
+            .filter(|line| line.contains("-->"))
+            .collect();
+        assert_eq!(locations.len(), 2);
+        assert!(locations[0].ends_with(":1:9"));
+        assert!(locations[1].ends_with(":3:3"));
+    }
+}
@@ -1,13 +1,43 @@
-use crate::Rule;
+use core::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::{GrammarParser, Rule};
+use pest::Parser;
+use pest::Position;
+use pest::error::{ErrorVariant, InputLocation};
 use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::PrattParser;
 
-#[derive(Debug, PartialEq)]
-pub enum Expression {
+/// A node of the syntax tree along with where it was parsed from. Syntax trees built by hand can
+/// leave the span out.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Node<T> {
+    pub kind: T,
+    pub span: Option<Span>,
+}
+
+pub type Expression = Node<ExprKind>;
+pub type Pattern = Node<PatternKind>;
+/// A type annotation as written. They are only read by the type checker and have no effect on how
+/// a program runs.
+pub type TypeExpr = Node<TypeKind>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
     Integer(i32),
     Boolean(bool),
     String(String),
+    Nil,
+    List(Vec<Expression>),
     Identifier(String),
+    /// A local variable found by the resolver, declared `depth` scopes out from the innermost one
+    /// in the given slot of that scope. The name is kept for error messages.
+    Local {
+        name: String,
+        depth: usize,
+        slot: usize,
+    },
     MathOp {
         lhs: Box<Expression>,
         op: MathOperator,
@@ -18,16 +48,369 @@ pub enum Expression {
         op: BooleanOperator,
         rhs: Box<Expression>,
     },
+    Coalesce {
+        lhs: Box<Expression>,
+        rhs: Box<Expression>,
+    },
+    Access {
+        target: Box<Expression>,
+        accessor: Accessor,
+        optional: bool,
+    },
+    Call {
+        callee: Box<Expression>,
+        args: Vec<Expression>,
+    },
+    MethodCall {
+        receiver: Box<Expression>,
+        method: String,
+        args: Vec<Expression>,
+        optional: bool,
+    },
+    StructLiteral {
+        name: String,
+        fields: Vec<(String, Expression)>,
+    },
+    Variant {
+        enum_name: String,
+        variant: String,
+    },
+    Match {
+        scrutinee: Box<Expression>,
+        arms: Vec<(Pattern, Expression)>,
+    },
+    Block(Vec<Expression>),
+    Throw(Box<Expression>),
+    Return(Option<Box<Expression>>),
+    Propagate(Box<Expression>),
+    Try {
+        body: Box<Expression>,
+        catch: Option<(String, Box<Expression>)>,
+        finally: Option<Box<Expression>>,
+    },
     LetStmt {
+        doc: Option<String>,
         identifier: String,
         value: Box<Expression>,
+        /// Declared with `const`, so it can't be replaced in the same scope.
+        constant: bool,
+        annotation: Option<TypeExpr>,
+    },
+    FnDecl {
+        doc: Option<String>,
+        name: String,
+        /// The names of its generic type parameters, like `T` in `fn first<T>(xs: [T]) -> T`.
+        type_params: Vec<String>,
+        params: Vec<Annotated>,
+        return_type: Option<TypeExpr>,
+        body: Box<Expression>,
+    },
+    ImplBlock {
+        type_name: String,
+        methods: Vec<Expression>,
+    },
+    Import {
+        path: String,
+        alias: String,
+    },
+    Export(Box<Expression>),
+    StructDecl {
+        name: String,
+        type_params: Vec<String>,
+        fields: Vec<Annotated>,
+    },
+    EnumDecl {
+        name: String,
+        variants: Vec<(String, Vec<String>)>,
+    },
+}
+
+/// A stretch of source code, as byte offsets along with the line and column it starts on, both
+/// counted from 1.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatternKind {
+    Wildcard,
+    Binding(String),
+    Literal(Box<Expression>),
+    Variant {
+        enum_name: String,
+        variant: String,
+        fields: Vec<Pattern>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Accessor {
+    Field(String),
+    Index(Box<Expression>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TypeKind {
+    Named(String),
+    /// A generic struct with its type parameters filled in, like `Pair<int, bool>`.
+    Generic {
+        name: String,
+        args: Vec<TypeExpr>,
+    },
+    List(Box<TypeExpr>),
+    Function {
+        params: Vec<TypeExpr>,
+        ret: Box<TypeExpr>,
     },
 }
 
+/// A function parameter or struct field, with its type annotation if it has one.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Annotated {
+    pub name: String,
+    pub annotation: Option<TypeExpr>,
+    pub span: Option<Span>,
+}
+
+impl Span {
+    /// The empty span at the very start of the input.
+    pub(crate) const START: Span = Span {
+        start: 0,
+        end: 0,
+        line: 1,
+        column: 1,
+    };
+
+    fn of(pair: &Pair<Rule>) -> Span {
+        let span = pair.as_span();
+        let (line, column) = span.start_pos().line_col();
+        Span {
+            start: span.start(),
+            end: span.end(),
+            line,
+            column,
+        }
+    }
+
+    /// From the start of this span to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl<T> Node<T> {
+    pub fn new(kind: T, span: Span) -> Node<T> {
+        Node {
+            kind,
+            span: Some(span),
+        }
+    }
+}
+
+impl<T> From<T> for Node<T> {
+    fn from(kind: T) -> Node<T> {
+        Node { kind, span: None }
+    }
+}
+
+impl Expression {
+    /// The same syntax tree with every span taken out, as if it had been built by hand.
+    pub fn without_spans(self) -> Expression {
+        let strip = |expr: Box<Expression>| Box::new(expr.without_spans());
+        let strip_all =
+            |exprs: Vec<Expression>| exprs.into_iter().map(Expression::without_spans).collect();
+        let kind = match self.kind {
+            ExprKind::List(items) => ExprKind::List(strip_all(items)),
+            ExprKind::MathOp { lhs, op, rhs } => ExprKind::MathOp {
+                lhs: strip(lhs),
+                op,
+                rhs: strip(rhs),
+            },
+            ExprKind::BooleanOp { lhs, op, rhs } => ExprKind::BooleanOp {
+                lhs: strip(lhs),
+                op,
+                rhs: strip(rhs),
+            },
+            ExprKind::Coalesce { lhs, rhs } => ExprKind::Coalesce {
+                lhs: strip(lhs),
+                rhs: strip(rhs),
+            },
+            ExprKind::Access {
+                target,
+                accessor,
+                optional,
+            } => ExprKind::Access {
+                target: strip(target),
+                accessor: match accessor {
+                    Accessor::Index(index) => Accessor::Index(strip(index)),
+                    field => field,
+                },
+                optional,
+            },
+            ExprKind::Call { callee, args } => ExprKind::Call {
+                callee: strip(callee),
+                args: strip_all(args),
+            },
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+                optional,
+            } => ExprKind::MethodCall {
+                receiver: strip(receiver),
+                method,
+                args: strip_all(args),
+                optional,
+            },
+            ExprKind::StructLiteral { name, fields } => ExprKind::StructLiteral {
+                name,
+                fields: fields
+                    .into_iter()
+                    .map(|(field, value)| (field, value.without_spans()))
+                    .collect(),
+            },
+            ExprKind::Match { scrutinee, arms } => ExprKind::Match {
+                scrutinee: strip(scrutinee),
+                arms: arms
+                    .into_iter()
+                    .map(|(pattern, body)| (pattern.without_spans(), body.without_spans()))
+                    .collect(),
+            },
+            ExprKind::Block(stmts) => ExprKind::Block(strip_all(stmts)),
+            ExprKind::Throw(value) => ExprKind::Throw(strip(value)),
+            ExprKind::Return(value) => ExprKind::Return(value.map(strip)),
+            ExprKind::Propagate(value) => ExprKind::Propagate(strip(value)),
+            ExprKind::Try {
+                body,
+                catch,
+                finally,
+            } => ExprKind::Try {
+                body: strip(body),
+                catch: catch.map(|(name, handler)| (name, strip(handler))),
+                finally: finally.map(strip),
+            },
+            ExprKind::LetStmt {
+                doc,
+                identifier,
+                value,
+                constant,
+                annotation,
+            } => ExprKind::LetStmt {
+                doc,
+                identifier,
+                value: strip(value),
+                constant,
+                annotation: annotation.map(TypeExpr::without_spans),
+            },
+            ExprKind::FnDecl {
+                doc,
+                name,
+                type_params,
+                params,
+                return_type,
+                body,
+            } => ExprKind::FnDecl {
+                doc,
+                name,
+                type_params,
+                params: params.into_iter().map(Annotated::without_spans).collect(),
+                return_type: return_type.map(TypeExpr::without_spans),
+                body: strip(body),
+            },
+            ExprKind::ImplBlock { type_name, methods } => ExprKind::ImplBlock {
+                type_name,
+                methods: strip_all(methods),
+            },
+            ExprKind::Export(declaration) => ExprKind::Export(strip(declaration)),
+            ExprKind::StructDecl {
+                name,
+                type_params,
+                fields,
+            } => ExprKind::StructDecl {
+                name,
+                type_params,
+                fields: fields.into_iter().map(Annotated::without_spans).collect(),
+            },
+            kind => kind,
+        };
+        Expression::from(kind)
+    }
+}
+
+impl Pattern {
+    fn without_spans(self) -> Pattern {
+        let kind = match self.kind {
+            PatternKind::Literal(value) => PatternKind::Literal(Box::new(value.without_spans())),
+            PatternKind::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => PatternKind::Variant {
+                enum_name,
+                variant,
+                fields: fields.into_iter().map(Pattern::without_spans).collect(),
+            },
+            kind => kind,
+        };
+        Pattern::from(kind)
+    }
+}
+
+impl TypeExpr {
+    fn without_spans(self) -> TypeExpr {
+        let kind = match self.kind {
+            TypeKind::Generic { name, args } => TypeKind::Generic {
+                name,
+                args: args.into_iter().map(TypeExpr::without_spans).collect(),
+            },
+            TypeKind::List(item) => TypeKind::List(Box::new(item.without_spans())),
+            TypeKind::Function { params, ret } => TypeKind::Function {
+                params: params.into_iter().map(TypeExpr::without_spans).collect(),
+                ret: Box::new(ret.without_spans()),
+            },
+            kind => kind,
+        };
+        TypeExpr::from(kind)
+    }
+}
+
+impl Annotated {
+    /// A parameter or field written without a type annotation.
+    pub fn new(name: &str) -> Annotated {
+        Annotated {
+            name: String::from(name),
+            annotation: None,
+            span: None,
+        }
+    }
+
+    fn without_spans(self) -> Annotated {
+        Annotated {
+            annotation: self.annotation.map(TypeExpr::without_spans),
+            span: None,
+            ..self
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
-enum Operator {
+pub(crate) enum Operator {
     Math(MathOperator),
     Bool(BooleanOperator),
+    Coalesce,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -47,8 +430,327 @@ pub enum BooleanOperator {
     LessThanEqual,
 }
 
-fn get_operator(op: Pair<Rule>) -> Operator {
-    match op.as_rule() {
+fn comma_separated<T: fmt::Display>(items: impl IntoIterator<Item = T>) -> String {
+    let items: Vec<String> = items.into_iter().map(|item| item.to_string()).collect();
+    items.join(", ")
+}
+
+impl fmt::Display for MathOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MathOperator::Add => write!(f, "+"),
+            MathOperator::Subtract => write!(f, "-"),
+            MathOperator::Multiply => write!(f, "*"),
+            MathOperator::Divide => write!(f, "/"),
+        }
+    }
+}
+
+impl fmt::Display for BooleanOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BooleanOperator::Eqaul => write!(f, "=="),
+            BooleanOperator::GreaterThan => write!(f, ">"),
+            BooleanOperator::GreaterThanEqual => write!(f, ">="),
+            BooleanOperator::LessThan => write!(f, "<"),
+            BooleanOperator::LessThanEqual => write!(f, "<="),
+        }
+    }
+}
+
+impl fmt::Display for TypeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeKind::Named(name) => write!(f, "{}", name),
+            TypeKind::Generic { name, args } => write!(f, "{}<{}>", name, comma_separated(args)),
+            TypeKind::List(item) => write!(f, "[{}]", item),
+            TypeKind::Function { params, ret } => {
+                write!(f, "fn({}) -> {}", comma_separated(params), ret)
+            }
+        }
+    }
+}
+
+impl fmt::Display for PatternKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternKind::Wildcard => write!(f, "_"),
+            PatternKind::Binding(name) => write!(f, "{}", name),
+            PatternKind::Literal(value) => write!(f, "{}", value),
+            PatternKind::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                match enum_name.as_str() {
+                    "Result" => write!(f, "{}", variant)?,
+                    _ => write!(f, "{}::{}", enum_name, variant)?,
+                }
+                match fields.is_empty() {
+                    true => Ok(()),
+                    false => write!(f, "({})", comma_separated(fields)),
+                }
+            }
+        }
+    }
+}
+
+impl ExprKind {
+    /// How tightly an expression binds, used to put back the brackets the parser dropped.
+    fn precedence(&self) -> u8 {
+        match self {
+            ExprKind::Coalesce { .. } => 1,
+            ExprKind::BooleanOp { .. } => 2,
+            ExprKind::MathOp {
+                op: MathOperator::Add | MathOperator::Subtract,
+                ..
+            } => 3,
+            ExprKind::MathOp { .. } => 4,
+            _ => 5,
+        }
+    }
+}
+
+/// Writes `operand` in brackets when it binds less tightly than `precedence`.
+fn fmt_operand(f: &mut fmt::Formatter<'_>, operand: &Expression, precedence: u8) -> fmt::Result {
+    match operand.kind.precedence() < precedence {
+        true => write!(f, "({})", operand),
+        false => write!(f, "{}", operand),
+    }
+}
+
+fn fmt_binary(
+    f: &mut fmt::Formatter<'_>,
+    expr: &ExprKind,
+    lhs: &Expression,
+    op: &dyn fmt::Display,
+    rhs: &Expression,
+) -> fmt::Result {
+    let precedence = expr.precedence();
+    fmt_operand(f, lhs, precedence)?;
+    write!(f, " {} ", op)?;
+    // Operators group to the left, so an operand of the same precedence on the right needs brackets.
+    fmt_operand(f, rhs, precedence + 1)
+}
+
+fn annotated(names: &[Annotated]) -> String {
+    comma_separated(names.iter().map(|name| match &name.annotation {
+        Some(ty) => format!("{}: {}", name.name, ty),
+        None => name.name.clone(),
+    }))
+}
+
+fn fmt_type_params(f: &mut fmt::Formatter<'_>, type_params: &[String]) -> fmt::Result {
+    match type_params.is_empty() {
+        true => Ok(()),
+        false => write!(f, "<{}>", type_params.join(", ")),
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Node<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+/// Writes an expression back out as source code, which is how error messages point at it.
+impl fmt::Display for ExprKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprKind::Integer(i) => write!(f, "{}", i),
+            ExprKind::Boolean(b) => write!(f, "{}", b),
+            ExprKind::String(s) => write!(f, "\"{}\"", s),
+            ExprKind::Nil => write!(f, "nil"),
+            ExprKind::List(items) => write!(f, "[{}]", comma_separated(items)),
+            ExprKind::Identifier(name) | ExprKind::Local { name, .. } => write!(f, "{}", name),
+            ExprKind::MathOp { lhs, op, rhs } => fmt_binary(f, self, lhs, op, rhs),
+            ExprKind::BooleanOp { lhs, op, rhs } => fmt_binary(f, self, lhs, op, rhs),
+            ExprKind::Coalesce { lhs, rhs } => fmt_binary(f, self, lhs, &"??", rhs),
+            ExprKind::Access {
+                target,
+                accessor,
+                optional,
+            } => {
+                fmt_operand(f, target, 5)?;
+                match (accessor, optional) {
+                    (Accessor::Field(field), false) => write!(f, ".{}", field),
+                    (Accessor::Field(field), true) => write!(f, "?.{}", field),
+                    (Accessor::Index(index), false) => write!(f, "[{}]", index),
+                    (Accessor::Index(index), true) => write!(f, "?.[{}]", index),
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                fmt_operand(f, callee, 5)?;
+                write!(f, "({})", comma_separated(args))
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+                optional,
+            } => {
+                fmt_operand(f, receiver, 5)?;
+                let dot = if *optional { "?." } else { "." };
+                write!(f, "{}{}({})", dot, method, comma_separated(args))
+            }
+            ExprKind::StructLiteral { name, fields } => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, value)| format!("{}: {}", field, value));
+                write!(f, "{} {{ {} }}", name, comma_separated(fields))
+            }
+            ExprKind::Variant { enum_name, variant } => write!(f, "{}::{}", enum_name, variant),
+            ExprKind::Match { scrutinee, arms } => {
+                let arms = arms
+                    .iter()
+                    .map(|(pattern, body)| format!("{} => {}", pattern, body));
+                write!(f, "match {} {{ {} }}", scrutinee, comma_separated(arms))
+            }
+            ExprKind::Block(stmts) => {
+                let stmts: Vec<String> = stmts.iter().map(|stmt| stmt.to_string()).collect();
+                write!(f, "{{ {} }}", stmts.join("; "))
+            }
+            ExprKind::Throw(value) => write!(f, "throw {}", value),
+            ExprKind::Return(Some(value)) => write!(f, "return {}", value),
+            ExprKind::Return(None) => write!(f, "return"),
+            ExprKind::Propagate(value) => {
+                fmt_operand(f, value, 5)?;
+                write!(f, "?")
+            }
+            ExprKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                write!(f, "try {}", body)?;
+                if let Some((name, handler)) = catch {
+                    write!(f, " catch {} {}", name, handler)?;
+                }
+                if let Some(finally) = finally {
+                    write!(f, " finally {}", finally)?;
+                }
+                Ok(())
+            }
+            ExprKind::LetStmt {
+                identifier,
+                value,
+                constant,
+                annotation,
+                ..
+            } => {
+                let keyword = if *constant { "const" } else { "let" };
+                write!(f, "{} {}", keyword, identifier)?;
+                if let Some(annotation) = annotation {
+                    write!(f, ": {}", annotation)?;
+                }
+                write!(f, " = {}", value)
+            }
+            ExprKind::FnDecl {
+                name,
+                type_params,
+                params,
+                return_type,
+                body,
+                ..
+            } => {
+                write!(f, "fn {}", name)?;
+                fmt_type_params(f, type_params)?;
+                write!(f, "({})", annotated(params))?;
+                if let Some(return_type) = return_type {
+                    write!(f, " -> {}", return_type)?;
+                }
+                write!(f, " {}", body)
+            }
+            ExprKind::ImplBlock { type_name, methods } => {
+                let methods: Vec<String> = methods.iter().map(|m| m.to_string()).collect();
+                write!(f, "impl {} {{ {} }}", type_name, methods.join(" "))
+            }
+            ExprKind::Import { path, alias } => write!(f, "import \"{}\" as {}", path, alias),
+            ExprKind::Export(declaration) => write!(f, "export {}", declaration),
+            ExprKind::StructDecl {
+                name,
+                type_params,
+                fields,
+            } => {
+                write!(f, "struct {}", name)?;
+                fmt_type_params(f, type_params)?;
+                write!(f, " {{ {} }}", annotated(fields))
+            }
+            ExprKind::EnumDecl { name, variants } => {
+                let variants = variants
+                    .iter()
+                    .map(|(variant, fields)| match fields.is_empty() {
+                        true => variant.clone(),
+                        false => format!("{}({})", variant, fields.join(", ")),
+                    });
+                write!(f, "enum {} {{ {} }}", name, comma_separated(variants))
+            }
+        }
+    }
+}
+
+/// A syntax error, or a parse tree the syntax tree couldn't be built from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// A guess at what was meant, when it looks like a common mistake.
+    pub hint: Option<String>,
+}
+
+impl ParseError {
+    fn new(message: String, span: Span) -> ParseError {
+        ParseError {
+            message,
+            span,
+            hint: None,
+        }
+    }
+
+    /// A pair that has no place where it was found, which means the grammar and the code building
+    /// the syntax tree from it disagree.
+    pub(crate) fn ended_early(span: Span) -> ParseError {
+        ParseError::new(String::from("the parse tree ended early"), span)
+    }
+
+    fn unexpected(pair: &Pair<Rule>) -> ParseError {
+        let message = format!("unexpected {:?} in the parse tree", pair.as_rule());
+        ParseError::new(message, Span::of(pair))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+type Parsed<T> = Result<T, ParseError>;
+
+/// The message for an integer literal that doesn't fit in an `i32`.
+pub(crate) fn too_big(digits: &str) -> String {
+    format!(
+        "`{}` is too big to be an integer, which goes up to {}",
+        digits,
+        i32::MAX
+    )
+}
+
+/// The next pair inside the one spanning `parent`, which the grammar always puts there.
+fn next<'i>(inner: &mut Pairs<'i, Rule>, parent: Span) -> Parsed<Pair<'i, Rule>> {
+    inner.next().ok_or_else(|| ParseError::ended_early(parent))
+}
+
+/// Takes the next pair only when its rule is one `f` accepts.
+fn take_if<'i>(inner: &mut Pairs<'i, Rule>, f: impl Fn(Rule) -> bool) -> Option<Pair<'i, Rule>> {
+    match inner.peek() {
+        Some(pair) if f(pair.as_rule()) => inner.next(),
+        _ => None,
+    }
+}
+
+fn get_operator(op: Pair<Rule>) -> Parsed<Operator> {
+    let operator = match op.as_rule() {
         Rule::add => Operator::Math(MathOperator::Add),
         Rule::subtract => Operator::Math(MathOperator::Subtract),
         Rule::multiply => Operator::Math(MathOperator::Multiply),
@@ -58,101 +760,752 @@ fn get_operator(op: Pair<Rule>) -> Operator {
         Rule::ge => Operator::Bool(BooleanOperator::GreaterThanEqual),
         Rule::lt => Operator::Bool(BooleanOperator::LessThan),
         Rule::le => Operator::Bool(BooleanOperator::LessThanEqual),
-        rule => unreachable!("Expected Operator rule found: {:?}", rule),
-    }
+        Rule::coalesce => Operator::Coalesce,
+        _ => return Err(ParseError::unexpected(&op)),
+    };
+    Ok(operator)
 }
 
-fn get_operation(lhs: Expression, op: Operator, rhs: Expression) -> Expression {
-    match op {
-        Operator::Math(o) => Expression::MathOp {
+pub(crate) fn get_operation(lhs: Expression, op: Operator, rhs: Expression) -> Expression {
+    let span = lhs.span.zip(rhs.span).map(|(lhs, rhs)| lhs.to(rhs));
+    let kind = match op {
+        Operator::Math(o) => ExprKind::MathOp {
             lhs: Box::new(lhs),
             op: o,
             rhs: Box::new(rhs),
         },
-        Operator::Bool(o) => Expression::BooleanOp {
+        Operator::Bool(o) => ExprKind::BooleanOp {
             lhs: Box::new(lhs),
             op: o,
             rhs: Box::new(rhs),
         },
+        Operator::Coalesce => ExprKind::Coalesce {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+    };
+    Node { kind, span }
+}
+
+fn get_postfix(target: Expression, postfix: Pair<Rule>) -> Parsed<Expression> {
+    let span = target.span.map(|target| target.to(Span::of(&postfix)));
+    let kind = map_postfix(target, postfix)?;
+    Ok(Node { kind, span })
+}
+
+fn map_postfix(target: Expression, postfix: Pair<Rule>) -> Parsed<ExprKind> {
+    let rule = postfix.as_rule();
+    let accessor = match rule {
+        Rule::propagate => return Ok(ExprKind::Propagate(Box::new(target))),
+        Rule::call => {
+            let args = postfix
+                .into_inner()
+                .map(map_primary)
+                .collect::<Parsed<_>>()?;
+            // `a.b(...)` is a method call on `a` rather than a call of the field `b`.
+            return Ok(match target.kind {
+                ExprKind::Access {
+                    target,
+                    accessor: Accessor::Field(method),
+                    optional,
+                } => ExprKind::MethodCall {
+                    receiver: target,
+                    method,
+                    args,
+                    optional,
+                },
+                kind => ExprKind::Call {
+                    callee: Box::new(Node {
+                        kind,
+                        span: target.span,
+                    }),
+                    args,
+                },
+            });
+        }
+        Rule::field | Rule::optional_field => {
+            Accessor::Field(String::from(postfix.into_inner().as_str()))
+        }
+        Rule::index | Rule::optional_index => {
+            Accessor::Index(Box::new(parse_program(postfix.into_inner())?))
+        }
+        _ => return Err(ParseError::unexpected(&postfix)),
+    };
+
+    Ok(ExprKind::Access {
+        target: Box::new(target),
+        accessor,
+        optional: matches!(rule, Rule::optional_field | Rule::optional_index),
+    })
+}
+
+/// Joins the `///` lines at the front of a declaration into its doc comment, dropping the space
+/// that usually follows the slashes.
+fn take_doc(inner: &mut Pairs<Rule>) -> Option<String> {
+    let mut lines = Vec::new();
+    while let Some(doc) = take_if(inner, |rule| rule == Rule::doc_comment) {
+        let line = doc.into_inner().as_str();
+        lines.push(line.strip_prefix(' ').unwrap_or(line));
     }
+
+    match lines.is_empty() {
+        true => None,
+        false => Some(lines.join("\n")),
+    }
+}
+
+fn map_variant_path(path: Pair<Rule>) -> Parsed<(String, String)> {
+    let span = Span::of(&path);
+    let mut inner = path.into_inner();
+    let enum_name = String::from(next(&mut inner, span)?.as_str());
+    let variant = String::from(next(&mut inner, span)?.as_str());
+    Ok((enum_name, variant))
+}
+
+fn map_pattern(pattern: Pair<Rule>) -> Parsed<Pattern> {
+    let span = Span::of(&pattern);
+    let kind = match pattern.as_rule() {
+        Rule::wildcard => PatternKind::Wildcard,
+        Rule::identifier => PatternKind::Binding(String::from(pattern.as_str())),
+        Rule::variant_pattern => {
+            let mut inner = pattern.into_inner();
+            let path = next(&mut inner, span)?;
+            let (enum_name, variant) = match path.as_rule() {
+                Rule::result_variant => (String::from("Result"), String::from(path.as_str())),
+                _ => map_variant_path(path)?,
+            };
+            PatternKind::Variant {
+                enum_name,
+                variant,
+                fields: inner.map(map_pattern).collect::<Parsed<_>>()?,
+            }
+        }
+        _ => PatternKind::Literal(Box::new(map_primary(pattern)?)),
+    };
+    Ok(Pattern::new(kind, span))
+}
+
+/// A parameter or field with an optional type annotation.
+fn map_annotated(pair: Pair<Rule>) -> Parsed<Annotated> {
+    let span = Span::of(&pair);
+    let mut inner = pair.into_inner();
+    let name = String::from(next(&mut inner, span)?.as_str());
+    Ok(Annotated {
+        name,
+        annotation: inner.next().map(map_type).transpose()?,
+        span: Some(span),
+    })
+}
+
+fn take_type_params(inner: &mut Pairs<Rule>) -> Vec<String> {
+    match take_if(inner, |rule| rule == Rule::type_params) {
+        Some(params) => params
+            .into_inner()
+            .map(|param| String::from(param.as_str()))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn map_type(ty: Pair<Rule>) -> Parsed<TypeExpr> {
+    let span = Span::of(&ty);
+    let kind = match ty.as_rule() {
+        Rule::list_type => TypeKind::List(Box::new(map_type(next(&mut ty.into_inner(), span)?)?)),
+        Rule::generic_type => {
+            let mut inner = ty.into_inner();
+            TypeKind::Generic {
+                name: String::from(next(&mut inner, span)?.as_str()),
+                args: inner.map(map_type).collect::<Parsed<_>>()?,
+            }
+        }
+        Rule::function_type => {
+            let mut params = ty.into_inner().map(map_type).collect::<Parsed<Vec<_>>>()?;
+            let ret = params.pop().ok_or_else(|| ParseError::ended_early(span))?;
+            TypeKind::Function {
+                params,
+                ret: Box::new(ret),
+            }
+        }
+        _ => TypeKind::Named(String::from(ty.as_str())),
+    };
+    Ok(TypeExpr::new(kind, span))
+}
+
+fn map_primary(primary: Pair<Rule>) -> Parsed<Expression> {
+    let span = Span::of(&primary);
+    Ok(Expression::new(map_node(primary)?, span))
 }
 
-fn map_primary(primary: Pair<Rule>) -> Expression {
-    match primary.as_rule() {
-        Rule::integer => Expression::Integer(primary.as_str().parse().unwrap()),
-        Rule::boolean => Expression::Boolean(primary.as_str() == "true"),
-        Rule::string => Expression::String(String::from(primary.as_str())),
-        Rule::identifier => Expression::Identifier(String::from(primary.as_str())),
-        Rule::math_operation => parse_program(primary.into_inner()),
-        Rule::boolean_operation => parse_program(primary.into_inner()),
+fn map_node(primary: Pair<Rule>) -> Parsed<ExprKind> {
+    let span = Span::of(&primary);
+    let node = match primary.as_rule() {
+        Rule::integer => match primary.as_str().parse() {
+            Ok(i) => ExprKind::Integer(i),
+            Err(_) => return Err(ParseError::new(too_big(primary.as_str()), span)),
+        },
+        Rule::boolean => ExprKind::Boolean(primary.as_str() == "true"),
+        Rule::nil => ExprKind::Nil,
+        Rule::string => ExprKind::String(String::from(primary.as_str())),
+        Rule::identifier => ExprKind::Identifier(String::from(primary.as_str())),
+        Rule::list => ExprKind::List(
+            primary
+                .into_inner()
+                .map(map_primary)
+                .collect::<Parsed<_>>()?,
+        ),
+        Rule::operation => parse_program(primary.into_inner())?.kind,
+        Rule::parenthesized => map_node(next(&mut primary.into_inner(), span)?)?,
+        Rule::variant_path => {
+            let (enum_name, variant) = map_variant_path(primary)?;
+            ExprKind::Variant { enum_name, variant }
+        }
+        Rule::match_expr => {
+            let mut inner = primary.into_inner();
+            let scrutinee = map_primary(next(&mut inner, span)?)?;
+            let arms = inner
+                .map(|arm| {
+                    let span = Span::of(&arm);
+                    let mut arm = arm.into_inner();
+                    let pattern = map_pattern(next(&mut arm, span)?)?;
+                    Ok((pattern, parse_program(arm)?))
+                })
+                .collect::<Parsed<_>>()?;
+            ExprKind::Match {
+                scrutinee: Box::new(scrutinee),
+                arms,
+            }
+        }
         Rule::let_stmt => {
             let mut inner = primary.into_inner();
-            let next = inner.next().unwrap().as_str();
-            Expression::LetStmt {
-                identifier: String::from(next),
-                value: Box::new(parse_program(inner)),
+            let doc = take_doc(&mut inner);
+            let constant = take_if(&mut inner, |rule| rule == Rule::constant).is_some();
+            let identifier = String::from(next(&mut inner, span)?.as_str());
+            let annotation = take_if(&mut inner, |rule| rule != Rule::operation)
+                .map(map_type)
+                .transpose()?;
+            ExprKind::LetStmt {
+                doc,
+                identifier,
+                value: Box::new(parse_program(inner)?),
+                constant,
+                annotation,
+            }
+        }
+        Rule::stmt => parse_program(primary.into_inner())?.kind,
+        Rule::import_stmt => {
+            let mut inner = primary.into_inner();
+            ExprKind::Import {
+                path: String::from(next(&mut inner, span)?.as_str()),
+                alias: String::from(next(&mut inner, span)?.as_str()),
+            }
+        }
+        Rule::export_stmt => {
+            let mut inner = primary.into_inner();
+            let doc = take_doc(&mut inner);
+            let declaration = next(&mut inner, span)?;
+            let span = Span::of(&declaration);
+            let declaration = match (doc, map_node(declaration)?) {
+                (
+                    Some(doc),
+                    ExprKind::LetStmt {
+                        identifier,
+                        value,
+                        constant,
+                        annotation,
+                        ..
+                    },
+                ) => ExprKind::LetStmt {
+                    doc: Some(doc),
+                    identifier,
+                    value,
+                    constant,
+                    annotation,
+                },
+                (
+                    Some(doc),
+                    ExprKind::FnDecl {
+                        name,
+                        type_params,
+                        params,
+                        return_type,
+                        body,
+                        ..
+                    },
+                ) => ExprKind::FnDecl {
+                    doc: Some(doc),
+                    name,
+                    type_params,
+                    params,
+                    return_type,
+                    body,
+                },
+                (_, declaration) => declaration,
+            };
+            ExprKind::Export(Box::new(Expression::new(declaration, span)))
+        }
+        Rule::block => ExprKind::Block(
+            primary
+                .into_inner()
+                .map(map_primary)
+                .collect::<Parsed<_>>()?,
+        ),
+        Rule::throw_expr => ExprKind::Throw(Box::new(parse_program(primary.into_inner())?)),
+        Rule::return_expr => {
+            let inner = primary.into_inner();
+            match inner.len() {
+                0 => ExprKind::Return(None),
+                _ => ExprKind::Return(Some(Box::new(parse_program(inner)?))),
+            }
+        }
+        Rule::try_expr => {
+            let mut inner = primary.into_inner();
+            let body = Box::new(map_primary(next(&mut inner, span)?)?);
+            let mut catch = None;
+            let mut finally = None;
+            for clause in inner {
+                let span = Span::of(&clause);
+                match clause.as_rule() {
+                    Rule::catch_clause => {
+                        let mut clause = clause.into_inner();
+                        let name = String::from(next(&mut clause, span)?.as_str());
+                        catch = Some((name, Box::new(map_primary(next(&mut clause, span)?)?)));
+                    }
+                    _ => {
+                        let block = next(&mut clause.into_inner(), span)?;
+                        finally = Some(Box::new(map_primary(block)?));
+                    }
+                }
+            }
+            ExprKind::Try {
+                body,
+                catch,
+                finally,
+            }
+        }
+        Rule::fn_decl => {
+            let mut inner = primary.into_inner();
+            let doc = take_doc(&mut inner);
+            let name = String::from(next(&mut inner, span)?.as_str());
+            let type_params = take_type_params(&mut inner);
+            let params = next(&mut inner, span)?
+                .into_inner()
+                .map(map_annotated)
+                .collect::<Parsed<_>>()?;
+            let return_type = take_if(&mut inner, |rule| rule != Rule::block)
+                .map(map_type)
+                .transpose()?;
+            ExprKind::FnDecl {
+                doc,
+                name,
+                type_params,
+                params,
+                return_type,
+                body: Box::new(map_primary(next(&mut inner, span)?)?),
             }
         }
+        Rule::impl_block => {
+            let mut inner = primary.into_inner();
+            ExprKind::ImplBlock {
+                type_name: String::from(next(&mut inner, span)?.as_str()),
+                methods: inner.map(map_primary).collect::<Parsed<_>>()?,
+            }
+        }
+        Rule::struct_decl => {
+            let mut inner = primary.into_inner();
+            let name = String::from(next(&mut inner, span)?.as_str());
+            ExprKind::StructDecl {
+                name,
+                type_params: take_type_params(&mut inner),
+                fields: inner.map(map_annotated).collect::<Parsed<_>>()?,
+            }
+        }
+        Rule::enum_decl => {
+            let mut inner = primary.into_inner();
+            let name = String::from(next(&mut inner, span)?.as_str());
+            let variants = inner
+                .map(|variant| {
+                    let span = Span::of(&variant);
+                    let mut variant = variant.into_inner().map(|p| String::from(p.as_str()));
+                    let name = variant
+                        .next()
+                        .ok_or_else(|| ParseError::ended_early(span))?;
+                    Ok((name, variant.collect()))
+                })
+                .collect::<Parsed<_>>()?;
+            ExprKind::EnumDecl { name, variants }
+        }
+        Rule::struct_literal => {
+            let mut inner = primary.into_inner();
+            let name = String::from(next(&mut inner, span)?.as_str());
+            let fields = inner
+                .map(|field| {
+                    let span = Span::of(&field);
+                    let mut field = field.into_inner();
+                    let name = String::from(next(&mut field, span)?.as_str());
+                    Ok((name, parse_program(field)?))
+                })
+                .collect::<Parsed<_>>()?;
+            ExprKind::StructLiteral { name, fields }
+        }
+
+        _ => return Err(ParseError::unexpected(&primary)),
+    };
+    Ok(node)
+}
+
+fn is_infix(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::add
+            | Rule::subtract
+            | Rule::multiply
+            | Rule::divide
+            | Rule::eq
+            | Rule::gt
+            | Rule::ge
+            | Rule::lt
+            | Rule::le
+            | Rule::coalesce
+    )
+}
+
+fn is_postfix(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::field
+            | Rule::index
+            | Rule::optional_field
+            | Rule::optional_index
+            | Rule::call
+            | Rule::propagate
+    )
+}
 
-        rule => unreachable!("Expected atomic rule found: {:?}", rule),
+/// Checks that `pairs` are an operand followed by postfixes, and operators with an operand after
+/// each, which is all pest's Pratt parser can take without panicking.
+fn check_operation(pairs: &Pairs<Rule>) -> Parsed<()> {
+    let mut needs_operand = true;
+    let mut last = None;
+    for pair in pairs.clone() {
+        let rule = pair.as_rule();
+        let operator = is_infix(rule) || is_postfix(rule);
+        if operator == needs_operand {
+            return Err(ParseError::unexpected(&pair));
+        }
+        needs_operand = is_infix(rule);
+        last = Some(Span::of(&pair));
+    }
+
+    match needs_operand {
+        true => {
+            let span = last.unwrap_or(Span::START);
+            Err(ParseError::ended_early(span))
+        }
+        false => Ok(()),
     }
 }
 
-pub fn parse_program(pairs: Pairs<Rule>) -> Expression {
+/// Builds the syntax tree of an operation, or of a single statement or declaration, from the
+/// pairs pest parsed it into.
+pub fn parse_program(pairs: Pairs<Rule>) -> Result<Expression, ParseError> {
     use pest::pratt_parser::{Assoc::*, Op};
 
+    check_operation(&pairs)?;
+
     let parser = PrattParser::new()
-        .op(Op::infix(Rule::add, Left) | Op::infix(Rule::subtract, Left))
-        .op(Op::infix(Rule::multiply, Left) | Op::infix(Rule::divide, Left))
+        .op(Op::infix(Rule::coalesce, Left))
         .op(Op::infix(Rule::eq, Left)
             | Op::infix(Rule::gt, Left)
             | Op::infix(Rule::ge, Left)
             | Op::infix(Rule::lt, Left)
-            | Op::infix(Rule::le, Left));
+            | Op::infix(Rule::le, Left))
+        .op(Op::infix(Rule::add, Left) | Op::infix(Rule::subtract, Left))
+        .op(Op::infix(Rule::multiply, Left) | Op::infix(Rule::divide, Left))
+        .op(Op::postfix(Rule::field)
+            | Op::postfix(Rule::index)
+            | Op::postfix(Rule::optional_field)
+            | Op::postfix(Rule::optional_index)
+            | Op::postfix(Rule::call)
+            | Op::postfix(Rule::propagate));
 
     parser
         .map_primary(map_primary)
-        .map_infix(|lhs, op, rhs| get_operation(lhs, get_operator(op), rhs))
+        .map_infix(|lhs, op, rhs| Ok(get_operation(lhs?, get_operator(op)?, rhs?)))
+        .map_postfix(|target, postfix| get_postfix(target?, postfix))
         .parse(pairs)
 }
 
-#[cfg(test)]
-mod twig_parser_tests {
-    use super::*;
-    use pest::{ParseResult, ParserState};
+/// A syntax error, along with a hint when it looks like a common mistake.
+#[derive(Debug, Clone)]
+pub struct SyntaxError {
+    pub error: pest::error::Error<Rule>,
+    pub hint: Option<String>,
+}
 
-    #[test]
-    fn test_get_operator_add() {
-        let input = "+";
-        let pair = pest::state(input, |state| state.rule(Rule::add, |s| Ok(s)))
-            .unwrap()
-            .next()
-            .unwrap();
-        let result = get_operator(pair);
+impl SyntaxError {
+    pub fn new(input: &str, error: pest::error::Error<Rule>) -> SyntaxError {
+        let hint = hint(input, error_position(&error));
+        SyntaxError { error, hint }
+    }
+}
 
-        assert_eq!(result, Operator::Math(MathOperator::Add));
+impl SyntaxError {
+    /// A statement that matched the grammar but has no syntax tree, like one with an integer too
+    /// big to fit.
+    fn from_parse_error(input: &str, e: ParseError) -> SyntaxError {
+        let position =
+            Position::new(input, e.span.start).unwrap_or_else(|| Position::from_start(input));
+        let variant = ErrorVariant::CustomError { message: e.message };
+        SyntaxError {
+            error: pest::error::Error::new_from_pos(variant, position),
+            hint: e.hint,
+        }
     }
+}
 
-    #[test]
-    fn test_get_operator_sub() {
-        let input = "-";
-        let pair = pest::state(input, |state| state.rule(Rule::subtract, |s| Ok(s)))
-            .unwrap()
-            .next()
-            .unwrap();
-        let result = get_operator(pair);
+fn error_position(error: &pest::error::Error<Rule>) -> usize {
+    match error.location {
+        InputLocation::Pos(pos) => pos,
+        InputLocation::Span((start, _)) => start,
+    }
+}
 
-        assert_eq!(result, Operator::Math(MathOperator::Subtract));
+/// Guesses at what was meant from the text around a syntax error.
+pub(crate) fn hint(input: &str, pos: usize) -> Option<String> {
+    let (before, after) = input.split_at(pos.min(input.len()));
+    if after.starts_with('=') && !after.starts_with("==") {
+        // `let x == 1` fails on the second `=`, having taken the first as the declaration's.
+        return Some(String::from(match before.trim_end().ends_with('=') {
+            true => "did you mean `=`? `==` compares two values",
+            false => "did you mean `==`? `=` only gives a value to a `let`",
+        }));
     }
 
-    #[test]
-    fn test_get_operator_mult() {
-        let input = "*";
-        let pair = pest::state(input, |state| state.rule(Rule::multiply, |s| Ok(s)))
+    // Strings can run over several lines, so one that's never closed swallows the code after it
+    // up to the next `"`, and the error shows up at that quote or right after it.
+    let start = statement_around(input, pos).0;
+    let mut chars = input.char_indices().peekable();
+    while chars.next_if(|&(i, _)| i < start).is_some() {}
+    while let Some((i, c)) = chars.next() {
+        if i > pos {
+            break;
+        }
+        match c {
+            '/' if skip_comment(&mut chars) => {}
+            '"' => {
+                let close = chars.find(|&(_, c)| c == '"').map(|(close, _)| close);
+                let end = close.map_or(input.len(), |close| close + 1);
+                let swallowed = close.is_none_or(|close| input[i..close].contains('\n'));
+                let at_end = pos < end || input[end..pos].chars().all(|c| c == ' ' || c == '\t');
+                if swallowed && i <= pos && at_end {
+                    return Some(format!(
+                        "the string on line {} might be missing its closing `\"`",
+                        input[..i].matches('\n').count() + 1
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The top-level statement that `pos` falls in, found by looking for the `;`s and line breaks
+/// outside of brackets, strings and comments that separate the statements of a file. A bracket
+/// that's never closed would take the rest of the file with it, so then the statement ends at the
+/// first `;` or line break outside of brackets opened from `pos` on, or at the line break before
+/// `pos` when it's at the start of a line.
+fn statement_around(input: &str, pos: usize) -> (usize, usize) {
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut depth_at_pos = None;
+    let (mut line_break, mut unclosed_end) = (None, None);
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if i >= pos && depth_at_pos.is_none() {
+            depth_at_pos = Some(depth);
+        }
+        match c {
+            '"' => {
+                chars.find(|&(_, c)| c == '"');
+            }
+            '/' if skip_comment(&mut chars) => {}
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            '\n' | ';' if depth == 0 => {
+                if i >= pos {
+                    return (start, i);
+                }
+                start = i + 1;
+            }
+            '\n' if i < pos => line_break = Some(i),
+            '\n' | ';' if depth_at_pos.is_some_and(|outer| depth <= outer) => {
+                unclosed_end = unclosed_end.or(Some(i));
+            }
+            _ => {}
+        }
+    }
+
+    let line_start = line_break.filter(|&i| i >= start && input[i + 1..pos].trim().is_empty());
+    match depth {
+        0 => (start, input.len()),
+        _ => (start, line_start.or(unclosed_end).unwrap_or(input.len())),
+    }
+}
+
+/// Skips the rest of a `//` or `/* */` comment when the `/` just taken starts one.
+fn skip_comment(chars: &mut Peekable<CharIndices>) -> bool {
+    if chars.next_if(|&(_, c)| c == '/').is_some() {
+        while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        return true;
+    }
+    if chars.next_if(|&(_, c)| c == '*').is_none() {
+        return false;
+    }
+    let mut nesting = 1;
+    while nesting > 0 {
+        match chars.next() {
+            Some((_, '/')) if chars.next_if(|&(_, c)| c == '*').is_some() => nesting += 1,
+            Some((_, '*')) if chars.next_if(|&(_, c)| c == '/').is_some() => nesting -= 1,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    true
+}
+
+/// The closest statement before the one starting at `start` that isn't blank.
+fn previous_statement(input: &str, mut start: usize) -> Option<(usize, usize)> {
+    while start > 0 {
+        let (before, after) = statement_around(input, start - 1);
+        if !input[before..after].trim().is_empty() {
+            return Some((before, after));
+        }
+        start = before;
+    }
+    None
+}
+
+/// Whether a statement ends with an operator, and so goes on to the next line.
+fn carries_on(statement: &str) -> bool {
+    let statement = statement.trim_end();
+    statement.ends_with(['+', '-', '*', '/', '=', '<', '>'])
+        && !statement.ends_with("*/")
+        && !statement.ends_with("->")
+        || statement.ends_with("??")
+}
+
+/// Parses a whole script file into its statements.
+///
+/// A statement that doesn't parse is blanked out and the file parsed again, so that every syntax
+/// error in it is found in one go. Blanking keeps every other statement where it was, so the
+/// errors still point at the right lines.
+pub fn parse_file(input: &str) -> Result<Vec<Expression>, Vec<SyntaxError>> {
+    let mut source = String::from(input);
+    let mut errors = Vec::new();
+    loop {
+        let error = match GrammarParser::parse(Rule::file, &source) {
+            Ok(pairs) if errors.is_empty() => {
+                let mut statements = Vec::new();
+                for pair in pairs.filter(|pair| pair.as_rule() == Rule::stmt) {
+                    match parse_program(pair.into_inner()) {
+                        Ok(statement) => statements.push(statement),
+                        Err(e) => errors.push(SyntaxError::from_parse_error(input, e)),
+                    }
+                }
+                return match errors.is_empty() {
+                    true => Ok(statements),
+                    false => Err(errors),
+                };
+            }
+            Ok(_) => return Err(errors),
+            Err(error) => error,
+        };
+
+        // An error at the start of a statement can belong to the one before it, when that one
+        // carries on over a line break after a trailing operator or hit the end of the file.
+        let pos = error_position(&error);
+        let (mut start, mut end) = statement_around(&source, pos);
+        if source[start..pos].trim().is_empty() {
+            let blank = source[start..end].trim().is_empty();
+            if let Some(previous) = previous_statement(&source, start)
+                .filter(|&(before, after)| blank || carries_on(&source[before..after]))
+            {
+                (start, end) = previous;
+            }
+        }
+        // A string that's never closed would take the rest of the file with it, so only its line
+        // goes.
+        if source[pos..].starts_with('"') && !source[pos + 1..].contains('"') {
+            end = source[pos..].find('\n').map_or(source.len(), |i| pos + i);
+        }
+        errors.push(SyntaxError::new(input, error));
+        if source[start..end].trim().is_empty() {
+            return Err(errors);
+        }
+
+        let blank: String = source[start..end]
+            .chars()
+            .map(|c| match c {
+                '\n' => String::from("\n"),
+                c => " ".repeat(c.len_utf8()),
+            })
+            .collect();
+        source.replace_range(start..end, &blank);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::redundant_closure)]
+mod twig_parser_tests {
+    use super::*;
+    use pest::{ParseResult, ParserState};
+
+    fn parse(input: &str) -> Expression {
+        parse_spanned(input).without_spans()
+    }
+
+    fn without_spans(program: Vec<Expression>) -> Vec<Expression> {
+        program.into_iter().map(Expression::without_spans).collect()
+    }
+
+    fn parse_spanned(input: &str) -> Expression {
+        let mut pairs = GrammarParser::parse(Rule::program, input).unwrap();
+        parse_program(pairs.next().unwrap().into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_get_operator_add() {
+        let input = "+";
+        let pair = pest::state(input, |state| state.rule(Rule::add, |s| Ok(s)))
+            .unwrap()
+            .next()
+            .unwrap();
+        let result = get_operator(pair).unwrap();
+
+        assert_eq!(result, Operator::Math(MathOperator::Add));
+    }
+
+    #[test]
+    fn test_get_operator_sub() {
+        let input = "-";
+        let pair = pest::state(input, |state| state.rule(Rule::subtract, |s| Ok(s)))
+            .unwrap()
+            .next()
+            .unwrap();
+        let result = get_operator(pair).unwrap();
+
+        assert_eq!(result, Operator::Math(MathOperator::Subtract));
+    }
+
+    #[test]
+    fn test_get_operator_mult() {
+        let input = "*";
+        let pair = pest::state(input, |state| state.rule(Rule::multiply, |s| Ok(s)))
             .unwrap()
             .next()
             .unwrap();
-        let result = get_operator(pair);
+        let result = get_operator(pair).unwrap();
 
         assert_eq!(result, Operator::Math(MathOperator::Multiply));
     }
@@ -164,7 +1517,7 @@ mod twig_parser_tests {
             .unwrap()
             .next()
             .unwrap();
-        let result = get_operator(pair);
+        let result = get_operator(pair).unwrap();
 
         assert_eq!(result, Operator::Math(MathOperator::Divide));
     }
@@ -176,7 +1529,7 @@ mod twig_parser_tests {
             .unwrap()
             .next()
             .unwrap();
-        let result = get_operator(pair);
+        let result = get_operator(pair).unwrap();
 
         assert_eq!(result, Operator::Bool(BooleanOperator::Eqaul));
     }
@@ -188,7 +1541,7 @@ mod twig_parser_tests {
             .unwrap()
             .next()
             .unwrap();
-        let result = get_operator(pair);
+        let result = get_operator(pair).unwrap();
 
         assert_eq!(result, Operator::Bool(BooleanOperator::GreaterThan));
     }
@@ -200,7 +1553,7 @@ mod twig_parser_tests {
             .unwrap()
             .next()
             .unwrap();
-        let result = get_operator(pair);
+        let result = get_operator(pair).unwrap();
 
         assert_eq!(result, Operator::Bool(BooleanOperator::GreaterThanEqual));
     }
@@ -212,7 +1565,7 @@ mod twig_parser_tests {
             .unwrap()
             .next()
             .unwrap();
-        let result = get_operator(pair);
+        let result = get_operator(pair).unwrap();
 
         assert_eq!(result, Operator::Bool(BooleanOperator::LessThan));
     }
@@ -224,7 +1577,7 @@ mod twig_parser_tests {
             .unwrap()
             .next()
             .unwrap();
-        let result = get_operator(pair);
+        let result = get_operator(pair).unwrap();
 
         assert_eq!(result, Operator::Bool(BooleanOperator::LessThanEqual));
     }
@@ -233,16 +1586,17 @@ mod twig_parser_tests {
     fn test_get_operation_math() {
         let op = MathOperator::Subtract;
         let result = get_operation(
-            Expression::Integer(1),
+            ExprKind::Integer(1).into(),
             Operator::Math(op),
-            Expression::Integer(1),
+            ExprKind::Integer(1).into(),
         );
 
-        let expected = Expression::MathOp {
-            lhs: Box::new(Expression::Integer(1)),
+        let expected: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
             op,
-            rhs: Box::new(Expression::Integer(1)),
-        };
+            rhs: Box::new(ExprKind::Integer(1).into()),
+        }
+        .into();
 
         assert_eq!(result, expected);
     }
@@ -251,16 +1605,17 @@ mod twig_parser_tests {
     fn test_get_operation_bool() {
         let op = BooleanOperator::LessThan;
         let result = get_operation(
-            Expression::Integer(1),
+            ExprKind::Integer(1).into(),
             Operator::Bool(op),
-            Expression::Integer(1),
+            ExprKind::Integer(1).into(),
         );
 
-        let expected = Expression::BooleanOp {
-            lhs: Box::new(Expression::Integer(1)),
+        let expected: Expression = ExprKind::BooleanOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
             op,
-            rhs: Box::new(Expression::Integer(1)),
-        };
+            rhs: Box::new(ExprKind::Integer(1).into()),
+        }
+        .into();
 
         assert_eq!(result, expected);
     }
@@ -277,8 +1632,8 @@ mod twig_parser_tests {
             .next()
             .unwrap();
 
-        let result = map_primary(pair);
-        let expected = Expression::Integer(8);
+        let result = map_primary(pair).unwrap().without_spans();
+        let expected: Expression = ExprKind::Integer(8).into();
 
         assert_eq!(result, expected);
     }
@@ -295,8 +1650,8 @@ mod twig_parser_tests {
             .next()
             .unwrap();
 
-        let result = map_primary(pair);
-        let expected = Expression::Boolean(true);
+        let result = map_primary(pair).unwrap().without_spans();
+        let expected: Expression = ExprKind::Boolean(true).into();
 
         assert_eq!(result, expected);
     }
@@ -313,8 +1668,8 @@ mod twig_parser_tests {
             .next()
             .unwrap();
 
-        let result = map_primary(pair);
-        let expected = Expression::String(String::from("test"));
+        let result = map_primary(pair).unwrap().without_spans();
+        let expected: Expression = ExprKind::String(String::from("test")).into();
 
         assert_eq!(result, expected);
     }
@@ -331,9 +1686,850 @@ mod twig_parser_tests {
             .next()
             .unwrap();
 
-        let result = map_primary(pair);
-        let expected = Expression::Identifier(String::from("test"));
+        let result = map_primary(pair).unwrap().without_spans();
+        let expected: Expression = ExprKind::Identifier(String::from("test")).into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_get_operator_coalesce() {
+        let input = "??";
+        let pair = pest::state(input, |state| state.rule(Rule::coalesce, |s| Ok(s)))
+            .unwrap()
+            .next()
+            .unwrap();
+        let result = get_operator(pair).unwrap();
+
+        assert_eq!(result, Operator::Coalesce);
+    }
+
+    #[test]
+    fn test_map_primary_nil() {
+        let input = "nil";
+        let rule = Rule::nil;
+        fn parser_rules(state: Box<ParserState<Rule>>) -> ParseResult<Box<ParserState<Rule>>> {
+            state.match_string("nil")
+        }
+        let pair = pest::state(input, |state| state.rule(rule, parser_rules))
+            .unwrap()
+            .next()
+            .unwrap();
+
+        let result = map_primary(pair).unwrap().without_spans();
+
+        assert_eq!(result, ExprKind::Nil.into());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let result = parse("[1, nil]");
+        let expected: Expression =
+            ExprKind::List(vec![ExprKind::Integer(1).into(), ExprKind::Nil.into()]).into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_optional_chaining() {
+        let result = parse("a?.b[0]");
+        let expected: Expression = ExprKind::Access {
+            target: Box::new(
+                ExprKind::Access {
+                    target: Box::new(ExprKind::Identifier(String::from("a")).into()),
+                    accessor: Accessor::Field(String::from("b")),
+                    optional: true,
+                }
+                .into(),
+            ),
+            accessor: Accessor::Index(Box::new(ExprKind::Integer(0).into())),
+            optional: false,
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_coalesce_precedence() {
+        let result = parse("a ?? 1 + 2");
+        let expected: Expression = ExprKind::Coalesce {
+            lhs: Box::new(ExprKind::Identifier(String::from("a")).into()),
+            rhs: Box::new(
+                ExprKind::MathOp {
+                    lhs: Box::new(ExprKind::Integer(1).into()),
+                    op: MathOperator::Add,
+                    rhs: Box::new(ExprKind::Integer(2).into()),
+                }
+                .into(),
+            ),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_struct_decl() {
+        let result = parse("struct Point { x, y }");
+        let expected: Expression = ExprKind::StructDecl {
+            name: String::from("Point"),
+            type_params: Vec::new(),
+            fields: vec![Annotated::new("x"), Annotated::new("y")],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_struct_literal() {
+        let result = parse("Point { x: 1, y: 2 }");
+        let expected: Expression = ExprKind::StructLiteral {
+            name: String::from("Point"),
+            fields: vec![
+                (String::from("x"), ExprKind::Integer(1).into()),
+                (String::from("y"), ExprKind::Integer(2).into()),
+            ],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_call() {
+        let result = parse("type_of(p)");
+        let expected: Expression = ExprKind::Call {
+            callee: Box::new(ExprKind::Identifier(String::from("type_of")).into()),
+            args: vec![ExprKind::Identifier(String::from("p")).into()],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_enum_decl() {
+        let result = parse("enum Shape { Circle(r), Empty }");
+        let expected: Expression = ExprKind::EnumDecl {
+            name: String::from("Shape"),
+            variants: vec![
+                (String::from("Circle"), vec![String::from("r")]),
+                (String::from("Empty"), vec![]),
+            ],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_match() {
+        let result = parse("match s { Shape::Circle(r) => r, 1 => 2, _ => 0 }");
+        let expected: Expression = ExprKind::Match {
+            scrutinee: Box::new(ExprKind::Identifier(String::from("s")).into()),
+            arms: vec![
+                (
+                    PatternKind::Variant {
+                        enum_name: String::from("Shape"),
+                        variant: String::from("Circle"),
+                        fields: vec![PatternKind::Binding(String::from("r")).into()],
+                    }
+                    .into(),
+                    ExprKind::Identifier(String::from("r")).into(),
+                ),
+                (
+                    PatternKind::Literal(Box::new(ExprKind::Integer(1).into())).into(),
+                    ExprKind::Integer(2).into(),
+                ),
+                (PatternKind::Wildcard.into(), ExprKind::Integer(0).into()),
+            ],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_fn_decl() {
+        let result = parse("fn add(a, b) { let c = a + b; c }");
+        let expected: Expression = ExprKind::FnDecl {
+            doc: None,
+            name: String::from("add"),
+            type_params: Vec::new(),
+            params: vec![Annotated::new("a"), Annotated::new("b")],
+            return_type: None,
+            body: Box::new(
+                ExprKind::Block(vec![
+                    ExprKind::LetStmt {
+                        doc: None,
+                        identifier: String::from("c"),
+                        value: Box::new(
+                            ExprKind::MathOp {
+                                lhs: Box::new(ExprKind::Identifier(String::from("a")).into()),
+                                op: MathOperator::Add,
+                                rhs: Box::new(ExprKind::Identifier(String::from("b")).into()),
+                            }
+                            .into(),
+                        ),
+                        constant: false,
+                        annotation: None,
+                    }
+                    .into(),
+                    ExprKind::Identifier(String::from("c")).into(),
+                ])
+                .into(),
+            ),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_impl_block() {
+        let result = parse("impl Point { fn x(self) { self.x } }");
+        let expected: Expression = ExprKind::ImplBlock {
+            type_name: String::from("Point"),
+            methods: vec![
+                ExprKind::FnDecl {
+                    doc: None,
+                    name: String::from("x"),
+                    type_params: Vec::new(),
+                    params: vec![Annotated::new("self")],
+                    return_type: None,
+                    body: Box::new(
+                        ExprKind::Block(vec![
+                            ExprKind::Access {
+                                target: Box::new(ExprKind::Identifier(String::from("self")).into()),
+                                accessor: Accessor::Field(String::from("x")),
+                                optional: false,
+                            }
+                            .into(),
+                        ])
+                        .into(),
+                    ),
+                }
+                .into(),
+            ],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_method_call() {
+        let result = parse("p?.dist(1)");
+        let expected: Expression = ExprKind::MethodCall {
+            receiver: Box::new(ExprKind::Identifier(String::from("p")).into()),
+            method: String::from("dist"),
+            args: vec![ExprKind::Integer(1).into()],
+            optional: true,
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_try_catch_finally() {
+        let result = parse("try { throw 1 } catch e { e } finally { 2 }");
+        let expected: Expression = ExprKind::Try {
+            body: Box::new(
+                ExprKind::Block(vec![
+                    ExprKind::Throw(Box::new(ExprKind::Integer(1).into())).into(),
+                ])
+                .into(),
+            ),
+            catch: Some((
+                String::from("e"),
+                Box::new(
+                    ExprKind::Block(vec![ExprKind::Identifier(String::from("e")).into()]).into(),
+                ),
+            )),
+            finally: Some(Box::new(
+                ExprKind::Block(vec![ExprKind::Integer(2).into()]).into(),
+            )),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_try_finally() {
+        let result = parse("try { 1 } finally { 2 }");
+        let expected: Expression = ExprKind::Try {
+            body: Box::new(ExprKind::Block(vec![ExprKind::Integer(1).into()]).into()),
+            catch: None,
+            finally: Some(Box::new(
+                ExprKind::Block(vec![ExprKind::Integer(2).into()]).into(),
+            )),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_propagate() {
+        let result = parse("f()? ?? 1");
+        let expected: Expression = ExprKind::Coalesce {
+            lhs: Box::new(
+                ExprKind::Propagate(Box::new(
+                    ExprKind::Call {
+                        callee: Box::new(ExprKind::Identifier(String::from("f")).into()),
+                        args: vec![],
+                    }
+                    .into(),
+                ))
+                .into(),
+            ),
+            rhs: Box::new(ExprKind::Integer(1).into()),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_result_pattern() {
+        let result = parse("match r { Ok(v) => v, Err(_) => 0 }");
+        let expected: Expression = ExprKind::Match {
+            scrutinee: Box::new(ExprKind::Identifier(String::from("r")).into()),
+            arms: vec![
+                (
+                    PatternKind::Variant {
+                        enum_name: String::from("Result"),
+                        variant: String::from("Ok"),
+                        fields: vec![PatternKind::Binding(String::from("v")).into()],
+                    }
+                    .into(),
+                    ExprKind::Identifier(String::from("v")).into(),
+                ),
+                (
+                    PatternKind::Variant {
+                        enum_name: String::from("Result"),
+                        variant: String::from("Err"),
+                        fields: vec![PatternKind::Wildcard.into()],
+                    }
+                    .into(),
+                    ExprKind::Integer(0).into(),
+                ),
+            ],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_return() {
+        assert_eq!(parse("return"), ExprKind::Return(None).into());
+        assert_eq!(
+            parse("return 1"),
+            ExprKind::Return(Some(Box::new(ExprKind::Integer(1).into()))).into()
+        );
+    }
+
+    #[test]
+    fn test_parse_import() {
+        let result = parse("import \"lib/math.twig\" as m");
+        let expected: Expression = ExprKind::Import {
+            path: String::from("lib/math.twig"),
+            alias: String::from("m"),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_export() {
+        let result = parse("export let a = 1");
+        let expected: Expression = ExprKind::Export(Box::new(
+            ExprKind::LetStmt {
+                doc: None,
+                identifier: String::from("a"),
+                value: Box::new(ExprKind::Integer(1).into()),
+                constant: false,
+                annotation: None,
+            }
+            .into(),
+        ))
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let result = without_spans(parse_file("let a = 1\n\na; 2\n").unwrap());
+        let expected = vec![
+            ExprKind::LetStmt {
+                doc: None,
+                identifier: String::from("a"),
+                value: Box::new(ExprKind::Integer(1).into()),
+                constant: false,
+                annotation: None,
+            }
+            .into(),
+            ExprKind::Identifier(String::from("a")).into(),
+            ExprKind::Integer(2).into(),
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    fn syntax_errors(input: &str) -> Vec<((usize, usize), Option<String>)> {
+        parse_file(input)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| match e.error.line_col {
+                pest::error::LineColLocation::Pos(pos)
+                | pest::error::LineColLocation::Span(pos, _) => (pos, e.hint),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_file_reports_every_error() {
+        let result = syntax_errors("let a = 1 +* 2\nlet b = [1,\n  2 3]\nlet c = 3; ) ; 4\nc");
+        let positions: Vec<_> = result.iter().map(|(pos, _)| *pos).collect();
+
+        assert_eq!(positions, [(1, 12), (3, 5), (4, 12)]);
+    }
+
+    #[test]
+    fn test_parse_file_recovers_after_trailing_operator() {
+        let result = syntax_errors("let a = 1 +\n\nlet b = *");
+        let positions: Vec<_> = result.iter().map(|(pos, _)| *pos).collect();
+
+        assert_eq!(positions, [(3, 1), (3, 9)]);
+    }
+
+    #[test]
+    fn test_parse_file_recovery_skips_brackets_strings_and_comments() {
+        let input = "fn f() {\n  let s = \"a;\n)\" /* ; \n */\n  s +\n}\nlet x = [";
+        let positions: Vec<_> = syntax_errors(input).iter().map(|(pos, _)| *pos).collect();
+
+        assert_eq!(positions, [(6, 1), (7, 10)]);
+    }
+
+    #[test]
+    fn test_parse_file_recovers_after_unclosed_bracket() {
+        let input = "let a = f(1, 2\nlet b = *\nlet c = [1 +* 2\nlet d = *";
+        let positions: Vec<_> = syntax_errors(input).iter().map(|(pos, _)| *pos).collect();
+
+        assert_eq!(positions, [(2, 1), (2, 9), (3, 13), (4, 9)]);
+    }
+
+    #[test]
+    fn test_parse_file_hint_assignment() {
+        let result = syntax_errors("a = 1");
+        let expected = vec![(
+            (1, 3),
+            Some(String::from(
+                "did you mean `==`? `=` only gives a value to a `let`",
+            )),
+        )];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_file_hint_comparison() {
+        let result = syntax_errors("let b == 3");
+        let expected = vec![(
+            (1, 8),
+            Some(String::from("did you mean `=`? `==` compares two values")),
+        )];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_file_hint_unclosed_string() {
+        let result = syntax_errors("let a = \"abc\nlet b = 2\nprint(\"x\")");
+        let expected = vec![(
+            (3, 8),
+            Some(String::from(
+                "the string on line 1 might be missing its closing `\"`",
+            )),
+        )];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_file_no_string_hint_away_from_the_string() {
+        let result = syntax_errors("let s = \"two\nlines\" + *");
+
+        assert_eq!(result, vec![((2, 10), None)]);
+    }
+
+    #[test]
+    fn test_parse_file_unclosed_string_only_skips_its_line() {
+        let result = syntax_errors("let a = \"abc\nlet b = *");
+        let positions: Vec<_> = result.iter().map(|(pos, _)| *pos).collect();
+
+        assert_eq!(positions, [(1, 9), (2, 9)]);
+        assert!(result[0].1.is_some());
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let result = parse("1 /* a /* nested */ comment */ + 2 // trailing");
+        let expected: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: MathOperator::Add,
+            rhs: Box::new(ExprKind::Integer(2).into()),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_doc_comment_fn() {
+        let result = without_spans(
+            parse_file("// not a doc\n/// Adds one.\n/// Really.\nfn inc(n) { n + 1 }").unwrap(),
+        );
+        let expected = vec![
+            ExprKind::FnDecl {
+                doc: Some(String::from("Adds one.\nReally.")),
+                name: String::from("inc"),
+                type_params: Vec::new(),
+                params: vec![Annotated::new("n")],
+                return_type: None,
+                body: Box::new(
+                    ExprKind::Block(vec![
+                        ExprKind::MathOp {
+                            lhs: Box::new(ExprKind::Identifier(String::from("n")).into()),
+                            op: MathOperator::Add,
+                            rhs: Box::new(ExprKind::Integer(1).into()),
+                        }
+                        .into(),
+                    ])
+                    .into(),
+                ),
+            }
+            .into(),
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_doc_comment_export() {
+        let result = without_spans(parse_file("/// The answer.\nexport let a = 42").unwrap());
+        let expected = vec![
+            ExprKind::Export(Box::new(
+                ExprKind::LetStmt {
+                    doc: Some(String::from("The answer.")),
+                    identifier: String::from("a"),
+                    value: Box::new(ExprKind::Integer(42).into()),
+                    constant: false,
+                    annotation: None,
+                }
+                .into(),
+            ))
+            .into(),
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_doc_comment_without_declaration_is_comment() {
+        let result = without_spans(parse_file("/// Dangling.\n1 + 1\n/// At the end.").unwrap());
+
+        assert_eq!(result, vec![parse("1 + 1")]);
+    }
+
+    #[test]
+    fn test_parse_identifier_digits_and_underscores() {
+        let result = parse("total_count2 + _x");
+        let expected: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Identifier(String::from("total_count2")).into()),
+            op: MathOperator::Add,
+            rhs: Box::new(ExprKind::Identifier(String::from("_x")).into()),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_identifier_unicode() {
+        let result = parse("let café = 1");
+        let expected: Expression = ExprKind::LetStmt {
+            doc: None,
+            identifier: String::from("café"),
+            value: Box::new(ExprKind::Integer(1).into()),
+            constant: false,
+            annotation: None,
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_identifier_starting_with_keyword() {
+        assert_eq!(
+            parse("letter"),
+            ExprKind::Identifier(String::from("letter")).into()
+        );
+        assert_eq!(
+            parse("trueish"),
+            ExprKind::Identifier(String::from("trueish")).into()
+        );
+    }
+
+    #[test]
+    fn test_parse_identifier_rejects_keywords() {
+        assert!(GrammarParser::parse(Rule::program, "let nil = 1").is_err());
+        assert!(GrammarParser::parse(Rule::program, "let _ = 1").is_err());
+        assert!(GrammarParser::parse(Rule::program, "let 1a = 1").is_err());
+        assert!(GrammarParser::parse(Rule::program, "letx = 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_tabs_and_carriage_returns() {
+        let result = without_spans(parse_file("let\ta =\t1\r\nlet b = 2\r\n").unwrap());
+
+        assert_eq!(result.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_block_newline_separators() {
+        let result = parse("{\n\tlet a = 1\n\n\ta\n}");
+        let expected: Expression = ExprKind::Block(vec![
+            ExprKind::LetStmt {
+                doc: None,
+                identifier: String::from("a"),
+                value: Box::new(ExprKind::Integer(1).into()),
+                constant: false,
+                annotation: None,
+            }
+            .into(),
+            ExprKind::Identifier(String::from("a")).into(),
+        ])
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_continuation_inside_brackets() {
+        let result = parse("f(\n  1,\n  [2,\n   3],\n)");
+        let expected: Expression = ExprKind::Call {
+            callee: Box::new(ExprKind::Identifier(String::from("f")).into()),
+            args: vec![
+                ExprKind::Integer(1).into(),
+                ExprKind::List(vec![
+                    ExprKind::Integer(2).into(),
+                    ExprKind::Integer(3).into(),
+                ])
+                .into(),
+            ],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_continuation_after_operator() {
+        let result = parse("1 +\n\n  2");
+        let expected: Expression = ExprKind::MathOp {
+            lhs: Box::new(ExprKind::Integer(1).into()),
+            op: MathOperator::Add,
+            rhs: Box::new(ExprKind::Integer(2).into()),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_newline_ends_statement() {
+        let result = parse_file("let a = 1\n+ 2");
+        assert!(result.is_err());
+
+        let result = without_spans(parse_file("let a = (1\n+ 2)\na").unwrap());
+        assert_eq!(result.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_block_inside_brackets_keeps_separators() {
+        let result = parse("f({\n  1\n  2\n})");
+        let expected: Expression = ExprKind::Call {
+            callee: Box::new(ExprKind::Identifier(String::from("f")).into()),
+            args: vec![
+                ExprKind::Block(vec![
+                    ExprKind::Integer(1).into(),
+                    ExprKind::Integer(2).into(),
+                ])
+                .into(),
+            ],
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_const() {
+        let result = parse("const limit = 10");
+        let expected: Expression = ExprKind::LetStmt {
+            doc: None,
+            identifier: String::from("limit"),
+            value: Box::new(ExprKind::Integer(10).into()),
+            constant: true,
+            annotation: None,
+        }
+        .into();
+
+        assert_eq!(result, expected);
+        assert_eq!(
+            parse("constant"),
+            ExprKind::Identifier(String::from("constant")).into()
+        );
+    }
+
+    #[test]
+    fn test_parse_let_annotation() {
+        let result = parse("let xs: [string] = []");
+        let expected: Expression = ExprKind::LetStmt {
+            doc: None,
+            identifier: String::from("xs"),
+            value: Box::new(ExprKind::List(vec![]).into()),
+            constant: false,
+            annotation: Some(
+                TypeKind::List(Box::new(TypeKind::Named(String::from("string")).into())).into(),
+            ),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_fn_annotations() {
+        let result = parse("fn f(a: nil, b, g: fn(int) -> bool) -> int { 1 }");
+        let expected: Expression = ExprKind::FnDecl {
+            doc: None,
+            name: String::from("f"),
+            type_params: Vec::new(),
+            params: vec![
+                Annotated {
+                    annotation: Some(TypeKind::Named(String::from("nil")).into()),
+                    ..Annotated::new("a")
+                },
+                Annotated::new("b"),
+                Annotated {
+                    annotation: Some(
+                        TypeKind::Function {
+                            params: vec![TypeKind::Named(String::from("int")).into()],
+                            ret: Box::new(TypeKind::Named(String::from("bool")).into()),
+                        }
+                        .into(),
+                    ),
+                    ..Annotated::new("g")
+                },
+            ],
+            return_type: Some(TypeKind::Named(String::from("int")).into()),
+            body: Box::new(ExprKind::Block(vec![ExprKind::Integer(1).into()]).into()),
+        }
+        .into();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_parse_generics() {
+        let result = parse("fn first<T>(xs: [T]) -> T { xs[0] }");
+        let expected: Expression = ExprKind::FnDecl {
+            doc: None,
+            name: String::from("first"),
+            type_params: vec![String::from("T")],
+            params: vec![Annotated {
+                annotation: Some(
+                    TypeKind::List(Box::new(TypeKind::Named(String::from("T")).into())).into(),
+                ),
+                ..Annotated::new("xs")
+            }],
+            return_type: Some(TypeKind::Named(String::from("T")).into()),
+            body: Box::new(
+                ExprKind::Block(vec![
+                    ExprKind::Access {
+                        target: Box::new(ExprKind::Identifier(String::from("xs")).into()),
+                        accessor: Accessor::Index(Box::new(ExprKind::Integer(0).into())),
+                        optional: false,
+                    }
+                    .into(),
+                ])
+                .into(),
+            ),
+        }
+        .into();
 
         assert_eq!(result, expected);
+
+        let input = "struct Pair<A, B> { first: A, second: Pair<B, int>, rest }";
+        assert_eq!(parse(input).to_string(), input);
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        let result = parse_spanned("1 +\n f(a)[0]");
+
+        let ExprKind::MathOp { lhs, rhs, .. } = &result.kind else {
+            panic!("expected a math operation");
+        };
+        let span = |start, end, line, column| Span {
+            start,
+            end,
+            line,
+            column,
+        };
+        assert_eq!(result.span, Some(span(0, 12, 1, 1)));
+        assert_eq!(lhs.span, Some(span(0, 1, 1, 1)));
+        assert_eq!(rhs.span, Some(span(5, 12, 2, 2)));
+
+        let ExprKind::Access { target, .. } = &rhs.kind else {
+            panic!("expected an index");
+        };
+        let ExprKind::Call { callee, args } = &target.kind else {
+            panic!("expected a call");
+        };
+        assert_eq!(target.span, Some(span(5, 9, 2, 2)));
+        assert_eq!(callee.span, Some(span(5, 6, 2, 2)));
+        assert_eq!(args[0].span, Some(span(7, 8, 2, 4)));
+    }
+
+    #[test]
+    fn test_parse_file_reports_integer_overflow() {
+        let errors = parse_file("let x = 1\nlet y = 99999999999").unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(error_position(&errors[0].error), 18);
+        assert!(
+            errors[0]
+                .error
+                .to_string()
+                .contains("`99999999999` is too big to be an integer, which goes up to 2147483647")
+        );
+    }
+
+    #[test]
+    fn test_display_expression() {
+        let input = "let x: [int] = (1 + 2) * f(a.b, xs?.[0]) - (3 - 4)";
+
+        assert_eq!(parse(input).to_string(), input);
     }
 }
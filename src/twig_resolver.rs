@@ -0,0 +1,455 @@
+use std::collections::BTreeSet;
+
+use crate::twig_builtins::Builtin;
+use crate::twig_interp::{Env, InterpErrors, closest};
+use crate::twig_parser::{Accessor, ExprKind, Expression, Pattern, PatternKind, Span};
+
+/// Works out where every local variable will live before a program runs, by walking it with the
+/// same scopes the interpreter pushes. Each scope is a list of names in the order they're first
+/// declared, which is the order they get their slots in at runtime.
+struct Resolver {
+    /// The names declared at the top level, which are global and so seen everywhere.
+    globals: BTreeSet<String>,
+    /// The local scopes of the function being resolved, or of the top level, innermost last.
+    scopes: Vec<Vec<String>>,
+    /// Whether a name that isn't declared yet is left to be looked up when it runs. Inside a
+    /// function a global only has to exist by the time it's called, which in the REPL can be
+    /// after a later line declares it, and inside a `try` a name that doesn't exist is an error
+    /// the `catch` can handle.
+    deferred: bool,
+    span: Option<Span>,
+    errors: Vec<InterpErrors>,
+}
+
+/// Resolves the local variables of a program to the depth and slot they'll be found at, and
+/// reports every name outside of a function or `try` that's neither a local in scope, a global nor a
+/// builtin. Globals declared by earlier lines of the REPL are taken from `env`.
+pub fn resolve_program(program: &mut [Expression], env: &Env) -> Result<(), Vec<InterpErrors>> {
+    let mut globals: BTreeSet<String> = env.global_names().map(String::from).collect();
+    // A function can use a global declared further down, since it's only looked up once called.
+    globals.extend(program.iter().filter_map(declared_name));
+
+    let mut resolver = Resolver {
+        globals,
+        scopes: Vec::new(),
+        deferred: false,
+        span: None,
+        errors: Vec::new(),
+    };
+    program.iter_mut().for_each(|stmt| resolver.expr(stmt));
+
+    match resolver.errors.is_empty() {
+        true => Ok(()),
+        false => Err(resolver.errors),
+    }
+}
+
+/// The name a statement declares in the scope it's in, if any.
+fn declared_name(stmt: &Expression) -> Option<String> {
+    match &stmt.kind {
+        ExprKind::LetStmt { identifier, .. } => Some(identifier.clone()),
+        ExprKind::FnDecl { name, .. }
+        | ExprKind::StructDecl { name, .. }
+        | ExprKind::EnumDecl { name, .. } => Some(name.clone()),
+        ExprKind::Import { alias, .. } => Some(alias.clone()),
+        ExprKind::Export(declaration) => declared_name(declaration),
+        _ => None,
+    }
+}
+
+impl Resolver {
+    /// Declares a name in the innermost scope. Names declared at the top level are globals, which
+    /// were all collected up front.
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut()
+            && !scope.iter().any(|declared| declared == name)
+        {
+            scope.push(String::from(name));
+        }
+    }
+
+    /// Resolves an expression in a new scope holding the given names, the way match arms and
+    /// `catch` blocks run.
+    fn scoped(&mut self, names: &[&str], expr: &mut Expression) {
+        self.scopes.push(Vec::new());
+        names.iter().for_each(|name| self.declare(name));
+        self.expr(expr);
+        self.scopes.pop();
+    }
+
+    /// Resolves the body of a function, which only sees its own parameters and the globals.
+    fn function(&mut self, params: &[&str], body: &mut Expression) {
+        let outer = std::mem::take(&mut self.scopes);
+        let deferred = std::mem::replace(&mut self.deferred, true);
+        self.scoped(params, body);
+        self.deferred = deferred;
+        self.scopes = outer;
+    }
+
+    fn identifier(&mut self, expr: &mut ExprKind) {
+        let ExprKind::Identifier(name) = expr else {
+            return;
+        };
+
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.iter().position(|declared| declared == name) {
+                let name = std::mem::take(name);
+                *expr = ExprKind::Local { name, depth, slot };
+                return;
+            }
+        }
+
+        // Anything else is looked up by name when it runs, so inside a function or a `try` a
+        // name that isn't declared yet is left for then.
+        if !self.deferred
+            && !self.globals.contains(name.as_str())
+            && Builtin::from_name(name).is_none()
+        {
+            let mut names: Vec<&str> = Builtin::ALL.iter().map(Builtin::name).collect();
+            names.extend(self.scopes.iter().flatten().map(String::as_str));
+            names.extend(self.globals.iter().map(String::as_str));
+            let e = InterpErrors::VariableDoesNotExist {
+                name: name.clone(),
+                suggestion: closest(name, names.into_iter()).map(String::from),
+            };
+            self.errors.push(match self.span {
+                Some(span) => e.at(span),
+                None => e,
+            });
+        }
+    }
+
+    /// Resolves a method from an `impl` block, which unlike a function isn't a variable.
+    fn method(&mut self, method: &mut Expression) {
+        let outer = self.span;
+        self.span = method.span.or(outer);
+        match &mut method.kind {
+            ExprKind::FnDecl { params, body, .. } => {
+                let params: Vec<&str> = params.iter().map(|param| param.name.as_str()).collect();
+                self.function(&params, body);
+            }
+            _ => self.errors.push(InterpErrors::Internal(String::from(
+                "an `impl` block holds something other than a function",
+            ))),
+        }
+        self.span = outer;
+    }
+
+    fn pattern(&mut self, pattern: &mut Pattern, bindings: &mut Vec<String>) {
+        match &mut pattern.kind {
+            PatternKind::Wildcard => {}
+            PatternKind::Binding(name) => bindings.push(name.clone()),
+            PatternKind::Literal(literal) => self.expr(literal),
+            PatternKind::Variant { fields, .. } => {
+                fields
+                    .iter_mut()
+                    .for_each(|field| self.pattern(field, bindings));
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expression) {
+        let outer = self.span;
+        self.span = expr.span.or(outer);
+        self.node(&mut expr.kind);
+        self.span = outer;
+    }
+
+    fn node(&mut self, kind: &mut ExprKind) {
+        match kind {
+            ExprKind::Identifier(_) => self.identifier(kind),
+            ExprKind::Integer(_)
+            | ExprKind::Boolean(_)
+            | ExprKind::String(_)
+            | ExprKind::Nil
+            | ExprKind::Local { .. }
+            | ExprKind::Variant { .. } => {}
+            ExprKind::List(items) => items.iter_mut().for_each(|item| self.expr(item)),
+            ExprKind::MathOp { lhs, rhs, .. }
+            | ExprKind::BooleanOp { lhs, rhs, .. }
+            | ExprKind::Coalesce { lhs, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Access {
+                target, accessor, ..
+            } => {
+                self.expr(target);
+                if let Accessor::Index(index) = accessor {
+                    self.expr(index);
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                args.iter_mut().for_each(|arg| self.expr(arg));
+            }
+            ExprKind::MethodCall { receiver, args, .. } => {
+                self.expr(receiver);
+                args.iter_mut().for_each(|arg| self.expr(arg));
+            }
+            ExprKind::StructLiteral { fields, .. } => {
+                fields.iter_mut().for_each(|(_, value)| self.expr(value));
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.expr(scrutinee);
+                for (pattern, body) in arms {
+                    let mut bindings = Vec::new();
+                    self.pattern(pattern, &mut bindings);
+                    let bindings: Vec<&str> = bindings.iter().map(String::as_str).collect();
+                    self.scoped(&bindings, body);
+                }
+            }
+            ExprKind::Block(stmts) => {
+                self.scopes.push(Vec::new());
+                stmts.iter_mut().for_each(|stmt| self.expr(stmt));
+                self.scopes.pop();
+            }
+            ExprKind::Throw(value) | ExprKind::Propagate(value) => self.expr(value),
+            ExprKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                let deferred = std::mem::replace(&mut self.deferred, true);
+                self.expr(body);
+                self.deferred = deferred;
+                if let Some((name, handler)) = catch {
+                    self.scoped(&[name.as_str()], handler);
+                }
+                if let Some(finally) = finally {
+                    self.expr(finally);
+                }
+            }
+            ExprKind::LetStmt {
+                identifier, value, ..
+            } => {
+                self.expr(value);
+                self.declare(identifier);
+            }
+            ExprKind::FnDecl {
+                name, params, body, ..
+            } => {
+                let params: Vec<&str> = params.iter().map(|param| param.name.as_str()).collect();
+                self.function(&params, body);
+                self.declare(name);
+            }
+            ExprKind::ImplBlock { methods, .. } => {
+                methods.iter_mut().for_each(|method| self.method(method));
+            }
+            ExprKind::StructDecl { name, .. } | ExprKind::EnumDecl { name, .. } => {
+                self.declare(name);
+            }
+            ExprKind::Import { alias, .. } => self.declare(alias),
+            ExprKind::Export(declaration) => self.expr(declaration),
+        }
+    }
+}
+
+#[cfg(test)]
+mod twig_resolver_tests {
+    use super::*;
+    use crate::twig_descent::{parse_statement, parse_statements};
+    use crate::twig_interp::{Types, interp_program};
+    use crate::twig_parser::MathOperator;
+
+    fn resolved(source: &str, env: &Env) -> Result<Vec<Expression>, Vec<InterpErrors>> {
+        let mut program = parse_statements(source).unwrap();
+        resolve_program(&mut program, env)?;
+        Ok(program.into_iter().map(Expression::without_spans).collect())
+    }
+
+    fn local(name: &str, depth: usize, slot: usize) -> Expression {
+        ExprKind::Local {
+            name: String::from(name),
+            depth,
+            slot,
+        }
+        .into()
+    }
+
+    fn let_stmt(identifier: &str, value: Expression) -> Expression {
+        ExprKind::LetStmt {
+            doc: None,
+            identifier: String::from(identifier),
+            value: Box::new(value),
+            constant: false,
+            annotation: None,
+        }
+        .into()
+    }
+
+    fn body(declaration: &Expression) -> &Expression {
+        let ExprKind::FnDecl { body, .. } = &declaration.kind else {
+            panic!("expected a function, found {:?}", declaration);
+        };
+        body
+    }
+
+    #[test]
+    fn test_resolve_program_slots() {
+        let program = resolved(
+            "fn f(a, b) { let c = a; { let a = 1; [a, b, c] } }",
+            &Env::new(),
+        );
+        let expected: Expression = ExprKind::Block(vec![
+            let_stmt("c", local("a", 1, 0)),
+            ExprKind::Block(vec![
+                let_stmt("a", ExprKind::Integer(1).into()),
+                ExprKind::List(vec![local("a", 0, 0), local("b", 2, 1), local("c", 1, 0)]).into(),
+            ])
+            .into(),
+        ])
+        .into();
+        assert_eq!(*body(&program.unwrap()[0]), expected);
+    }
+
+    #[test]
+    fn test_resolve_program_declaring_again_keeps_the_slot() {
+        let program = resolved("fn f() { let x = 1; let y = x; let x = y; x }", &Env::new());
+        let expected: Expression = ExprKind::Block(vec![
+            let_stmt("x", ExprKind::Integer(1).into()),
+            let_stmt("y", local("x", 0, 0)),
+            let_stmt("x", local("y", 0, 1)),
+            local("x", 0, 0),
+        ])
+        .into();
+        assert_eq!(*body(&program.unwrap()[0]), expected);
+    }
+
+    #[test]
+    fn test_resolve_program_pattern_and_catch_scopes() {
+        let source =
+            "fn f(p) { match p { Pair::Of(a, b) => b, other => try { other } catch e { e } } }";
+        let program = resolved(source, &Env::new()).unwrap();
+        let ExprKind::Block(stmts) = &body(&program[0]).kind else {
+            panic!("expected a block");
+        };
+        let ExprKind::Match { scrutinee, arms } = &stmts[0].kind else {
+            panic!("expected a match");
+        };
+        assert_eq!(**scrutinee, local("p", 1, 0));
+        assert_eq!(arms[0].1, local("b", 0, 1));
+        let ExprKind::Try { body, catch, .. } = &arms[1].1.kind else {
+            panic!("expected a try");
+        };
+        assert_eq!(**body, ExprKind::Block(vec![local("other", 1, 0)]).into());
+        let (_, handler) = catch.as_ref().unwrap();
+        assert_eq!(**handler, ExprKind::Block(vec![local("e", 1, 0)]).into());
+    }
+
+    #[test]
+    fn test_resolve_program_globals_keep_their_names() {
+        // `g` is declared after the function that calls it, which is fine since it only has to
+        // exist by the time the call happens.
+        let program = resolved("fn f() { print(g()) }\nfn g() { 1 }", &Env::new()).unwrap();
+        let call = |callee: &str, args| {
+            ExprKind::Call {
+                callee: Box::new(ExprKind::Identifier(String::from(callee)).into()),
+                args,
+            }
+            .into()
+        };
+        let expected: Expression =
+            ExprKind::Block(vec![call("print", vec![call("g", Vec::new())])]).into();
+        assert_eq!(*body(&program[0]), expected);
+    }
+
+    #[test]
+    fn test_resolve_program_reports_unresolved_names() {
+        let errors = resolved("let x = 1\ny + x\nlet total = 1\ntotl", &Env::new()).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind(), "VariableDoesNotExist");
+        assert_eq!(errors[0].span().map(|span| span.line), Some(2));
+        assert_eq!(
+            errors[1].to_string(),
+            "Undefined variable `totl`; did you mean `total`? at line 4, column 1"
+        );
+    }
+
+    #[test]
+    fn test_resolve_program_leaves_unknown_names_in_functions() {
+        let program = resolved("fn f(x) { y + x }", &Env::new()).unwrap();
+        let expected: Expression = ExprKind::Block(vec![
+            ExprKind::MathOp {
+                lhs: Box::new(ExprKind::Identifier(String::from("y")).into()),
+                op: MathOperator::Add,
+                rhs: Box::new(local("x", 1, 0)),
+            }
+            .into(),
+        ])
+        .into();
+        assert_eq!(*body(&program[0]), expected);
+    }
+
+    #[test]
+    fn test_resolve_program_leaves_unknown_names_in_try() {
+        let program = resolved("try { nope } catch e { e.kind }", &Env::new()).unwrap();
+        let result = interp_program(&program[0], &mut Env::new());
+        assert_eq!(
+            result,
+            Ok(Types::String(String::from("VariableDoesNotExist")))
+        );
+
+        // Only the body of the `try` is left for later, since only its errors are caught.
+        assert!(resolved("try { 1 } catch e { nope }", &Env::new()).is_err());
+    }
+
+    #[test]
+    fn test_resolve_program_functions_cannot_see_enclosing_locals() {
+        let program = resolved("{ let a = 1; fn f() { a } }", &Env::new()).unwrap();
+        let ExprKind::Block(stmts) = &program[0].kind else {
+            panic!("expected a block");
+        };
+        let expected: Expression =
+            ExprKind::Block(vec![ExprKind::Identifier(String::from("a")).into()]).into();
+        assert_eq!(*body(&stmts[1]), expected);
+    }
+
+    #[test]
+    fn test_resolve_program_sees_earlier_globals() {
+        let mut env = Env::new();
+        for statement in parse_statements("let a = 1").unwrap() {
+            interp_program(&statement, &mut env).unwrap();
+        }
+        assert!(resolved("a", &env).is_ok());
+        assert!(resolved("b", &env).is_err());
+    }
+
+    #[test]
+    fn test_resolve_program_functions_see_later_repl_lines() {
+        let mut env = Env::new();
+        let lines = [
+            "fn is_even(n) { match n { 0 => true, _ => is_odd(n - 1) } }",
+            "fn is_odd(n) { match n { 0 => false, _ => is_even(n - 1) } }",
+            "is_even(10)",
+        ];
+        let mut result = Ok(Types::Unit);
+        for line in lines {
+            let mut statement = parse_statement(line).unwrap();
+            resolve_program(std::slice::from_mut(&mut statement), &env).unwrap();
+            result = interp_program(&statement, &mut env);
+        }
+        assert_eq!(result, Ok(Types::Boolean(true)));
+    }
+
+    #[test]
+    fn test_resolve_program_reports_internal_errors() {
+        let mut program: Vec<Expression> = vec![
+            ExprKind::ImplBlock {
+                type_name: String::from("Point"),
+                methods: vec![ExprKind::Integer(1).into()],
+            }
+            .into(),
+        ];
+
+        let errors = resolve_program(&mut program, &Env::new()).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), "Internal");
+    }
+}
@@ -0,0 +1,1876 @@
+use core::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::twig_parser::{
+    Accessor, Annotated, BooleanOperator, ExprKind, Expression, Pattern, PatternKind, Span,
+    TypeExpr, TypeKind,
+};
+
+/// What the checker knows about a value before the program runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Bool,
+    String,
+    Nil,
+    Unit,
+    List(Box<Type>),
+    /// A record of a struct or a value of an enum, by the name of its type along with what its
+    /// type parameters stand for.
+    Named(String, Vec<Type>),
+    /// A type parameter of the generic function or struct being checked. It only fits itself,
+    /// since the code has to work whatever it ends up standing for.
+    Param(String),
+    Function {
+        params: Vec<Type>,
+        ret: Box<Type>,
+    },
+    Module,
+    /// A type that hasn't been worked out yet. Unifying it with another type fills it in.
+    Var(usize),
+    /// Could be anything. Unannotated code that can't be worked out gets this type, and it's
+    /// compatible with every other type, so only code whose types are known gets checked.
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub message: String,
+    /// The innermost expression being checked when the error was found.
+    pub span: Option<Span>,
+}
+
+/// The type of a name. Functions can be used at a different type each time they're referred to,
+/// so the variables in `vars` are swapped for fresh ones on every use.
+#[derive(Debug, Clone, PartialEq)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Scheme {
+        Scheme {
+            vars: Vec::new(),
+            ty,
+        }
+    }
+}
+
+type Scope = BTreeMap<String, Scheme>;
+
+/// A struct's type parameters and the declared types of its fields.
+#[derive(Debug, Clone)]
+struct StructType {
+    params: Vec<String>,
+    fields: Vec<(String, Option<Type>)>,
+}
+
+impl StructType {
+    /// The type of a field of a record whose type parameters stand for `args`. In strict mode
+    /// each unannotated field has a type parameter of its own, after the declared ones and in
+    /// the order the fields are declared, and is `Unknown` otherwise.
+    fn field(&self, field: &str, args: &[Type]) -> Option<Type> {
+        let mut slot = self.params.len();
+        for (name, ty) in &self.fields {
+            match ty {
+                Some(ty) if name == field => {
+                    let params = self.params.iter().cloned().zip(args.iter().cloned());
+                    return Some(replace_params(ty, &params.collect()));
+                }
+                None if name == field => {
+                    return Some(args.get(slot).cloned().unwrap_or(Type::Unknown));
+                }
+                Some(_) => {}
+                None => slot += 1,
+            }
+        }
+        None
+    }
+}
+
+/// Checks a program for type errors before it runs. Annotations are optional: anything without
+/// one gets the type of its value when that can be worked out, and `Unknown` otherwise.
+///
+/// In strict mode nothing is `Unknown` unless it's annotated as `any`. Everything else gets a type
+/// variable instead, which is worked out from how it's used, Hindley-Milner style, and programs
+/// whose types don't line up are rejected.
+///
+/// Like `Env`, the checker keeps track of what has been declared so far, so the REPL can keep one
+/// around between lines.
+#[derive(Debug, Clone)]
+pub struct Checker {
+    strict: bool,
+    globals: Scope,
+    scopes: Vec<Scope>,
+    structs: BTreeMap<String, StructType>,
+    /// Each enum's variants along with how many fields they have.
+    enums: BTreeMap<String, Vec<(String, usize)>>,
+    /// The signatures of the functions in each type's `impl` blocks.
+    methods: BTreeMap<String, BTreeMap<String, Scheme>>,
+    /// The declared return type of each function being checked, innermost last.
+    returns: Vec<Type>,
+    /// What each type variable has been unified with so far.
+    bindings: Vec<Option<Type>>,
+    /// The type parameters of the generic functions and structs being checked.
+    type_params: Vec<String>,
+    /// Where the innermost expression being checked came from, if it was parsed.
+    span: Option<Span>,
+    errors: Vec<TypeError>,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Nil => write!(f, "nil"),
+            Type::Unit => write!(f, "unit"),
+            Type::List(item) => write!(f, "[{}]", item),
+            Type::Named(name, args) if args.is_empty() => write!(f, "{}", name),
+            Type::Named(name, args) => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}<{}>", name, args.join(", "))
+            }
+            Type::Param(name) => write!(f, "{}", name),
+            Type::Function { params, ret } => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), ret)
+            }
+            Type::Module => write!(f, "module"),
+            Type::Var(var) if *var < 26 => write!(f, "'{}", (b'a' + *var as u8) as char),
+            Type::Var(var) => write!(f, "'t{}", var),
+            Type::Unknown => write!(f, "any"),
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The type of a value that could come from either of two places.
+fn join(a: Type, b: Type) -> Type {
+    match a == b {
+        true => a,
+        false => Type::Unknown,
+    }
+}
+
+fn collect_vars(ty: &Type, vars: &mut Vec<usize>) {
+    match ty {
+        Type::Var(var) if !vars.contains(var) => vars.push(*var),
+        Type::List(item) => collect_vars(item, vars),
+        Type::Named(_, args) => args.iter().for_each(|arg| collect_vars(arg, vars)),
+        Type::Function { params, ret } => {
+            params.iter().for_each(|param| collect_vars(param, vars));
+            collect_vars(ret, vars);
+        }
+        _ => {}
+    }
+}
+
+/// Rebuilds `ty` with `replace` applied to every type in it that isn't made of other types.
+fn map_leaves(ty: &Type, replace: &impl Fn(&Type) -> Option<Type>) -> Type {
+    match ty {
+        Type::List(item) => Type::List(Box::new(map_leaves(item, replace))),
+        Type::Named(name, args) => Type::Named(
+            name.clone(),
+            args.iter().map(|arg| map_leaves(arg, replace)).collect(),
+        ),
+        Type::Function { params, ret } => Type::Function {
+            params: params
+                .iter()
+                .map(|param| map_leaves(param, replace))
+                .collect(),
+            ret: Box::new(map_leaves(ret, replace)),
+        },
+        ty => replace(ty).unwrap_or_else(|| ty.clone()),
+    }
+}
+
+fn replace_vars(ty: &Type, replacements: &BTreeMap<usize, Type>) -> Type {
+    map_leaves(ty, &|ty| match ty {
+        Type::Var(var) => replacements.get(var).cloned(),
+        _ => None,
+    })
+}
+
+fn replace_params(ty: &Type, replacements: &BTreeMap<String, Type>) -> Type {
+    map_leaves(ty, &|ty| match ty {
+        Type::Param(name) => replacements.get(name).cloned(),
+        _ => None,
+    })
+}
+
+/// Renames the type variables in `types` to `'a`, `'b`, ... in the order they appear, so that
+/// messages don't depend on how many variables were made before.
+fn normalize(types: &[Type]) -> Vec<Type> {
+    let mut vars = Vec::new();
+    types.iter().for_each(|ty| collect_vars(ty, &mut vars));
+    let replacements = vars
+        .into_iter()
+        .enumerate()
+        .map(|(i, var)| (var, Type::Var(i)))
+        .collect();
+    types
+        .iter()
+        .map(|ty| replace_vars(ty, &replacements))
+        .collect()
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Checker {
+        let result = vec![(String::from("Ok"), 1), (String::from("Err"), 1)];
+        Checker {
+            strict: false,
+            globals: Scope::new(),
+            scopes: Vec::new(),
+            structs: BTreeMap::new(),
+            enums: BTreeMap::from([(String::from("Result"), result)]),
+            methods: BTreeMap::new(),
+            returns: Vec::new(),
+            bindings: Vec::new(),
+            type_params: Vec::new(),
+            span: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn strict() -> Checker {
+        Checker {
+            strict: true,
+            ..Checker::new()
+        }
+    }
+
+    /// Checks one statement, returning its type or every error found in it.
+    pub fn check(&mut self, expr: &Expression) -> Result<Type, Vec<TypeError>> {
+        let ty = self.expr(expr);
+        match self.errors.is_empty() {
+            true => Ok(normalize(&[self.substitute(&ty)]).remove(0)),
+            false => Err(std::mem::take(&mut self.errors)),
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        let span = self.span;
+        self.errors.push(TypeError { message, span });
+    }
+
+    fn fresh_var(&mut self) -> usize {
+        self.bindings.push(None);
+        self.bindings.len() - 1
+    }
+
+    fn fresh(&mut self) -> Type {
+        Type::Var(self.fresh_var())
+    }
+
+    /// The type of something nothing is known about yet.
+    fn unknown(&mut self) -> Type {
+        match self.strict {
+            true => self.fresh(),
+            false => Type::Unknown,
+        }
+    }
+
+    /// Follows type variables that have been filled in until reaching something that isn't one.
+    fn shallow(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(var) => match &self.bindings[*var] {
+                Some(bound) => self.shallow(bound),
+                None => ty.clone(),
+            },
+            ty => ty.clone(),
+        }
+    }
+
+    /// Fills in every type variable in `ty` that has been worked out.
+    fn substitute(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::List(item) => Type::List(Box::new(self.substitute(&item))),
+            Type::Named(name, args) => {
+                Type::Named(name, args.iter().map(|arg| self.substitute(arg)).collect())
+            }
+            Type::Function { params, ret } => Type::Function {
+                params: params.iter().map(|param| self.substitute(param)).collect(),
+                ret: Box::new(self.substitute(&ret)),
+            },
+            ty => ty,
+        }
+    }
+
+    /// Makes two types the same by filling in type variables, returning whether that's possible.
+    fn unify(&mut self, expected: &Type, actual: &Type) -> bool {
+        match (self.shallow(expected), self.shallow(actual)) {
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                // A type can't contain itself, as in `fn f(x) { f }` applied to itself.
+                let mut vars = Vec::new();
+                collect_vars(&self.substitute(&ty), &mut vars);
+                if vars.contains(&var) {
+                    return false;
+                }
+                self.bindings[var] = Some(ty);
+                true
+            }
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::List(expected), Type::List(actual)) => self.unify(&expected, &actual),
+            (Type::Named(name, args), Type::Named(actual_name, actual_args)) => {
+                name == actual_name
+                    && args.len() == actual_args.len()
+                    && args
+                        .iter()
+                        .zip(&actual_args)
+                        .all(|(expected, actual)| self.unify(expected, actual))
+            }
+            (
+                Type::Function { params, ret },
+                Type::Function {
+                    params: actual_params,
+                    ret: actual_ret,
+                },
+            ) => {
+                params.len() == actual_params.len()
+                    && params
+                        .iter()
+                        .zip(&actual_params)
+                        .all(|(expected, actual)| self.unify(expected, actual))
+                    && self.unify(&ret, &actual_ret)
+            }
+            (expected, actual) => expected == actual,
+        }
+    }
+
+    /// Reports `expr` when its type doesn't fit where it's used. `context` says where that is.
+    fn expect(&mut self, expected: &Type, actual: &Type, expr: &Expression, context: &str) {
+        if !self.unify(expected, actual) {
+            let types = normalize(&[self.substitute(actual), self.substitute(expected)]);
+            let message = format!(
+                "Mismatched types in {}: `{}` is {}, expected {}",
+                context, expr, types[0], types[1]
+            );
+            let span = expr.span.or(self.span);
+            self.errors.push(TypeError { message, span });
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let replacements = scheme.vars.iter().map(|var| (*var, self.fresh())).collect();
+        replace_vars(&scheme.ty, &replacements)
+    }
+
+    /// Turns the type variables of `ty` that nothing else in scope refers to into parameters of
+    /// the scheme, so that each use of the name can fill them in differently.
+    fn generalize(&self, ty: &Type) -> Scheme {
+        let mut in_scope = Vec::new();
+        for scheme in self
+            .scopes
+            .iter()
+            .chain([&self.globals])
+            .flat_map(|s| s.values())
+        {
+            let mut vars = Vec::new();
+            collect_vars(&self.substitute(&scheme.ty), &mut vars);
+            in_scope.extend(vars.into_iter().filter(|var| !scheme.vars.contains(var)));
+        }
+        let in_scope: BTreeSet<usize> = in_scope.into_iter().collect();
+
+        let ty = self.substitute(ty);
+        let mut vars = Vec::new();
+        collect_vars(&ty, &mut vars);
+        vars.retain(|var| !in_scope.contains(var));
+        Scheme { vars, ty }
+    }
+
+    /// Generalizes the type of a generic function, whose type parameters can stand for something
+    /// different each time it's used.
+    fn quantify(&mut self, ty: &Type, params: &[String]) -> Scheme {
+        let mut scheme = self.generalize(ty);
+        let mut replacements = BTreeMap::new();
+        for param in params {
+            let var = self.fresh_var();
+            scheme.vars.push(var);
+            replacements.insert(param.clone(), Type::Var(var));
+        }
+        scheme.ty = replace_params(&scheme.ty, &replacements);
+        scheme
+    }
+
+    /// How many type parameters a struct or enum has besides the ones it declares. In strict
+    /// mode each unannotated struct field and each enum payload gets one, so that what a value
+    /// is built from and what's taken back out of it have the same type.
+    fn slots(&self, name: &str) -> usize {
+        if !self.strict {
+            return 0;
+        }
+        match (self.structs.get(name), self.enums.get(name)) {
+            (Some(declared), _) => declared
+                .fields
+                .iter()
+                .filter(|(_, ty)| ty.is_none())
+                .count(),
+            (None, Some(variants)) => variants.iter().map(|(_, arity)| arity).sum(),
+            (None, None) => 0,
+        }
+    }
+
+    /// A struct or enum type with fresh type variables for its type parameters.
+    fn instance(&mut self, name: &str) -> Type {
+        let params = self.structs.get(name).map_or(0, |s| s.params.len()) + self.slots(name);
+        Type::Named(
+            String::from(name),
+            (0..params).map(|_| self.fresh()).collect(),
+        )
+    }
+
+    /// A fresh instance of an enum type along with the types of a variant's payload in it, or
+    /// `None` when the variant doesn't exist.
+    fn variant(&mut self, enum_name: &str, variant: &str) -> Option<(Type, Vec<Type>)> {
+        let variants = self.enums.get(enum_name)?;
+        let position = variants.iter().position(|(name, _)| name == variant)?;
+        let offset: usize = variants[..position].iter().map(|(_, arity)| arity).sum();
+        let arity = variants[position].1;
+        let ty = self.instance(enum_name);
+        let Type::Named(_, args) = &ty else {
+            return None;
+        };
+        let payload = (offset..offset + arity)
+            .map(|slot| args.get(slot).cloned().unwrap_or(Type::Unknown))
+            .collect();
+        Some((ty, payload))
+    }
+
+    /// Runs `check` with more type parameters in scope.
+    fn with_type_params<T>(&mut self, params: &[String], check: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = self.type_params.len();
+        self.type_params.extend(params.iter().cloned());
+        let result = check(self);
+        self.type_params.truncate(outer);
+        result
+    }
+
+    fn builtin_type(&mut self, name: &str) -> Option<Type> {
+        let ret = match name {
+            "type_of" => Type::String,
+            "print" => Type::Unit,
+            "Ok" | "Err" => {
+                let (ty, payload) = self.variant("Result", name)?;
+                return Some(Type::Function {
+                    params: payload,
+                    ret: Box::new(ty),
+                });
+            }
+            _ => return None,
+        };
+        Some(Type::Function {
+            params: vec![self.fresh()],
+            ret: Box::new(ret),
+        })
+    }
+
+    /// The parameters and result of the methods every string and list has, from `twig_builtins`.
+    /// Lists can hold anything unless the checker is strict, when every item has the same type.
+    fn builtin_method(&mut self, receiver: &Type, method: &str) -> Option<(Vec<Type>, Type)> {
+        let item = match receiver {
+            Type::List(item) if self.strict => (**item).clone(),
+            _ => Type::Unknown,
+        };
+        match (receiver, method) {
+            (Type::String, "len") => Some((vec![], Type::Int)),
+            (Type::String, "upper" | "lower") => Some((vec![], Type::String)),
+            (Type::String, "contains") => Some((vec![Type::String], Type::Bool)),
+            (Type::List(_), "len") => Some((vec![], Type::Int)),
+            (Type::List(_), "contains") => Some((vec![item], Type::Bool)),
+            (Type::List(_), "get") => Some((vec![Type::Int], item)),
+            (Type::List(list_item), "push") => Some((vec![item], Type::List(list_item.clone()))),
+            _ => None,
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Type {
+        let scheme = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+            .cloned();
+        match scheme {
+            Some(scheme) => self.instantiate(&scheme),
+            None => match self.builtin_type(name) {
+                Some(ty) => ty,
+                None if self.strict => {
+                    self.error(format!("Variable does not exist: `{}`", name));
+                    Type::Unknown
+                }
+                None => Type::Unknown,
+            },
+        }
+    }
+
+    fn declare(&mut self, name: String, scheme: Scheme) {
+        match self.scopes.last_mut() {
+            Some(scope) => scope.insert(name, scheme),
+            None => self.globals.insert(name, scheme),
+        };
+    }
+
+    /// The type an annotation names. Types that don't exist are reported at the annotation.
+    fn resolve(&mut self, ty: &TypeExpr) -> Type {
+        let outer = self.span;
+        self.span = ty.span.or(outer);
+        let resolved = match &ty.kind {
+            TypeKind::Named(name) if self.type_params.contains(name) => Type::Param(name.clone()),
+            TypeKind::Named(name) => match name.as_str() {
+                "int" => Type::Int,
+                "bool" => Type::Bool,
+                "string" => Type::String,
+                "nil" => Type::Nil,
+                "unit" => Type::Unit,
+                "any" => Type::Unknown,
+                "module" => Type::Module,
+                // Type parameters that aren't given can be anything.
+                name if self.structs.contains_key(name) || self.enums.contains_key(name) => {
+                    let params = self.structs.get(name).map_or(0, |s| s.params.len());
+                    let params = params + self.slots(name);
+                    Type::Named(
+                        String::from(name),
+                        (0..params).map(|_| self.unknown()).collect(),
+                    )
+                }
+                name => {
+                    self.error(format!("Unknown type `{}`", name));
+                    Type::Unknown
+                }
+            },
+            TypeKind::Generic { name, args } => {
+                let args: Vec<Type> = args.iter().map(|arg| self.resolve(arg)).collect();
+                match self.structs.get(name) {
+                    Some(declared) if declared.params.len() == args.len() => {
+                        let slots = (0..self.slots(name)).map(|_| self.unknown());
+                        Type::Named(name.clone(), args.into_iter().chain(slots).collect())
+                    }
+                    Some(declared) => {
+                        let message = format!(
+                            "`{}` takes {} type parameters, found {}",
+                            name,
+                            declared.params.len(),
+                            args.len()
+                        );
+                        self.error(message);
+                        Type::Unknown
+                    }
+                    None => {
+                        self.error(format!("Unknown type `{}`", ty));
+                        Type::Unknown
+                    }
+                }
+            }
+            TypeKind::List(item) => Type::List(Box::new(self.resolve(item))),
+            TypeKind::Function { params, ret } => Type::Function {
+                params: params.iter().map(|param| self.resolve(param)).collect(),
+                ret: Box::new(self.resolve(ret)),
+            },
+        };
+        self.span = outer;
+        resolved
+    }
+
+    /// The type of a function from its annotations. In an `impl` block an unannotated first
+    /// parameter is the value the method was called on.
+    fn signature(
+        &mut self,
+        params: &[Annotated],
+        return_type: &Option<TypeExpr>,
+        receiver: Option<&Type>,
+    ) -> Type {
+        let params = params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| match (&param.annotation, receiver) {
+                (Some(ty), _) => self.resolve(ty),
+                (None, Some(receiver)) if i == 0 => receiver.clone(),
+                (None, _) => self.unknown(),
+            })
+            .collect();
+        let ret = match return_type {
+            Some(ty) => self.resolve(ty),
+            None => self.unknown(),
+        };
+        Type::Function {
+            params,
+            ret: Box::new(ret),
+        }
+    }
+
+    /// Checks a function's body against its signature. Like at run time, the body only sees
+    /// globals and its own parameters.
+    fn function_body(
+        &mut self,
+        name: &str,
+        params: &[Annotated],
+        signature: &Type,
+        body: &Expression,
+    ) {
+        let Type::Function {
+            params: param_types,
+            ret,
+        } = signature
+        else {
+            return;
+        };
+
+        let scope = params
+            .iter()
+            .map(|param| param.name.clone())
+            .zip(param_types.iter().cloned().map(Scheme::mono))
+            .collect();
+        let caller = std::mem::replace(&mut self.scopes, vec![scope]);
+        self.returns.push((**ret).clone());
+        let body_type = self.expr(body);
+        self.returns.pop();
+        self.scopes = caller;
+
+        // Point at the statement the result comes from rather than the whole body.
+        let result = match &body.kind {
+            ExprKind::Block(stmts) => stmts.last().unwrap_or(body),
+            _ => body,
+        };
+        self.expect(
+            ret,
+            &body_type,
+            result,
+            &format!("the result of `{}`", name),
+        );
+    }
+
+    /// Binds the names in a pattern. When strict, the pattern also has to fit the value matched.
+    fn bind_pattern(
+        &mut self,
+        expr: &Expression,
+        pattern: &Pattern,
+        ty: &Type,
+        bindings: &mut Scope,
+    ) {
+        let pattern_type = match &pattern.kind {
+            PatternKind::Wildcard => return,
+            PatternKind::Binding(name) => {
+                bindings.insert(name.clone(), Scheme::mono(ty.clone()));
+                return;
+            }
+            PatternKind::Literal(value) => self.expr(value),
+            PatternKind::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                let (ty, payload) = self
+                    .variant(enum_name, variant)
+                    .unwrap_or_else(|| (self.instance(enum_name), Vec::new()));
+                for (i, field) in fields.iter().enumerate() {
+                    let field_type = match payload.get(i) {
+                        Some(field_type) => field_type.clone(),
+                        None => self.unknown(),
+                    };
+                    self.bind_pattern(expr, field, &field_type, bindings);
+                }
+                ty
+            }
+        };
+
+        if self.strict && !self.unify(ty, &pattern_type) {
+            let message = format!(
+                "Mismatched types in `{}`: pattern `{}` is {}, expected {}",
+                expr,
+                pattern,
+                pattern_type,
+                self.substitute(ty)
+            );
+            let span = pattern.span.or(self.span);
+            self.errors.push(TypeError { message, span });
+        }
+    }
+
+    fn with_scope(&mut self, scope: Scope, expr: &Expression) -> Type {
+        self.scopes.push(scope);
+        let ty = self.expr(expr);
+        self.scopes.pop();
+        ty
+    }
+
+    fn call(&mut self, expr: &Expression, callee: Type, args: &[Expression]) -> Type {
+        let arg_types: Vec<Type> = args.iter().map(|arg| self.expr(arg)).collect();
+        self.apply(expr, callee, args, &arg_types)
+    }
+
+    /// Checks already checked arguments against the type of what they're passed to.
+    fn apply(
+        &mut self,
+        expr: &Expression,
+        callee: Type,
+        args: &[Expression],
+        arg_types: &[Type],
+    ) -> Type {
+        match self.shallow(&callee) {
+            Type::Function { params, ret } => {
+                if params.len() != args.len() {
+                    self.error(format!(
+                        "Wrong number of arguments in `{}`: expected {}, found {}",
+                        expr,
+                        params.len(),
+                        args.len()
+                    ));
+                    return *ret;
+                }
+                for ((param, arg_type), arg) in params.iter().zip(arg_types).zip(args) {
+                    self.expect(param, arg_type, arg, &format!("`{}`", expr));
+                }
+                *ret
+            }
+            // Calling something makes it a function of its arguments.
+            callee @ Type::Var(_) if self.strict => {
+                let ret = self.fresh();
+                let function = Type::Function {
+                    params: arg_types.to_vec(),
+                    ret: Box::new(ret.clone()),
+                };
+                if !self.unify(&callee, &function) {
+                    self.error(format!(
+                        "The type of `{}` would have to contain itself",
+                        expr
+                    ));
+                }
+                ret
+            }
+            Type::Unknown | Type::Var(_) => Type::Unknown,
+            callee => {
+                self.error(format!("Cannot call {} in `{}`", callee, expr));
+                Type::Unknown
+            }
+        }
+    }
+
+    /// Works out the type of a value from the only kind of value that has a method or field of
+    /// the given name, or reports that it needs an annotation.
+    fn infer_from_member(
+        &mut self,
+        expr: &Expression,
+        target: &Type,
+        member: &str,
+        candidates: Vec<Type>,
+    ) -> Type {
+        match candidates.as_slice() {
+            [ty] => {
+                self.unify(target, ty);
+                ty.clone()
+            }
+            _ => {
+                self.error(format!(
+                    "The type of `{}` can't be worked out from `.{}`, so it needs an annotation",
+                    expr, member
+                ));
+                Type::Unknown
+            }
+        }
+    }
+
+    fn method_call(
+        &mut self,
+        expr: &Expression,
+        receiver_expr: &Expression,
+        receiver: Type,
+        method: &str,
+        args: &[Expression],
+    ) -> Type {
+        let mut receiver = self.shallow(&receiver);
+        if matches!(receiver, Type::Var(_)) && self.strict {
+            let mut candidates: Vec<Type> = self
+                .methods
+                .iter()
+                .filter(|(_, methods)| methods.contains_key(method))
+                .map(|(type_name, _)| type_name.clone())
+                .collect::<Vec<String>>()
+                .into_iter()
+                .map(|type_name| self.instance(&type_name))
+                .collect();
+            if self.builtin_method(&Type::String, method).is_some() {
+                candidates.push(Type::String);
+            }
+            let list = Type::List(Box::new(self.fresh()));
+            if self.builtin_method(&list, method).is_some() {
+                candidates.push(list);
+            }
+            receiver = self.infer_from_member(receiver_expr, &receiver, method, candidates);
+        }
+
+        let signature = match &receiver {
+            Type::Named(name, _) => {
+                match self.methods.get(name).and_then(|m| m.get(method)).cloned() {
+                    // The receiver is passed as the first parameter.
+                    Some(scheme) => match self.instantiate(&scheme) {
+                        Type::Function { params, ret } if !params.is_empty() => {
+                            self.expect(
+                                &params[0],
+                                &receiver,
+                                receiver_expr,
+                                &format!("`{}`", expr),
+                            );
+                            Type::Function {
+                                params: params[1..].to_vec(),
+                                ret,
+                            }
+                        }
+                        _ => Type::Unknown,
+                    },
+                    // Records can also hold functions in their fields.
+                    None => self.unknown(),
+                }
+            }
+            Type::String | Type::List(_) => match self.builtin_method(&receiver, method) {
+                Some((params, ret)) => Type::Function {
+                    params,
+                    ret: Box::new(ret),
+                },
+                None => {
+                    self.error(format!(
+                        "{} has no method `{}` in `{}`",
+                        self.substitute(&receiver),
+                        method,
+                        expr
+                    ));
+                    Type::Unknown
+                }
+            },
+            Type::Unknown | Type::Module | Type::Var(_) => Type::Unknown,
+            receiver => {
+                self.error(format!(
+                    "{} has no method `{}` in `{}`",
+                    receiver, method, expr
+                ));
+                Type::Unknown
+            }
+        };
+
+        let arg_types: Vec<Type> = args.iter().map(|arg| self.expr(arg)).collect();
+        let ret = self.apply(expr, signature, args, &arg_types);
+        match (&receiver, method, arg_types.as_slice()) {
+            // Pushing something of another type makes a list that holds both.
+            (Type::List(item), "push", [pushed]) if !self.strict => {
+                Type::List(Box::new(join((**item).clone(), pushed.clone())))
+            }
+            _ => ret,
+        }
+    }
+
+    fn comparison(&mut self, expr: &Expression, op: &BooleanOperator, lhs: Type, rhs: Type) {
+        let ordering = *op != BooleanOperator::Eqaul;
+        let (lhs, rhs) = (self.shallow(&lhs), self.shallow(&rhs));
+        match (&lhs, &rhs) {
+            // nil is only ever equal to itself, so any value can be compared with it for equality.
+            (Type::Nil, _) | (_, Type::Nil) if ordering => {
+                self.error(format!("Cannot order nil in `{}`", expr))
+            }
+            (Type::Nil, _) | (_, Type::Nil) => {}
+            (lhs, rhs) if !self.unify(lhs, rhs) => self.error(format!(
+                "Cannot compare {} with {} in `{}`",
+                self.substitute(lhs),
+                self.substitute(rhs),
+                expr
+            )),
+            _ if !ordering => {}
+            (lhs, _) => match self.substitute(lhs) {
+                Type::Int | Type::Bool | Type::Unknown | Type::Var(_) => {}
+                ty => self.error(format!("Cannot order values of type {} in `{}`", ty, expr)),
+            },
+        }
+    }
+
+    /// The type of a value that could come from any of `exprs`. When strict they all have to have
+    /// the same type.
+    fn common_type(&mut self, expr: &Expression, exprs: &[&Expression], types: Vec<Type>) -> Type {
+        if !self.strict {
+            return types.into_iter().reduce(join).unwrap_or(Type::Unknown);
+        }
+
+        let common = self.fresh();
+        for (branch, ty) in exprs.iter().zip(&types) {
+            self.expect(&common, ty, branch, &format!("`{}`", expr));
+        }
+        common
+    }
+
+    fn expr(&mut self, expr: &Expression) -> Type {
+        let outer = self.span;
+        self.span = expr.span.or(outer);
+        let ty = self.node(expr);
+        self.span = outer;
+        ty
+    }
+
+    /// Checks one link of a chain of accesses and calls, like the `.c` of `a?.b.c`, or gives `None`
+    /// when a `?.` earlier in the chain is always `nil`, which skips the rest of the chain.
+    fn link(&mut self, expr: &Expression) -> Option<Type> {
+        match &expr.kind {
+            ExprKind::Access {
+                target: target_expr,
+                accessor,
+                optional,
+            } => {
+                let mut target = self.target(target_expr)?;
+                if *optional && self.shallow(&target) == Type::Nil {
+                    return None;
+                }
+                if let (Type::Var(_), Accessor::Field(field), true) =
+                    (self.shallow(&target), accessor, self.strict)
+                {
+                    let candidates = self
+                        .structs
+                        .iter()
+                        .filter(|(_, declared)| declared.fields.iter().any(|(f, _)| f == field))
+                        .map(|(name, _)| name.clone())
+                        .collect::<Vec<String>>()
+                        .into_iter()
+                        .map(|name| self.instance(&name))
+                        .collect();
+                    target = self.infer_from_member(target_expr, &target, field, candidates);
+                }
+
+                let ty = match (self.shallow(&target), accessor) {
+                    (target, Accessor::Index(index)) => {
+                        let index_type = self.expr(index);
+                        self.expect(&Type::Int, &index_type, index, &format!("`{}`", expr));
+                        match target {
+                            // An optional index is nil when it's out of bounds.
+                            Type::List(item) if !optional || self.strict => *item,
+                            Type::Var(_) if self.strict => {
+                                let item = self.fresh();
+                                self.unify(&target, &Type::List(Box::new(item.clone())));
+                                item
+                            }
+                            Type::List(_) | Type::Unknown | Type::Var(_) => Type::Unknown,
+                            target => {
+                                self.error(format!("Cannot index {} in `{}`", target, expr));
+                                Type::Unknown
+                            }
+                        }
+                    }
+                    (Type::Unknown | Type::Module, Accessor::Field(_)) => Type::Unknown,
+                    (Type::Var(_), Accessor::Field(_)) => Type::Unknown,
+                    (Type::Named(name, args), Accessor::Field(field))
+                        if self.structs.contains_key(&name) =>
+                    {
+                        match self.structs[&name].field(field, &args) {
+                            Some(ty) => ty,
+                            None => self.unknown(),
+                        }
+                    }
+                    (target, Accessor::Field(field)) => {
+                        self.error(format!(
+                            "{} has no field `{}` in `{}`",
+                            self.substitute(&target),
+                            field,
+                            expr
+                        ));
+                        Type::Unknown
+                    }
+                };
+                Some(ty)
+            }
+            ExprKind::Call { callee, args } => {
+                let callee_type = self.target(callee)?;
+                Some(self.call(expr, callee_type, args))
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+                optional,
+            } => {
+                let receiver_type = self.target(receiver)?;
+                let ty = match (*optional, self.shallow(&receiver_type)) {
+                    (true, Type::Nil) => {
+                        args.iter().for_each(|arg| {
+                            self.expr(arg);
+                        });
+                        return None;
+                    }
+                    // The method isn't called when the receiver is nil.
+                    (true, _) if !self.strict => {
+                        self.method_call(expr, receiver, receiver_type, method, args);
+                        Type::Unknown
+                    }
+                    _ => self.method_call(expr, receiver, receiver_type, method, args),
+                };
+                Some(ty)
+            }
+            _ => Some(self.node(expr)),
+        }
+    }
+
+    /// Checks what a link of a chain is applied to, carrying on the chain.
+    fn target(&mut self, expr: &Expression) -> Option<Type> {
+        let outer = self.span;
+        self.span = expr.span.or(outer);
+        let ty = self.link(expr);
+        self.span = outer;
+        ty
+    }
+
+    fn node(&mut self, expr: &Expression) -> Type {
+        match &expr.kind {
+            ExprKind::Integer(_) => Type::Int,
+            ExprKind::Boolean(_) => Type::Bool,
+            ExprKind::String(_) => Type::String,
+            ExprKind::Nil => Type::Nil,
+            ExprKind::List(items) => {
+                let types = items.iter().map(|item| self.expr(item)).collect();
+                let items: Vec<&Expression> = items.iter().collect();
+                let item = self.common_type(expr, &items, types);
+                Type::List(Box::new(item))
+            }
+            ExprKind::Identifier(name) | ExprKind::Local { name, .. } => self.lookup(name),
+            ExprKind::MathOp { lhs, rhs, .. } => {
+                let context = format!("`{}`", expr);
+                let lhs_type = self.expr(lhs);
+                self.expect(&Type::Int, &lhs_type, lhs, &context);
+                let rhs_type = self.expr(rhs);
+                self.expect(&Type::Int, &rhs_type, rhs, &context);
+                Type::Int
+            }
+            ExprKind::BooleanOp { lhs, op, rhs } => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                self.comparison(expr, op, lhs, rhs);
+                Type::Bool
+            }
+            ExprKind::Coalesce {
+                lhs: lhs_expr,
+                rhs: rhs_expr,
+            } => {
+                let lhs = self.expr(lhs_expr);
+                let rhs = self.expr(rhs_expr);
+                match self.shallow(&lhs) {
+                    Type::Nil => rhs,
+                    _ if self.strict => {
+                        self.expect(&lhs, &rhs, rhs_expr, &format!("`{}`", expr));
+                        lhs
+                    }
+                    Type::Unknown | Type::Var(_) => join(lhs, rhs),
+                    lhs => lhs,
+                }
+            }
+            ExprKind::Access { .. } | ExprKind::Call { .. } | ExprKind::MethodCall { .. } => {
+                self.link(expr).unwrap_or(Type::Nil)
+            }
+            ExprKind::StructLiteral { name, fields } => {
+                let types: Vec<Type> = fields.iter().map(|(_, value)| self.expr(value)).collect();
+                let Some(declared) = self.structs.get(name).cloned() else {
+                    return self.unknown();
+                };
+                let Type::Named(_, args) = self.instance(name) else {
+                    return Type::Unknown;
+                };
+                for ((field, value), value_type) in fields.iter().zip(&types) {
+                    match declared.field(field, &args) {
+                        Some(field_type) => {
+                            self.expect(&field_type, value_type, value, &format!("`{}`", expr));
+                        }
+                        None => {
+                            self.error(format!("{} has no field `{}` in `{}`", name, field, expr))
+                        }
+                    }
+                }
+                for (field, _) in declared.fields {
+                    if !fields.iter().any(|(name, _)| *name == field) {
+                        self.error(format!("Missing field `{}` in `{}`", field, expr));
+                    }
+                }
+                Type::Named(name.clone(), args)
+            }
+            ExprKind::Variant { enum_name, variant } => {
+                let method = self.methods.get(enum_name).and_then(|m| m.get(variant));
+                if let Some(scheme) = method.cloned() {
+                    return self.instantiate(&scheme);
+                }
+                if !self.enums.contains_key(enum_name) {
+                    return self.unknown();
+                }
+                match self.variant(enum_name, variant) {
+                    Some((ty, payload)) if payload.is_empty() => ty,
+                    Some((ty, payload)) => Type::Function {
+                        params: payload,
+                        ret: Box::new(ty),
+                    },
+                    None => {
+                        self.error(format!("Variant does not exist: `{}`", expr));
+                        Type::Unknown
+                    }
+                }
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let scrutinee = self.expr(scrutinee);
+                let types = arms
+                    .iter()
+                    .map(|(pattern, body)| {
+                        let mut bindings = Scope::new();
+                        self.bind_pattern(expr, pattern, &scrutinee, &mut bindings);
+                        self.with_scope(bindings, body)
+                    })
+                    .collect();
+                let bodies: Vec<&Expression> = arms.iter().map(|(_, body)| body).collect();
+                self.common_type(expr, &bodies, types)
+            }
+            ExprKind::Block(stmts) => {
+                self.scopes.push(Scope::new());
+                let ty = stmts
+                    .iter()
+                    .map(|stmt| self.expr(stmt))
+                    .last()
+                    .unwrap_or(Type::Unit);
+                self.scopes.pop();
+                ty
+            }
+            ExprKind::Throw(value) => {
+                self.expr(value);
+                self.unknown()
+            }
+            ExprKind::Return(value) => {
+                if let Some(value) = value {
+                    let ty = self.expr(value);
+                    if let Some(expected) = self.returns.last().cloned() {
+                        self.expect(&expected, &ty, value, &format!("`{}`", expr));
+                    }
+                }
+                self.unknown()
+            }
+            ExprKind::Propagate(value) => {
+                let ty = self.expr(value);
+                let result = self.instance("Result");
+                match self.shallow(&ty) {
+                    Type::Unknown => return Type::Unknown,
+                    Type::Var(_) if !self.strict => return Type::Unknown,
+                    _ if self.unify(&ty, &result) => {}
+                    ty => {
+                        self.error(format!(
+                            "`?` needs a Result, but `{}` is {}",
+                            value,
+                            self.substitute(&ty)
+                        ));
+                        return Type::Unknown;
+                    }
+                }
+                let Type::Named(_, args) = result else {
+                    return Type::Unknown;
+                };
+                match args.as_slice() {
+                    // An `Err` is returned from the enclosing function as it is.
+                    [ok, err] => {
+                        if let Some(expected) = self.returns.last().cloned() {
+                            let returned = vec![self.fresh(), err.clone()];
+                            let returned = Type::Named(String::from("Result"), returned);
+                            self.expect(&expected, &returned, value, &format!("`{}`", expr));
+                        }
+                        ok.clone()
+                    }
+                    _ => self.unknown(),
+                }
+            }
+            ExprKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                let mut ty = self.expr(body);
+                if let Some((name, handler)) = catch {
+                    let error = self.unknown();
+                    let scope = Scope::from([(name.clone(), Scheme::mono(error))]);
+                    let handler_type = self.with_scope(scope, handler);
+                    ty = self.common_type(expr, &[body, handler], vec![ty, handler_type]);
+                }
+                if let Some(finally) = finally {
+                    self.expr(finally);
+                }
+                ty
+            }
+            ExprKind::LetStmt {
+                identifier,
+                value,
+                annotation,
+                ..
+            } => {
+                let value_type = self.expr(value);
+                let ty = match annotation {
+                    Some(annotation) => {
+                        let annotation = self.resolve(annotation);
+                        let context = format!("the declaration of `{}`", identifier);
+                        self.expect(&annotation, &value_type, value, &context);
+                        annotation
+                    }
+                    None => value_type,
+                };
+                self.declare(identifier.clone(), Scheme::mono(ty));
+                Type::Unit
+            }
+            ExprKind::FnDecl {
+                name,
+                type_params,
+                params,
+                return_type,
+                body,
+                ..
+            } => {
+                let signature = self.with_type_params(type_params, |checker| {
+                    let signature = checker.signature(params, return_type, None);
+                    // Declared before the body is checked so that it can call itself.
+                    checker.declare(name.clone(), Scheme::mono(signature.clone()));
+                    checker.function_body(name, params, &signature, body);
+                    signature
+                });
+                // Its own declaration isn't something else in scope holding on to its variables.
+                match self.scopes.last_mut() {
+                    Some(scope) => scope.remove(name),
+                    None => self.globals.remove(name),
+                };
+                let scheme = self.quantify(&signature, type_params);
+                self.declare(name.clone(), scheme);
+                Type::Unit
+            }
+            ExprKind::ImplBlock { type_name, methods } => {
+                // The type parameters of a generic struct can be used throughout its methods.
+                let struct_params = self
+                    .structs
+                    .get(type_name)
+                    .map_or(Vec::new(), |declared| declared.params.clone());
+                let slots: Vec<Type> = (0..self.slots(type_name)).map(|_| self.fresh()).collect();
+                let args = struct_params.iter().cloned().map(Type::Param).chain(slots);
+                let receiver = Type::Named(type_name.clone(), args.collect());
+                let mut signatures = Vec::new();
+                for method in methods {
+                    if let ExprKind::FnDecl {
+                        name,
+                        type_params,
+                        params,
+                        return_type,
+                        ..
+                    } = &method.kind
+                    {
+                        let type_params = [struct_params.clone(), type_params.clone()].concat();
+                        let signature = self.with_type_params(&type_params, |checker| {
+                            checker.signature(params, return_type, Some(&receiver))
+                        });
+                        self.methods
+                            .entry(type_name.clone())
+                            .or_default()
+                            .insert(name.clone(), Scheme::mono(signature.clone()));
+                        signatures.push((name, type_params, signature));
+                    }
+                }
+                // Every signature is known before any body is checked, so methods can call each
+                // other in any order.
+                for (method, (_, type_params, signature)) in methods.iter().zip(&signatures) {
+                    if let ExprKind::FnDecl {
+                        name, params, body, ..
+                    } = &method.kind
+                    {
+                        self.with_type_params(type_params, |checker| {
+                            checker.function_body(name, params, signature, body)
+                        });
+                    }
+                }
+                for (name, type_params, signature) in signatures {
+                    let scheme = self.quantify(&signature, &type_params);
+                    self.methods
+                        .entry(type_name.clone())
+                        .or_default()
+                        .insert(name.clone(), scheme);
+                }
+                Type::Unit
+            }
+            ExprKind::Import { alias, .. } => {
+                self.declare(alias.clone(), Scheme::mono(Type::Module));
+                Type::Unit
+            }
+            ExprKind::Export(declaration) => self.expr(declaration),
+            ExprKind::StructDecl {
+                name,
+                type_params,
+                fields,
+            } => {
+                // Declared before its fields are resolved so that they can refer to it, with the
+                // same unannotated fields.
+                let declared = StructType {
+                    params: type_params.clone(),
+                    fields: fields
+                        .iter()
+                        .map(|field| {
+                            let ty = field.annotation.as_ref().map(|_| Type::Unknown);
+                            (field.name.clone(), ty)
+                        })
+                        .collect(),
+                };
+                self.structs.insert(name.clone(), declared);
+                let fields = self.with_type_params(type_params, |checker| {
+                    fields
+                        .iter()
+                        .map(|field| {
+                            let ty = field.annotation.as_ref().map(|ty| checker.resolve(ty));
+                            (field.name.clone(), ty)
+                        })
+                        .collect()
+                });
+                self.structs.get_mut(name).unwrap().fields = fields;
+                self.declare(name.clone(), Scheme::mono(Type::Unknown));
+                Type::Unit
+            }
+            ExprKind::EnumDecl { name, variants } => {
+                let variants = variants
+                    .iter()
+                    .map(|(variant, fields)| (variant.clone(), fields.len()))
+                    .collect();
+                self.enums.insert(name.clone(), variants);
+                self.declare(name.clone(), Scheme::mono(Type::Unknown));
+                Type::Unit
+            }
+        }
+    }
+}
+
+/// Checks every statement of a file in order.
+pub fn check_program(program: &[Expression], strict: bool) -> Result<(), Vec<TypeError>> {
+    let mut checker = match strict {
+        true => Checker::strict(),
+        false => Checker::new(),
+    };
+    let errors: Vec<TypeError> = program
+        .iter()
+        .filter_map(|stmt| checker.check(stmt).err())
+        .flatten()
+        .collect();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+#[cfg(test)]
+mod twig_types_tests {
+    use super::*;
+    use crate::twig_parser::parse_file;
+
+    fn check_with(input: &str, strict: bool) -> Result<(), Vec<String>> {
+        check_program(&parse_file(input).unwrap(), strict)
+            .map_err(|errors| errors.into_iter().map(|e| e.message).collect())
+    }
+
+    fn check(input: &str) -> Result<(), Vec<String>> {
+        check_with(input, false)
+    }
+
+    fn check_strict(input: &str) -> Result<(), Vec<String>> {
+        check_with(input, true)
+    }
+
+    fn infer(input: &str) -> String {
+        let mut checker = Checker::strict();
+        let program = parse_file(input).unwrap();
+        let (last, rest) = program.split_last().unwrap();
+        for stmt in rest {
+            checker.check(stmt).unwrap();
+        }
+        checker.check(last).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_check_math_op_mismatch() {
+        let result = check("1 + true").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec!["Mismatched types in `1 + true`: `true` is bool, expected int"]
+        );
+    }
+
+    #[test]
+    fn test_check_reports_paths_that_do_not_run() {
+        let result = check("fn f(n) { match n { 0 => 1, _ => \"a\" - 1 } }").unwrap_err();
+
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_check_unannotated_is_unknown() {
+        assert!(check("fn f(a, b) { a + b }\nf(1, 2)").is_ok());
+        assert!(check("let x = nil ?? 1\nx + 1").is_ok());
+    }
+
+    #[test]
+    fn test_check_optional_chain_on_nil() {
+        assert!(check("let a = nil\na?.b.c[0].len()").is_ok());
+        assert!(check_strict("let a = nil\na?.b.c[0].len()").is_ok());
+        assert_eq!(infer("let a = nil\na?.b.c"), "nil");
+    }
+
+    #[test]
+    fn test_check_let_annotation() {
+        assert!(check("let x: int = 1 + 2").is_ok());
+        assert!(check("let xs: [string] = [\"a\", \"b\"]").is_ok());
+
+        let result = check("let x: int = \"a\"").unwrap_err();
+        assert_eq!(
+            result,
+            vec!["Mismatched types in the declaration of `x`: `\"a\"` is string, expected int"]
+        );
+    }
+
+    #[test]
+    fn test_check_annotation_used_after_declaration() {
+        let result = check("let s: string = \"hi\"\ns * 2").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec!["Mismatched types in `s * 2`: `s` is string, expected int"]
+        );
+    }
+
+    #[test]
+    fn test_check_fn_signature() {
+        let program = "fn f(a: string) -> bool { a.contains(\"x\") }";
+        assert!(check(program).is_ok());
+
+        let result = check(&format!("{}\nf(1)", program)).unwrap_err();
+        assert_eq!(
+            result,
+            vec!["Mismatched types in `f(1)`: `1` is int, expected string"]
+        );
+
+        let result = check(&format!("{}\nf(\"a\") + 1", program)).unwrap_err();
+        assert_eq!(
+            result,
+            vec!["Mismatched types in `f(\"a\") + 1`: `f(\"a\")` is bool, expected int"]
+        );
+    }
+
+    #[test]
+    fn test_check_fn_result() {
+        let result = check("fn f(a: string) -> bool { a.len() }").unwrap_err();
+        assert_eq!(
+            result,
+            vec!["Mismatched types in the result of `f`: `a.len()` is int, expected bool"]
+        );
+
+        let result = check("fn f() -> int { return \"s\" }").unwrap_err();
+        assert_eq!(
+            result,
+            vec!["Mismatched types in `return \"s\"`: `\"s\"` is string, expected int"]
+        );
+    }
+
+    #[test]
+    fn test_check_wrong_argument_count() {
+        let result = check("fn f(a: int) { a }\nf(1, 2)").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec!["Wrong number of arguments in `f(1, 2)`: expected 1, found 2"]
+        );
+    }
+
+    #[test]
+    fn test_check_comparisons() {
+        assert!(check("1 < 2").is_ok());
+        assert!(check("\"a\" == \"b\"").is_ok());
+        assert!(check("1 == nil").is_ok());
+
+        assert_eq!(
+            check("1 == true").unwrap_err(),
+            vec!["Cannot compare int with bool in `1 == true`"]
+        );
+        assert_eq!(
+            check("\"a\" < \"b\"").unwrap_err(),
+            vec!["Cannot order values of type string in `\"a\" < \"b\"`"]
+        );
+    }
+
+    #[test]
+    fn test_check_methods() {
+        assert!(check("let n: int = \"abc\".len()").is_ok());
+
+        assert_eq!(
+            check("1.len()").unwrap_err(),
+            vec!["int has no method `len` in `1.len()`"]
+        );
+        assert_eq!(
+            check("[1].pop()").unwrap_err(),
+            vec!["[int] has no method `pop` in `[1].pop()`"]
+        );
+    }
+
+    #[test]
+    fn test_check_impl_methods() {
+        let program = "struct P { x, y }
+impl P { fn sum(self) -> int { self.x + self.y } }
+let p = P { x: 1, y: 2 }";
+        assert!(check(&format!("{}\np.sum() + 1", program)).is_ok());
+
+        let result = check(&format!("{}\np.sum(1)", program)).unwrap_err();
+        assert_eq!(
+            result,
+            vec!["Wrong number of arguments in `p.sum(1)`: expected 0, found 1"]
+        );
+    }
+
+    #[test]
+    fn test_check_struct_literal() {
+        let result = check("struct P { x, y }\nP { x: 1, z: 2 }").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec![
+                "P has no field `z` in `P { x: 1, z: 2 }`",
+                "Missing field `y` in `P { x: 1, z: 2 }`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_named_types() {
+        let program = "struct P { x }\nenum Shape { Circle(r), Empty }";
+        assert!(check(&format!("{}\nlet p: P = P {{ x: 1 }}", program)).is_ok());
+        assert!(check(&format!("{}\nlet s: Shape = Shape::Circle(1)", program)).is_ok());
+
+        let result = check(&format!("{}\nlet s: Shape = P {{ x: 1 }}", program)).unwrap_err();
+        assert_eq!(
+            result,
+            vec!["Mismatched types in the declaration of `s`: `P { x: 1 }` is P, expected Shape"]
+        );
+
+        let result = check("let q: Foo = 1").unwrap_err();
+        assert_eq!(result, vec!["Unknown type `Foo`"]);
+    }
+
+    #[test]
+    fn test_check_function_type() {
+        let program = "fn apply(f: fn(int) -> int, n: int) -> int { f(n) }";
+        assert!(
+            check(&format!(
+                "{}\nfn double(n: int) -> int {{ n * 2 }}\napply(double, 1)",
+                program
+            ))
+            .is_ok()
+        );
+
+        let result = check(&format!(
+            "{}\nfn name(n: int) -> string {{ \"n\" }}\napply(name, 1)",
+            program
+        ))
+        .unwrap_err();
+        assert_eq!(
+            result,
+            vec![
+                "Mismatched types in `apply(name, 1)`: `name` is fn(int) -> string, expected fn(int) -> int"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_not_callable() {
+        let result = check("let n = 1\nn(2)").unwrap_err();
+
+        assert_eq!(result, vec!["Cannot call int in `n(2)`"]);
+    }
+
+    #[test]
+    fn test_check_propagate_needs_result() {
+        assert!(check("fn f(n) { Ok(n)? }").is_ok());
+
+        let result = check("fn f() { 1? }").unwrap_err();
+        assert_eq!(result, vec!["`?` needs a Result, but `1` is int"]);
+    }
+
+    #[test]
+    fn test_strict_infers_params() {
+        let result = infer("fn add(a, b) { a + b }\nadd");
+
+        assert_eq!(result, "fn(int, int) -> int");
+    }
+
+    #[test]
+    fn test_strict_generalizes_functions() {
+        assert_eq!(infer("fn id(x) { x }\nid"), "fn('a) -> 'a");
+        assert_eq!(infer("fn id(x) { x }\n[id(1), id(2)]"), "[int]");
+        assert!(check_strict("fn id(x) { x }\nlet a = id(1)\nlet b = id(true)").is_ok());
+    }
+
+    #[test]
+    fn test_strict_infers_list_items() {
+        assert_eq!(
+            infer("fn first(xs) { xs[0] + 1 }\nfirst"),
+            "fn([int]) -> int"
+        );
+        assert_eq!(
+            infer("fn add(xs) { xs.push(1) }\nadd"),
+            "fn([int]) -> [int]"
+        );
+        assert_eq!(infer("fn head(xs) { xs[0] }\nhead"), "fn(['a]) -> 'a");
+    }
+
+    #[test]
+    fn test_strict_list_item_mismatch() {
+        let result = check_strict("[1, true]").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec!["Mismatched types in `[1, true]`: `true` is bool, expected int"]
+        );
+        assert!(check("[1, true]").is_ok());
+    }
+
+    #[test]
+    fn test_strict_unknown_variable() {
+        let result = check_strict("fn f(n) { n + m }").unwrap_err();
+
+        assert_eq!(result, vec!["Variable does not exist: `m`"]);
+        assert!(check("fn f(n) { n + m }").is_ok());
+    }
+
+    #[test]
+    fn test_strict_argument_mismatch() {
+        let result =
+            check_strict("fn twice(f, x) { f(f(x)) }\nfn inc(n) { n + 1 }\ntwice(inc, true)");
+
+        assert_eq!(
+            result.unwrap_err(),
+            vec!["Mismatched types in `twice(inc, true)`: `true` is bool, expected int"]
+        );
+
+        let result = check_strict("fn inc(n) { n + 1 }\ninc(\"one\")").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec!["Mismatched types in `inc(\"one\")`: `\"one\"` is string, expected int"]
+        );
+    }
+
+    #[test]
+    fn test_strict_match_arms() {
+        let result = check_strict("fn f(n) { match n { 0 => \"zero\", _ => n } }").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec![
+                "Mismatched types in `match n { 0 => \"zero\", _ => n }`: `n` is int, expected string"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_pattern_mismatch() {
+        let result =
+            check_strict("fn f(n) { n + 1 }\nmatch f(1) { true => 1, _ => 0 }").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec![
+                "Mismatched types in `match f(1) { true => 1, _ => 0 }`: pattern `true` is bool, expected int"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_infers_from_fields_and_methods() {
+        let program = "struct Point { x, y }\nimpl Point { fn norm(self) { 1 } }\n";
+
+        assert_eq!(
+            infer(&format!("{}fn f(p) {{ p.x }}\nf", program)),
+            "fn(Point<'a, 'b>) -> 'a"
+        );
+        assert_eq!(
+            infer(&format!("{}fn f(p) {{ p.norm() }}\nf", program)),
+            "fn(Point<'a, 'b>) -> int"
+        );
+        assert_eq!(infer("fn f(s) { s.upper() }\nf"), "fn(string) -> string");
+    }
+
+    #[test]
+    fn test_strict_ambiguous_method() {
+        let result = check_strict("fn len2(xs) { xs.len() }").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec!["The type of `xs` can't be worked out from `.len`, so it needs an annotation"]
+        );
+        assert_eq!(
+            infer("fn len2(xs: [int]) { xs.len() }\nlen2"),
+            "fn([int]) -> int"
+        );
+    }
+
+    #[test]
+    fn test_strict_enum_payloads() {
+        let program = "enum Opt { Some(v), None }\n";
+
+        assert_eq!(
+            check_strict(&format!(
+                "{}match Opt::Some(\"s\") {{ Opt::Some(v) => v + 1, Opt::None => 0 }}",
+                program
+            ))
+            .unwrap_err(),
+            vec!["Mismatched types in `v + 1`: `v` is string, expected int"]
+        );
+        assert_eq!(infer(&format!("{}Opt::Some(1)", program)), "Opt<int>");
+        assert_eq!(
+            infer(&format!(
+                "{}fn get(o, d) {{ match o {{ Opt::Some(v) => v, Opt::None => d }} }}\nget",
+                program
+            )),
+            "fn(Opt<'a>, 'a) -> 'a"
+        );
+        assert!(
+            check(&format!(
+                "{}match Opt::Some(\"s\") {{ Opt::Some(v) => v + 1, Opt::None => 0 }}",
+                program
+            ))
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_strict_result_types() {
+        assert_eq!(
+            check_strict("match Ok(\"s\") { Ok(q) => q + 1, Err(e) => 0 }").unwrap_err(),
+            vec!["Mismatched types in `q + 1`: `q` is string, expected int"]
+        );
+        assert_eq!(infer("Ok(1)"), "Result<int, 'a>");
+        assert_eq!(
+            infer("fn f(r) { Ok(r? + 1) }\nf"),
+            "fn(Result<int, 'a>) -> Result<int, 'a>"
+        );
+        assert_eq!(
+            check_strict("fn f(r) { r? + 1 }").unwrap_err(),
+            vec!["Mismatched types in the result of `f`: `r? + 1` is int, expected Result<'a, 'b>"]
+        );
+    }
+
+    #[test]
+    fn test_strict_struct_fields() {
+        let program = "struct P { x }\n";
+
+        assert_eq!(
+            check_strict(&format!("{}let p = P {{ x: \"s\" }}\np.x + 1", program)).unwrap_err(),
+            vec!["Mismatched types in `p.x + 1`: `p.x` is string, expected int"]
+        );
+        assert_eq!(infer(&format!("{}P {{ x: 1 }}.x", program)), "int");
+        assert_eq!(
+            infer(&format!(
+                "{}impl P {{ fn get(self) {{ self.x }} }}\nP {{ x: true }}.get()",
+                program
+            )),
+            "bool"
+        );
+        assert!(check(&format!("{}let p = P {{ x: \"s\" }}\np.x + 1", program)).is_ok());
+    }
+
+    #[test]
+    fn test_strict_infinite_type() {
+        let result = check_strict("fn f(x) { x(x) }").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec!["The type of `x(x)` would have to contain itself"]
+        );
+    }
+
+    #[test]
+    fn test_strict_any_opts_out() {
+        assert!(check_strict("fn f(x: any) { x.len() }").is_ok());
+        assert_eq!(infer("fn f(x: any) { x }\nf"), "fn(any) -> any");
+    }
+
+    #[test]
+    fn test_generic_function() {
+        let program = "fn first<T>(xs: [T]) -> T { xs[0] }\n";
+
+        assert_eq!(infer(&format!("{}first", program)), "fn(['a]) -> 'a");
+        assert_eq!(infer(&format!("{}first([true])", program)), "bool");
+        assert!(
+            check(&format!(
+                "{}let a: int = first([1])\nlet b: string = first([\"b\"])",
+                program
+            ))
+            .is_ok()
+        );
+        assert_eq!(
+            check(&format!("{}let n: int = first([true])", program)).unwrap_err(),
+            vec![
+                "Mismatched types in the declaration of `n`: `first([true])` is bool, expected int"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generic_function_body() {
+        let result = check("fn first<T>(xs: [T]) -> T { xs[0] + 1 }").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec![
+                "Mismatched types in `xs[0] + 1`: `xs[0]` is T, expected int",
+                "Mismatched types in the result of `first`: `xs[0] + 1` is int, expected T"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generic_struct() {
+        let program = "struct Pair<A, B> { first: A, second: B }\n\
+            impl Pair { fn swap(self) -> Pair<B, A> { Pair { first: self.second, second: self.first } } }\n";
+
+        assert_eq!(
+            infer(&format!("{}Pair {{ first: 1, second: \"a\" }}", program)),
+            "Pair<int, string>"
+        );
+        assert_eq!(
+            infer(&format!(
+                "{}Pair {{ first: 1, second: \"a\" }}.swap().first",
+                program
+            )),
+            "string"
+        );
+        assert_eq!(
+            check(&format!(
+                "{}let p: Pair<int, int> = Pair {{ first: 1, second: true }}",
+                program
+            ))
+            .unwrap_err(),
+            vec![
+                "Mismatched types in the declaration of `p`: `Pair { first: 1, second: true }` is Pair<int, bool>, expected Pair<int, int>"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_struct_field_types() {
+        let result =
+            check("struct Point { x: int, y: int }\nPoint { x: 1, y: \"2\" }").unwrap_err();
+
+        assert_eq!(
+            result,
+            vec!["Mismatched types in `Point { x: 1, y: \"2\" }`: `\"2\"` is string, expected int"]
+        );
+        assert_eq!(
+            check("struct Pair<A, B> { first: A, second: B }\nlet p: Pair<int> = nil").unwrap_err(),
+            vec!["`Pair` takes 2 type parameters, found 1"]
+        );
+    }
+}
@@ -0,0 +1,703 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::twig_bytecode::{Chunk, Fail, Op, Wrap, compile};
+use crate::twig_interp::{
+    Caller, Env, Function, InterpErrors, Method, Scope, Types, build_record, call_value,
+    check_exhaustive, check_impl_type, compare, find_method, get_field, get_index, get_variant,
+    lookup, math, missing_local, not_indexable, propagate, struct_fields,
+};
+use crate::twig_modules::import;
+use crate::twig_parser::{Expression, Span};
+
+/// A `try` whose body is running, or the error a `finally` block is running for.
+enum Handler {
+    Try {
+        /// The number of calls that were running when the `try` started.
+        frame: usize,
+        handler: usize,
+        /// The region of the `try` itself, which is as far as errors are noted on the way to it.
+        region: usize,
+        stack: usize,
+        scopes: usize,
+        bindings: usize,
+    },
+    Pending {
+        frame: usize,
+        error: InterpErrors,
+    },
+}
+
+impl Handler {
+    fn frame(&self) -> usize {
+        match self {
+            Handler::Try { frame, .. } | Handler::Pending { frame, .. } => *frame,
+        }
+    }
+}
+
+struct CallFrame {
+    chunk: Rc<Chunk>,
+    /// Where the frame carries on from once the call it's making returns.
+    pc: usize,
+    /// The height of the value stack when the call started.
+    stack: usize,
+    /// Where the call was made from, which is also where calls made from it without a span of
+    /// their own are made from.
+    site: Option<Span>,
+    /// What to go back to once the call ends, or `None` for the program itself.
+    caller: Option<Caller>,
+}
+
+enum Flow {
+    Next,
+    Jump(usize),
+    /// A call pushed a new frame.
+    Call,
+    End(Types),
+}
+
+/// Runs compiled programs on a stack of values, with a frame for each running call. Variables
+/// live in the same scopes of the same `Env` the tree-walker uses, so the two can be swapped for
+/// each other between statements.
+struct Vm<'a> {
+    env: &'a mut Env,
+    stack: Vec<Types>,
+    frames: Vec<CallFrame>,
+    handlers: Vec<Handler>,
+    /// The variables bound by the patterns being tested.
+    bindings: Vec<(String, Types)>,
+    /// Each function is compiled the first time it's called. The function is kept along with its
+    /// chunk so its address can't be reused.
+    chunks: HashMap<*const Function, (Rc<Function>, Rc<Chunk>)>,
+}
+
+/// Compiles a statement to bytecode and runs it, with the same result `interp_program` would have.
+pub fn run_program(expr: &Expression, env: &mut Env) -> Result<Types, InterpErrors> {
+    let chunk = Rc::new(compile(expr, env.module().cloned())?);
+    let depth = env.scope_depth();
+    let frame = CallFrame {
+        chunk,
+        pc: 0,
+        stack: 0,
+        site: env.site(),
+        caller: None,
+    };
+    let mut vm = Vm {
+        env,
+        stack: Vec::new(),
+        frames: vec![frame],
+        handlers: Vec::new(),
+        bindings: Vec::new(),
+        chunks: HashMap::new(),
+    };
+
+    let result = vm.run();
+    // Scopes an error left in the middle of are dropped on its way out, as they are by the
+    // tree-walker.
+    vm.env.truncate_scopes(depth);
+    result
+}
+
+impl Vm<'_> {
+    fn run(&mut self) -> Result<Types, InterpErrors> {
+        let mut chunk = self.frames[0].chunk.clone();
+        let mut pc = 0;
+        loop {
+            let result = match self.step(&chunk, pc) {
+                Ok(Flow::Next) => {
+                    pc += 1;
+                    continue;
+                }
+                Ok(Flow::Jump(target)) => {
+                    pc = target;
+                    continue;
+                }
+                Ok(Flow::Call) => {
+                    let depth = self.frames.len();
+                    self.frames[depth - 2].pc = pc + 1;
+                    chunk = self.frames[depth - 1].chunk.clone();
+                    pc = 0;
+                    continue;
+                }
+                Ok(Flow::End(value)) => self.leave(Ok(value)),
+                Err(e) => self.unwind(e, pc),
+            };
+
+            match result {
+                Ok(Some((next, target))) => {
+                    chunk = next;
+                    pc = target;
+                }
+                Ok(None) => return Ok(self.stack.pop().unwrap_or(Types::Unit)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn push(&mut self, value: Types) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Result<Types, InterpErrors> {
+        self.stack.pop().ok_or_else(empty_stack)
+    }
+
+    fn pop_n(&mut self, n: usize) -> Result<Vec<Types>, InterpErrors> {
+        match self.stack.len().checked_sub(n) {
+            Some(at) => Ok(self.stack.split_off(at)),
+            None => Err(empty_stack()),
+        }
+    }
+
+    fn top(&self) -> Result<&Types, InterpErrors> {
+        self.stack.last().ok_or_else(empty_stack)
+    }
+
+    fn step(&mut self, chunk: &Chunk, pc: usize) -> Result<Flow, InterpErrors> {
+        match chunk.code[pc] {
+            Op::Constant(i) => self.push(chunk.constants[i].clone()),
+            Op::Integer(value) => self.push(Types::Integer(value)),
+            Op::Boolean(value) => self.push(Types::Boolean(value)),
+            Op::Nil => self.push(Types::Nil),
+            Op::Unit => self.push(Types::Unit),
+            Op::List(n) => {
+                let items = self.pop_n(n)?;
+                self.push(Types::List(items));
+            }
+            Op::Pop => {
+                self.pop()?;
+            }
+            Op::Copy => {
+                let value = self.top()?.clone();
+                self.push(value);
+            }
+            Op::Nip => {
+                let value = self.pop()?;
+                self.pop()?;
+                self.push(value);
+            }
+            Op::Get(name) => self.push(lookup(&chunk.names[name], self.env)?),
+            Op::Local(name, depth, slot) => match self.env.local(depth, slot) {
+                Some(value) => self.push(value.clone()),
+                None => return Err(missing_local(&chunk.names[name])),
+            },
+            Op::Declare(name, constant) => {
+                let value = self.pop()?;
+                let name = chunk.names[name].clone();
+                self.env.declare(name, value, constant)?;
+            }
+            Op::PushScope => self.env.push_scope(Scope::new()),
+            Op::PopScope => self.env.pop_scope(),
+            Op::Math(op) => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                self.push(math(op, left, right)?);
+            }
+            Op::Compare(op) => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                self.push(compare(op, left, right)?);
+            }
+            Op::Jump(target) => return Ok(Flow::Jump(target)),
+            Op::JumpIfNil(target) => {
+                if *self.top()? == Types::Nil {
+                    return Ok(Flow::Jump(target));
+                }
+            }
+            Op::JumpUnlessNil(target) => {
+                if *self.top()? != Types::Nil {
+                    return Ok(Flow::Jump(target));
+                }
+                self.pop()?;
+            }
+            Op::Field(field, optional) => {
+                let value = self.pop()?;
+                self.push(get_field(value, &chunk.names[field], optional)?);
+            }
+            Op::Indexable => {
+                let value = self.top()?;
+                if !matches!(value, Types::List(_)) {
+                    return Err(not_indexable(value));
+                }
+            }
+            Op::Index(optional) => {
+                let index = self.pop()?;
+                let Types::List(items) = self.pop()? else {
+                    return Err(internal("`Index` found something other than a list"));
+                };
+                self.push(get_index(items, index, optional)?);
+            }
+            Op::Call(argc) => {
+                let args = self.pop_n(argc)?;
+                let callee = self.pop()?;
+                return self.call(callee, args, chunk.tree[chunk.regions[pc]].site);
+            }
+            Op::Invoke(method, argc) => {
+                let args = self.pop_n(argc)?;
+                let receiver = self.pop()?;
+                let method = chunk.names[method].clone();
+                match find_method(receiver, method, args, self.env)? {
+                    Method::Call(callee, args) => {
+                        return self.call(callee, args, chunk.tree[chunk.regions[pc]].site);
+                    }
+                    Method::Done(value) => self.push(value),
+                }
+            }
+            Op::StructType(name) => {
+                let name = &chunk.names[name];
+                let fields = struct_fields(name, self.env)?;
+                self.push(Types::StructType {
+                    name: name.clone(),
+                    fields,
+                });
+            }
+            Op::CheckField(field, depth) => {
+                let at = self.stack.len().checked_sub(depth + 1);
+                let Some(Types::StructType { fields, .. }) = at.map(|at| &self.stack[at]) else {
+                    return Err(internal(
+                        "a struct literal doesn't start with its struct type",
+                    ));
+                };
+                let field = &chunk.names[field];
+                if !fields.contains(field) {
+                    return Err(InterpErrors::FieldDoesNotExist(field.clone()));
+                }
+            }
+            Op::Record(record) => {
+                let (name, fields) = &chunk.records[record];
+                let values = self.pop_n(fields.len())?;
+                let Types::StructType {
+                    fields: declared, ..
+                } = self.pop()?
+                else {
+                    return Err(internal(
+                        "a struct literal doesn't start with its struct type",
+                    ));
+                };
+                let values = fields.iter().cloned().zip(values).collect();
+                self.push(build_record(name.clone(), declared, values)?);
+            }
+            Op::Variant(enum_name, variant) => {
+                let (enum_name, variant) = (&chunk.names[enum_name], &chunk.names[variant]);
+                self.push(get_variant(enum_name, variant, self.env)?);
+            }
+            Op::Function(function) => self.push(Types::Function(chunk.functions[function].clone())),
+            Op::Impl(block) => {
+                let (type_name, methods) = &chunk.impls[block];
+                check_impl_type(type_name, self.env)?;
+                for method in methods {
+                    self.env.add_method(type_name.clone(), method.clone());
+                }
+            }
+            Op::Import(path) => {
+                let module = import(&chunk.names[path], self.env)?;
+                self.push(Types::Module(module));
+            }
+            Op::Export(name) => self.env.export(chunk.names[name].clone()),
+            Op::Throw => return Err(InterpErrors::Thrown(self.pop()?)),
+            Op::Return => return Err(InterpErrors::Return(self.pop()?)),
+            Op::Propagate => {
+                let value = self.pop()?;
+                self.push(propagate(value)?);
+            }
+            Op::PushHandler(handler) => self.handlers.push(Handler::Try {
+                frame: self.frames.len(),
+                handler,
+                region: chunk.regions[pc],
+                stack: self.stack.len(),
+                scopes: self.env.scope_depth(),
+                bindings: self.bindings.len(),
+            }),
+            Op::PopHandler => {
+                self.handlers.pop();
+            }
+            Op::Rethrow => match self.handlers.pop() {
+                Some(Handler::Pending { error, .. }) => return Err(error),
+                _ => return Err(internal("`Rethrow` has no error to rethrow")),
+            },
+            Op::CheckExhaustive(arms) => {
+                check_exhaustive(self.top()?, chunk.matches[arms].iter(), self.env)?
+            }
+            Op::MatchVariant(pattern, fail) => {
+                let (enum_name, variant, arity) = &chunk.variants[pattern];
+                match self.pop()? {
+                    Types::EnumValue {
+                        enum_name: value_enum,
+                        variant: value_variant,
+                        payload,
+                    } if value_enum == *enum_name && value_variant == *variant => {
+                        if payload.len() != *arity {
+                            return Err(InterpErrors::WrongArgumentCount {
+                                expected: payload.len(),
+                                found: *arity,
+                            });
+                        }
+                        self.stack.extend(payload.into_iter().rev());
+                    }
+                    _ => return self.fail(&chunk.fails[fail]),
+                }
+            }
+            Op::MatchLiteral(fail) => {
+                let literal = self.pop()?;
+                if literal != self.pop()? {
+                    return self.fail(&chunk.fails[fail]);
+                }
+            }
+            Op::Bind(name) => {
+                let value = self.pop()?;
+                self.bindings.push((chunk.names[name].clone(), value));
+            }
+            Op::Bindings(n) => {
+                let Some(at) = self.bindings.len().checked_sub(n) else {
+                    return Err(internal("a pattern bound fewer names than its arm uses"));
+                };
+                let bindings = self.bindings.split_off(at);
+                self.env.push_scope(bindings.into_iter().collect());
+            }
+            Op::NoMatchingArm => return Err(InterpErrors::NoMatchingArm(self.pop()?)),
+            Op::End => return Ok(Flow::End(self.pop()?)),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn fail(&mut self, fail: &Fail) -> Result<Flow, InterpErrors> {
+        let stack = self.stack.len().checked_sub(fail.pending);
+        let bindings = self.bindings.len().checked_sub(fail.bound);
+        let (Some(stack), Some(bindings)) = (stack, bindings) else {
+            return Err(internal(
+                "a failed pattern left less behind than it was tested with",
+            ));
+        };
+        self.stack.truncate(stack);
+        self.bindings.truncate(bindings);
+        Ok(Flow::Jump(fail.target))
+    }
+
+    fn compiled(&mut self, function: &Rc<Function>) -> Result<Rc<Chunk>, InterpErrors> {
+        if let Some((_, chunk)) = self.chunks.get(&Rc::as_ptr(function)) {
+            return Ok(chunk.clone());
+        }
+        let chunk = Rc::new(compile(&function.body, function.module.clone())?);
+        let entry = (function.clone(), chunk.clone());
+        self.chunks.insert(Rc::as_ptr(function), entry);
+        Ok(chunk)
+    }
+
+    /// Calls a value. Functions get a frame of their own, while everything else is done at once.
+    fn call(
+        &mut self,
+        callee: Types,
+        args: Vec<Types>,
+        site: Option<Span>,
+    ) -> Result<Flow, InterpErrors> {
+        let Types::Function(function) = callee else {
+            let value = call_value(callee, args, self.env)?;
+            self.push(value);
+            return Ok(Flow::Next);
+        };
+
+        let site = site.or(self.frames[self.frames.len() - 1].site);
+        let chunk = self.compiled(&function)?;
+        let caller = self.env.enter_call(&function, args, site)?;
+        self.frames.push(CallFrame {
+            chunk,
+            pc: 0,
+            stack: self.stack.len(),
+            site,
+            caller: Some(caller),
+        });
+        Ok(Flow::Call)
+    }
+
+    /// Ends the innermost call with its result, returning where its caller carries on, or `None`
+    /// once the program itself has ended with a value.
+    fn leave(
+        &mut self,
+        result: Result<Types, InterpErrors>,
+    ) -> Result<Option<(Rc<Chunk>, usize)>, InterpErrors> {
+        let Some(caller) = self.pop_frame()? else {
+            return result.map(|value| {
+                self.push(value);
+                None
+            });
+        };
+
+        match self.env.leave_call(caller, result) {
+            Ok(value) => Ok(Some(self.resume(value))),
+            Err(e) => {
+                let pc = self.frames[self.frames.len() - 1].pc - 1;
+                self.unwind(e, pc)
+            }
+        }
+    }
+
+    /// Drops the innermost frame along with what it left on the stack, returning the call it was
+    /// running, or `None` for the program's own frame.
+    fn pop_frame(&mut self) -> Result<Option<Caller>, InterpErrors> {
+        let frame = self
+            .frames
+            .pop()
+            .ok_or_else(|| internal("the program's own frame has already ended"))?;
+        self.stack.truncate(frame.stack);
+        Ok(frame.caller)
+    }
+
+    /// Gives the innermost frame the value of the call it made, returning where it carries on.
+    fn resume(&mut self, value: Types) -> (Rc<Chunk>, usize) {
+        self.push(value);
+        let frame = &self.frames[self.frames.len() - 1];
+        (frame.chunk.clone(), frame.pc)
+    }
+
+    /// Takes an error raised at `pc` to the innermost `try` that handles it, noting it with each
+    /// region it leaves on the way the same way the tree-walker does. Calls it leaves are ended in
+    /// a loop rather than through `leave`, so that an error from deep recursion can't overflow the
+    /// stack on its way out.
+    fn unwind(
+        &mut self,
+        mut e: InterpErrors,
+        mut pc: usize,
+    ) -> Result<Option<(Rc<Chunk>, usize)>, InterpErrors> {
+        loop {
+            let depth = self.frames.len();
+            let chunk = self.frames[depth - 1].chunk.clone();
+            let from = chunk.regions[pc];
+
+            while let Some(handler) = self.handlers.pop_if(|handler| handler.frame() == depth) {
+                let Handler::Try {
+                    handler,
+                    region,
+                    stack,
+                    scopes,
+                    bindings,
+                    ..
+                } = handler
+                else {
+                    // An error from inside a `finally` block replaces the one it was running for.
+                    continue;
+                };
+                let handler = &chunk.handlers[handler];
+                // A `catch` doesn't stop a `return`, but a `finally` still runs for one.
+                if handler.catch && matches!(e, InterpErrors::Return(_)) {
+                    continue;
+                }
+
+                self.stack.truncate(stack);
+                self.env.truncate_scopes(scopes);
+                self.bindings.truncate(bindings);
+                match handler.catch {
+                    true => self.push(e.into_value()),
+                    false => {
+                        let error = self.wrap(e, &chunk, from, Some(region));
+                        self.handlers.push(Handler::Pending {
+                            frame: depth,
+                            error,
+                        });
+                    }
+                }
+                return Ok(Some((chunk.clone(), handler.target)));
+            }
+
+            e = self.wrap(e, &chunk, from, None);
+            let Some(caller) = self.pop_frame()? else {
+                return Err(e);
+            };
+            e = match self.env.leave_call(caller, Err(e)) {
+                Ok(value) => return Ok(Some(self.resume(value))),
+                Err(e) => e,
+            };
+            pc = self.frames[self.frames.len() - 1].pc - 1;
+        }
+    }
+
+    /// Notes an error with each region from `from` outwards, stopping before `until`.
+    fn wrap(
+        &self,
+        mut e: InterpErrors,
+        chunk: &Chunk,
+        from: usize,
+        until: Option<usize>,
+    ) -> InterpErrors {
+        let mut next = Some(from);
+        while let Some(region) = next
+            && next != until
+        {
+            let region = &chunk.tree[region];
+            e = match &region.wrap {
+                Wrap::Span(span) => e.at(*span).traced(self.env.frames()),
+                Wrap::Context(context) => e.context(context.clone()),
+                Wrap::Nothing => e,
+            };
+            next = region.parent;
+        }
+        e
+    }
+}
+
+fn internal(what: &str) -> InterpErrors {
+    InterpErrors::Internal(String::from(what))
+}
+
+fn empty_stack() -> InterpErrors {
+    internal("the value stack has fewer values than the compiler expected")
+}
+
+#[cfg(test)]
+mod twig_vm_tests {
+    use super::*;
+    use crate::twig_descent::parse_statements;
+    use crate::twig_interp::{Frame, interp_program};
+    use crate::twig_resolver::resolve_program;
+    use std::time::{Duration, Instant};
+
+    /// Runs a program on both the VM and the tree-walker, with and without its local variables
+    /// resolved to slots, checks they agree at every statement and returns the result of the last
+    /// one.
+    fn run(source: &str) -> Result<Types, InterpErrors> {
+        let program = parse_statements(source).unwrap();
+        let mut resolved = program.clone();
+        resolve_program(&mut resolved, &Env::new()).unwrap();
+
+        let mut envs = [Env::new(), Env::new(), Env::new(), Env::new()];
+        let mut result = Ok(Types::Unit);
+        for (statement, resolved) in program.iter().zip(&resolved) {
+            result = interp_program(statement, &mut envs[0]);
+            assert_eq!(run_program(statement, &mut envs[1]), result);
+            let walked = interp_program(resolved, &mut envs[2]);
+            assert_eq!(run_program(resolved, &mut envs[3]), walked);
+            assert_eq!(walked.is_ok(), result.is_ok());
+        }
+        result
+    }
+
+    #[test]
+    fn test_run_program_recursion() {
+        let source = "fn fib(n) { match n < 2 { true => n, false => fib(n - 1) + fib(n - 2) } }
+            fib(6)";
+        assert_eq!(run(source), Ok(Types::Integer(8)));
+    }
+
+    #[test]
+    fn test_run_program_integer_overflow() {
+        assert_eq!(run("2147483647 * 2").unwrap_err().kind(), "IntegerOverflow");
+        let source = "try { 2147483647 + 1 } catch e { e.kind }";
+        assert_eq!(
+            run(source),
+            Ok(Types::String(String::from("IntegerOverflow")))
+        );
+    }
+
+    #[test]
+    fn test_run_program_finally_runs_on_return() {
+        let source = "fn f() { try { return 1 } catch e { 2 } finally { 3 } }
+            f()";
+        assert_eq!(run(source), Ok(Types::Integer(1)));
+        // The `catch` lets the `return` past, and the `finally` replaces it with its own error.
+        let source = "fn f() { try { return 1 } catch e { 2 } finally { throw 3 } }
+            try { f() } catch e { e }";
+        assert_eq!(run(source), Ok(Types::Integer(3)));
+    }
+
+    #[test]
+    fn test_run_program_error_in_finally_replaces_error() {
+        let source = "fn f() { try { throw 1 } finally { throw 2 } }
+            try { f() } catch e { e }";
+        assert_eq!(run(source), Ok(Types::Integer(2)));
+    }
+
+    #[test]
+    fn test_run_program_error_through_calls() {
+        let source = "fn inner(x) { x / 0 }
+            fn outer(x) { [1, inner(x)] }
+            outer(1) + 1";
+        let e = run(source).unwrap_err();
+        assert_eq!(e.kind(), "DivisionByZero");
+        assert_eq!(e.span().map(|span| span.line), Some(1));
+        assert_eq!(e.trace().map(<[Frame]>::len), Some(2));
+    }
+
+    #[test]
+    fn test_run_program_error_context() {
+        let source = "let x = 1
+            x + (true - 1)";
+        let e = run(source).unwrap_err();
+        assert!(matches!(e, InterpErrors::Context(..)));
+        assert_eq!(e.kind(), "InvalidTypeError");
+    }
+
+    #[test]
+    fn test_run_program_nested_patterns() {
+        let source = "enum Tree { Leaf(v), Node(l, r) }
+            fn sum(t) { match t { Tree::Leaf(v) => v, Tree::Node(Tree::Leaf(0), r) => sum(r), Tree::Node(l, r) => sum(l) + sum(r) } }
+            sum(Tree::Node(Tree::Node(Tree::Leaf(0), Tree::Leaf(2)), Tree::Node(Tree::Leaf(3), Tree::Leaf(4))))";
+        assert_eq!(run(source), Ok(Types::Integer(9)));
+    }
+
+    #[test]
+    fn test_run_program_scopes_after_error() {
+        let source = "fn f(x) { match x { n => { let y = n; throw y } } }
+            try { f(5) } catch e { e + 1 }";
+        assert_eq!(run(source), Ok(Types::Integer(6)));
+    }
+
+    #[test]
+    fn test_run_program_internal_errors() {
+        let mut env = Env::new();
+        let mut vm = Vm {
+            env: &mut env,
+            stack: vec![Types::Integer(1)],
+            frames: Vec::new(),
+            handlers: Vec::new(),
+            bindings: Vec::new(),
+            chunks: HashMap::new(),
+        };
+
+        assert_eq!(vm.pop_n(2).unwrap_err().kind(), "Internal");
+        assert_eq!(vm.pop(), Ok(Types::Integer(1)));
+        assert_eq!(vm.pop().unwrap_err().kind(), "Internal");
+        assert_eq!(vm.top().unwrap_err().kind(), "Internal");
+        assert_eq!(vm.pop_frame().unwrap_err().kind(), "Internal");
+        let fail = Fail {
+            target: 0,
+            pending: 1,
+            bound: 0,
+        };
+        assert_eq!(vm.fail(&fail).err().map(|e| e.kind()), Some("Internal"));
+    }
+
+    /// The fastest of several runs, which is the one least disturbed by anything else running.
+    fn time(run: impl Fn() -> Result<Types, InterpErrors>) -> Duration {
+        (0..10)
+            .map(|_| {
+                let start = Instant::now();
+                assert_eq!(run(), Ok(Types::Integer(28657)));
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    /// Times the two engines on a call-heavy program, with and without its local variables
+    /// resolved to slots, and prints how much faster the VM is. The gap is too close to the noise
+    /// between runs to fail on. Run it with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark_run_program() {
+        let source = "fn fib(n) { match n < 2 { true => n, false => fib(n - 1) + fib(n - 2) } }";
+        let mut program = parse_statements(source).unwrap();
+        let call = parse_statements("fib(23)").unwrap().remove(0);
+        let mut env = Env::new();
+        interp_program(&program[0], &mut env).unwrap();
+        let mut resolved = Env::new();
+        resolve_program(&mut program, &resolved).unwrap();
+        interp_program(&program[0], &mut resolved).unwrap();
+
+        for (env, name) in [(env, "by name"), (resolved, "resolved")] {
+            let walked = time(|| interp_program(&call, &mut env.clone()));
+            let compiled = time(|| run_program(&call, &mut env.clone()));
+            let speedup = walked.as_secs_f64() / compiled.as_secs_f64();
+            println!("{name}: tree-walker {walked:?}, vm {compiled:?}, {speedup:.2}x");
+        }
+    }
+}